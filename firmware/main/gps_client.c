@@ -17,6 +17,7 @@
 #include "lwip/sys.h"
 #include "esp_sntp.h"
 #include "cJSON.h" // A standard library for JSON handling in ESP-IDF
+#include "mbedtls/md.h"
 
 static const char *TAG = "GPS_CLIENT";
 static EventGroupHandle_t wifi_event_group;
@@ -24,6 +25,7 @@ const int WIFI_CONNECTED_BIT = BIT0;
 
 // --- CONFIGURATION ---
 #define DEVICE_ID               CONFIG_DEVICE_ID //ESP32_001"
+#define DEVICE_SECRET           CONFIG_DEVICE_SECRET // empty = send unsigned requests
 #define WIFI_SSID               CONFIG_WIFI_SSID
 #define WIFI_PASS               CONFIG_WIFI_PASSWORD
 #define API_URL                 CONFIG_API_URL
@@ -31,6 +33,8 @@ const int WIFI_CONNECTED_BIT = BIT0;
 #define END_HOUR                CONFIG_END_HOUR
 #define POLLING_INTERVAL_SEC    CONFIG_POLLING_INTERVAL_SEC
 #define WAKEUP_LEAD_TIME_MIN    30 //minutes
+#define TZ_POSIX                "ICT-7"  // local time zone used for the operational window
+#define TZ_UTC_OFFSET           "+07:00" // same zone, as sent to the server in the "tz" field
 
 // use RTC for deep_sleep compatibilites for wakeup at before next working hour in the next day
 // Tracks the last hour (0-23) when data was successfully sent. Initialize to a value outside 0-23.
@@ -42,6 +46,8 @@ static void sync_time(void);
 static void wifi_init_station(void);
 static esp_err_t http_event_handler(esp_http_client_event_t *evt);
 static char* generate_payload(void);
+static const char *api_path(void);
+static esp_err_t sign_request(const char *path, const char *timestamp, const char *body, char *signature_hex);
 static esp_err_t send_gps_data(const char *json_data);
 static void send_gps_task(void *pvParameters);
 
@@ -51,6 +57,8 @@ static void send_gps_task(void *pvParameters);
  *
  * Structure: 4 hex (Longitude) + 4 hex (Latitude) + 2 hex (Battery %)
  * Total size: 10 hex characters (5 bytes)
+ * Coordinates are fixed-point positions inside the server's bounding box:
+ *   raw = (deg - min) / (max - min) * 0xFFFF  (see BUDDY_COORD_BBOX on the server)
  * The output string is 11 characters (10 for hex + null terminator)
  */
 static char* generate_payload(void) {
@@ -133,6 +141,48 @@ static esp_err_t http_event_handler(esp_http_client_event_t *evt) {
     return ESP_OK;
 }
 
+/**
+ * @brief Returns the path part of API_URL (e.g. "/api/data"), which the signature covers.
+ */
+static const char *api_path(void) {
+    const char *host = strstr(API_URL, "://");
+    const char *path = strchr(host ? host + 3 : API_URL, '/');
+    return path ? path : "/";
+}
+
+/**
+ * @brief Signs a POST request the way the server expects (X-Buddy-Signature).
+ *
+ * signature = hex(HMAC-SHA256(DEVICE_SECRET, "POST <path>\n<timestamp>.<body>"))
+ * signature_hex must hold 65 characters (64 hex + null terminator).
+ */
+static esp_err_t sign_request(const char *path, const char *timestamp, const char *body, char *signature_hex) {
+    unsigned char mac[32];
+    mbedtls_md_context_t ctx;
+    const mbedtls_md_info_t *info = mbedtls_md_info_from_type(MBEDTLS_MD_SHA256);
+
+    mbedtls_md_init(&ctx);
+    int ret = mbedtls_md_setup(&ctx, info, 1);
+    if (ret == 0) ret = mbedtls_md_hmac_starts(&ctx, (const unsigned char *)DEVICE_SECRET, strlen(DEVICE_SECRET));
+    if (ret == 0) ret = mbedtls_md_hmac_update(&ctx, (const unsigned char *)"POST ", 5);
+    if (ret == 0) ret = mbedtls_md_hmac_update(&ctx, (const unsigned char *)path, strlen(path));
+    if (ret == 0) ret = mbedtls_md_hmac_update(&ctx, (const unsigned char *)"\n", 1);
+    if (ret == 0) ret = mbedtls_md_hmac_update(&ctx, (const unsigned char *)timestamp, strlen(timestamp));
+    if (ret == 0) ret = mbedtls_md_hmac_update(&ctx, (const unsigned char *)".", 1);
+    if (ret == 0) ret = mbedtls_md_hmac_update(&ctx, (const unsigned char *)body, strlen(body));
+    if (ret == 0) ret = mbedtls_md_hmac_finish(&ctx, mac);
+    mbedtls_md_free(&ctx);
+    if (ret != 0) {
+        ESP_LOGE(TAG, "Failed to sign request: -0x%04X", -ret);
+        return ESP_FAIL;
+    }
+
+    for (int i = 0; i < sizeof(mac); i++) {
+        sprintf(&signature_hex[i * 2], "%02x", mac[i]);
+    }
+    return ESP_OK;
+}
+
 /**
  * @brief Assembles and sends the final JSON data.
  */
@@ -152,6 +202,20 @@ static esp_err_t send_gps_data(const char *json_data) {
     // Set headers
     esp_http_client_set_header(client, "Content-Type", "application/json");
 
+    // Authenticate with the device secret, if one is configured.
+    // The server rejects signatures whose timestamp is too far from its clock, so this relies on SNTP.
+    if (strlen(DEVICE_SECRET) > 0) {
+        char timestamp[21];
+        char signature[65];
+        snprintf(timestamp, sizeof(timestamp), "%lld", (long long)time(NULL));
+        if (sign_request(api_path(), timestamp, json_data, signature) != ESP_OK) {
+            esp_http_client_cleanup(client);
+            return ESP_FAIL;
+        }
+        esp_http_client_set_header(client, "X-Buddy-Timestamp", timestamp);
+        esp_http_client_set_header(client, "X-Buddy-Signature", signature);
+    }
+
     // Set POST data
     esp_http_client_set_post_field(client, json_data, strlen(json_data));
     
@@ -247,6 +311,7 @@ static void send_gps_task(void *pvParameters) {
                         cJSON_AddStringToObject(root, "payload", payload_str);
                         cJSON_AddStringToObject(root, "date", date_str);
                         cJSON_AddStringToObject(root, "time", time_str);
+                        cJSON_AddStringToObject(root, "tz", TZ_UTC_OFFSET);
                         json_out = cJSON_PrintUnformatted(root);
 
                         if (json_out != NULL) {
@@ -346,7 +411,7 @@ static void sync_time(void) {
     }
     
     // Set Timezone
-    setenv("TZ", "ICT-7", 1); 
+    setenv("TZ", TZ_POSIX, 1);
     tzset();
     
     time_t now;
@@ -0,0 +1,230 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[cfg(feature = "ssr")]
+use argon2::Argon2;
+#[cfg(feature = "ssr")]
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+#[cfg(feature = "ssr")]
+use sha2::{Digest, Sha256};
+
+// --- User Accounts ---
+//
+// People sign in to the dashboard with a username and password. Passwords are
+// stored as argon2id hashes; a successful login hands out a random session token
+// in an HttpOnly cookie, of which only the SHA-256 hash is kept server-side.
+// A user only ever sees the devices of the households they belong to.
+
+/// Shortest password accepted when creating an account.
+pub const MIN_PASSWORD_LEN: usize = 8;
+/// Longest username accepted when creating an account.
+pub const MAX_USERNAME_LEN: usize = 64;
+
+/// Name of the cookie carrying the session token.
+#[cfg(feature = "ssr")]
+pub const SESSION_COOKIE: &str = "buddy_session";
+
+/// Random bytes in a session token (hex-encoded, so twice as many characters).
+#[cfg(feature = "ssr")]
+const SESSION_TOKEN_LEN: usize = 32;
+
+/// Someone who can sign in to the dashboard.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct User {
+    pub user_id: i64,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Body of `POST /api/auth/login` and `POST /api/auth/register`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LoginForm {
+    pub username: String,
+    pub password: String,
+    /// Token of an invitation link; registering with one joins its household
+    /// and is allowed even when sign-up is closed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invitation: Option<String>,
+}
+
+impl LoginForm {
+    /// Checks a new account's username and password before it is created.
+    /// Usernames are letters, digits, `.`, `_` and `-`.
+    pub fn validate(&self) -> Result<(), AccountError> {
+        let username = self.username.as_str();
+        if username.is_empty()
+            || username.len() > MAX_USERNAME_LEN
+            || !username
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        {
+            return Err(AccountError::InvalidUsername(username.to_string()));
+        }
+        if self.password.chars().count() < MIN_PASSWORD_LEN {
+            return Err(AccountError::WeakPassword);
+        }
+        Ok(())
+    }
+}
+
+/// Hashes `password` with argon2id and a random salt, in PHC string format.
+#[cfg(feature = "ssr")]
+pub fn hash_password(password: &str) -> Result<String, AccountError> {
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+        .map_err(|e| AccountError::Hash(e.to_string()))?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AccountError::Hash(e.to_string()))
+}
+
+/// Whether `password` matches a hash made by `hash_password`.
+#[cfg(feature = "ssr")]
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+/// A hash of no one's password. Sign-in checks unknown usernames against it, so they take
+/// as long to refuse as a wrong password does.
+#[cfg(feature = "ssr")]
+pub fn dummy_password_hash() -> &'static str {
+    static HASH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    HASH.get_or_init(|| hash_password("not anyone's password").unwrap_or_default())
+}
+
+/// A fresh random session token for the cookie.
+#[cfg(feature = "ssr")]
+pub fn generate_session_token() -> String {
+    hex::encode(rand::random::<[u8; SESSION_TOKEN_LEN]>())
+}
+
+/// What the store keeps of a session token, so a leaked database can't be used to sign in.
+#[cfg(feature = "ssr")]
+pub fn session_token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// --- Errors ---
+
+/// Why an account request was refused.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccountError {
+    /// The username is empty, too long or has characters other than letters, digits, `.`, `_` and `-`.
+    InvalidUsername(String),
+    /// The password is shorter than `MIN_PASSWORD_LEN`.
+    WeakPassword,
+    /// Another account already has this username (compared case-insensitively).
+    UsernameTaken(String),
+    /// No account has this username and password.
+    InvalidCredentials,
+    /// The request has no valid session cookie.
+    NotLoggedIn,
+    /// Accounts can only be created by signed-in users or with an invitation.
+    SignupClosed,
+    /// argon2 failed to hash the password.
+    Hash(String),
+}
+
+impl AccountError {
+    /// Stable, machine-readable identifier returned in error responses.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AccountError::InvalidUsername(_) => "invalid_username",
+            AccountError::WeakPassword => "weak_password",
+            AccountError::UsernameTaken(_) => "username_taken",
+            AccountError::InvalidCredentials => "invalid_credentials",
+            AccountError::NotLoggedIn => "not_logged_in",
+            AccountError::SignupClosed => "signup_closed",
+            AccountError::Hash(_) => "password_hash_failed",
+        }
+    }
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::InvalidUsername(name) => write!(
+                f,
+                "Invalid username {:?}: use up to {} letters, digits, '.', '_' or '-'",
+                name, MAX_USERNAME_LEN
+            ),
+            AccountError::WeakPassword => write!(
+                f,
+                "Password must be at least {} characters long",
+                MIN_PASSWORD_LEN
+            ),
+            AccountError::UsernameTaken(name) => write!(f, "Username {:?} is taken", name),
+            AccountError::InvalidCredentials => write!(f, "Wrong username or password"),
+            AccountError::NotLoggedIn => write!(f, "Sign in to continue"),
+            AccountError::SignupClosed => {
+                write!(
+                    f,
+                    "New accounts need an invitation or a signed-in user to create them"
+                )
+            }
+            AccountError::Hash(e) => write!(f, "Failed to hash password: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+// --- Tests ---
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    fn form(username: &str, password: &str) -> LoginForm {
+        LoginForm {
+            username: username.to_string(),
+            password: password.to_string(),
+            invitation: None,
+        }
+    }
+
+    #[test]
+    fn usernames_and_passwords_are_checked_at_their_limits() {
+        assert_eq!(form("ann.b_c-1", "12345678").validate(), Ok(()));
+        assert_eq!(
+            form(&"a".repeat(MAX_USERNAME_LEN), "12345678").validate(),
+            Ok(())
+        );
+        for username in ["", "ann b", "änn", &"a".repeat(MAX_USERNAME_LEN + 1)] {
+            assert_eq!(
+                form(username, "12345678").validate(),
+                Err(AccountError::InvalidUsername(username.to_string())),
+                "{username:?}"
+            );
+        }
+        // Counted in characters, not bytes
+        assert_eq!(
+            form("ann", "ääääääa").validate(),
+            Err(AccountError::WeakPassword)
+        );
+    }
+
+    #[test]
+    fn a_password_only_matches_its_own_hash() {
+        let hash = hash_password("correct horse").unwrap();
+        assert_ne!(hash, hash_password("correct horse").unwrap(), "salted");
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("correct horse!", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+        assert!(!verify_password("correct horse", dummy_password_hash()));
+    }
+
+    #[test]
+    fn only_a_hash_of_the_session_token_is_kept() {
+        let token = generate_session_token();
+        assert_eq!(token.len(), 2 * SESSION_TOKEN_LEN);
+        assert_ne!(token, generate_session_token());
+        let hash = session_token_hash(&token);
+        assert_eq!(hash, session_token_hash(&token));
+        assert_ne!(hash, token);
+        assert_ne!(hash, session_token_hash(&generate_session_token()));
+    }
+}
@@ -0,0 +1,362 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[cfg(feature = "ssr")]
+use crate::gps_data::{DeviceTimeZone, StoredData};
+#[cfg(feature = "ssr")]
+use crate::storage::{DataStore, StorageError};
+#[cfg(feature = "ssr")]
+use std::collections::BTreeSet;
+
+// --- Alerts ---
+//
+// The server watches every registered device for three kinds of trouble:
+//
+//   low_battery  the newest fix reports a level below BUDDY_ALERT_BATTERY_PERCENT
+//   silent       nothing arrived for BUDDY_ALERT_SILENT_HOURS of the hours the tracker
+//                should have been awake (its START_HOUR..END_HOUR), so nights don't count
+//   clock_skew   the newest fix's device clock was off by more than BUDDY_MAX_CLOCK_SKEW_SECS
+//
+// Battery and clock rules are checked as each fix is stored, silence on a timer. A device
+// has at most one unresolved alert of each kind. It fires, may be acknowledged by someone
+// in the household, and resolves by itself once the condition clears.
+
+/// Points above the threshold the battery has to be back at before a low battery alert
+/// resolves, so a level wavering around the threshold doesn't fire it over and over.
+#[cfg(feature = "ssr")]
+const BATTERY_RESOLVE_MARGIN: u8 = 5;
+
+/// Alerts listed when the request doesn't say how many.
+pub const DEFAULT_ALERT_LIMIT: usize = 50;
+/// Most alerts listed by one request.
+pub const MAX_ALERT_LIMIT: usize = 500;
+
+/// What an alert is about.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    LowBattery,
+    Silent,
+    ClockSkew,
+}
+
+impl AlertKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlertKind::LowBattery => "low_battery",
+            AlertKind::Silent => "silent",
+            AlertKind::ClockSkew => "clock_skew",
+        }
+    }
+
+    /// Short heading for the dashboard.
+    pub fn title(self) -> &'static str {
+        match self {
+            AlertKind::LowBattery => "Low battery",
+            AlertKind::Silent => "Not reporting",
+            AlertKind::ClockSkew => "Clock off",
+        }
+    }
+}
+
+impl std::str::FromStr for AlertKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low_battery" => Ok(AlertKind::LowBattery),
+            "silent" => Ok(AlertKind::Silent),
+            "clock_skew" => Ok(AlertKind::ClockSkew),
+            _ => Err(format!(
+                "Unknown alert kind {:?} (expected low_battery, silent or clock_skew)",
+                s
+            )),
+        }
+    }
+}
+
+/// Where an alert is in its life.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    /// The condition holds and nobody has acknowledged it yet.
+    Firing,
+    /// Someone has seen it; the condition still holds.
+    Acknowledged,
+    /// The condition cleared.
+    Resolved,
+}
+
+impl AlertState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlertState::Firing => "firing",
+            AlertState::Acknowledged => "acknowledged",
+            AlertState::Resolved => "resolved",
+        }
+    }
+}
+
+impl std::str::FromStr for AlertState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "firing" => Ok(AlertState::Firing),
+            "acknowledged" => Ok(AlertState::Acknowledged),
+            "resolved" => Ok(AlertState::Resolved),
+            _ => Err(format!(
+                "Unknown alert state {:?} (expected firing, acknowledged or resolved)",
+                s
+            )),
+        }
+    }
+}
+
+/// Something wrong with a device, as listed by `GET /api/alerts`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Alert {
+    pub alert_id: i64,
+    pub device_id: String,
+    pub kind: AlertKind,
+    pub state: AlertState,
+    /// What was found, e.g. `Battery at 12 %, below 20 %`.
+    pub message: String,
+    pub fired_at: DateTime<Utc>,
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    /// Id of the user who acknowledged it.
+    pub acknowledged_by: Option<i64>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+impl Alert {
+    /// A new firing alert; its id is given when it is stored.
+    pub fn firing(device_id: &str, kind: AlertKind, message: String, now: DateTime<Utc>) -> Self {
+        Alert {
+            alert_id: 0,
+            device_id: device_id.to_string(),
+            kind,
+            state: AlertState::Firing,
+            message,
+            fired_at: now,
+            acknowledged_at: None,
+            acknowledged_by: None,
+            resolved_at: None,
+        }
+    }
+
+    /// Whether the condition still holds, acknowledged or not.
+    pub fn is_open(&self) -> bool {
+        self.state != AlertState::Resolved
+    }
+}
+
+/// Query parameters of `GET /api/alerts`. Every filter is optional.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct AlertQuery {
+    /// Only alerts about this device.
+    pub device_id: Option<String>,
+    /// Only alerts in this state.
+    pub state: Option<AlertState>,
+    /// How many to list, clamped to `MAX_ALERT_LIMIT`.
+    pub limit: Option<usize>,
+}
+
+impl AlertQuery {
+    /// The effective number of alerts to list.
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_ALERT_LIMIT)
+            .clamp(1, MAX_ALERT_LIMIT)
+    }
+}
+
+/// Thresholds the rules fire at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AlertRules {
+    /// Battery level, in percent, below which `low_battery` fires.
+    pub battery_below: u8,
+    /// Awake hours without a report after which `silent` fires.
+    pub silent_hours: f64,
+}
+
+// --- Engine ---
+
+/// Checks the battery and clock rules against a fix that was just stored, and resolves
+/// the device's `silent` alert now that it has been heard from. Returns every alert that
+/// fired or resolved.
+///
+/// Only the device's newest fix speaks for its battery and clock, so a buffered fix
+/// arriving late just resolves `silent`. Devices missing from the registry are ignored.
+#[cfg(feature = "ssr")]
+pub fn on_fix(
+    store: &dyn DataStore,
+    rules: AlertRules,
+    fix: &StoredData,
+    now: DateTime<Utc>,
+) -> Result<Vec<Alert>, StorageError> {
+    let Some(household_id) = store.device(&fix.id)?.and_then(|d| d.household_id) else {
+        return Ok(Vec::new());
+    };
+    let mut changed = store.resolve_alerts(&fix.id, AlertKind::Silent, now)?;
+
+    let is_newest = store
+        .latest(&[household_id])?
+        .iter()
+        .find(|latest| latest.id == fix.id)
+        .is_none_or(|latest| latest.timestamp <= fix.timestamp);
+    if !is_newest {
+        return Ok(changed);
+    }
+
+    if fix.battery < rules.battery_below {
+        let message = format!(
+            "Battery at {} %, below {} %",
+            fix.battery, rules.battery_below
+        );
+        changed.extend(store.create_alert(&Alert::firing(
+            &fix.id,
+            AlertKind::LowBattery,
+            message,
+            now,
+        ))?);
+    } else if fix.battery >= rules.battery_below.saturating_add(BATTERY_RESOLVE_MARGIN) {
+        changed.extend(store.resolve_alerts(&fix.id, AlertKind::LowBattery, now)?);
+    }
+
+    if fix.clock_skew_flagged {
+        let message = format!(
+            "Device clock is {} s {} the server's",
+            fix.clock_skew_secs.abs(),
+            if fix.clock_skew_secs > 0 {
+                "ahead of"
+            } else {
+                "behind"
+            }
+        );
+        changed.extend(store.create_alert(&Alert::firing(
+            &fix.id,
+            AlertKind::ClockSkew,
+            message,
+            now,
+        ))?);
+    } else {
+        changed.extend(store.resolve_alerts(&fix.id, AlertKind::ClockSkew, now)?);
+    }
+    Ok(changed)
+}
+
+/// Fires `silent` for every device in a household that has gone `rules.silent_hours`
+/// awake hours without reporting. Devices that never reported aren't expected to yet.
+/// Returns the alerts that fired.
+#[cfg(feature = "ssr")]
+pub fn check_silent(
+    store: &dyn DataStore,
+    rules: AlertRules,
+    default_tz: DeviceTimeZone,
+    now: DateTime<Utc>,
+) -> Result<Vec<Alert>, StorageError> {
+    let devices = store.all_devices()?;
+    let household_ids: Vec<i64> = devices
+        .iter()
+        .filter_map(|device| device.household_id)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let latest = store.latest(&household_ids)?;
+
+    let mut fired = Vec::new();
+    for device in devices.iter().filter(|d| d.household_id.is_some()) {
+        let Some(fix) = latest.iter().find(|fix| fix.id == device.id) else {
+            continue;
+        };
+        // When it arrived, not the device's own timestamp: a clock running ahead would
+        // otherwise keep the device looking heard from until its clock's "now" came round
+        let last_report = fix.received_at;
+        let tz = device.time_zone(default_tz);
+        let awake = crate::battery::awake_hours(last_report, now, device.schedule, tz);
+        if awake < rules.silent_hours {
+            continue;
+        }
+        let message = format!(
+            "No report for {:.1} awake hours, last heard from at {}",
+            awake,
+            tz.local_time(last_report).format("%Y-%m-%d %H:%M")
+        );
+        fired.extend(store.create_alert(&Alert::firing(
+            &device.id,
+            AlertKind::Silent,
+            message,
+            now,
+        ))?);
+    }
+    Ok(fired)
+}
+
+// --- Errors ---
+
+/// Why an alert request was refused.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AlertError {
+    /// No alert with this id in the user's households.
+    NotFound(i64),
+    /// Only firing alerts can be acknowledged.
+    NotFiring(AlertState),
+}
+
+impl AlertError {
+    /// Stable, machine-readable identifier returned in error responses.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AlertError::NotFound(_) => "alert_not_found",
+            AlertError::NotFiring(_) => "alert_not_firing",
+        }
+    }
+}
+
+impl fmt::Display for AlertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertError::NotFound(id) => write!(f, "Alert {} does not exist", id),
+            AlertError::NotFiring(state) => write!(
+                f,
+                "Only firing alerts can be acknowledged; this one is {}",
+                state.as_str()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AlertError {}
+
+// --- Tests ---
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{at, backends, device, household, insert, point};
+
+    const RULES: AlertRules = AlertRules {
+        battery_below: 20,
+        silent_hours: 3.0,
+    };
+
+    #[test]
+    fn a_device_with_its_clock_running_ahead_still_goes_silent() {
+        for (name, store) in backends() {
+            let (ann, _) = household(store.as_ref(), "ann");
+            store.create_device(&device("A", ann)).unwrap();
+            let mut fix = point("A", 0);
+            fix.timestamp = at(24 * 60);
+            insert(store.as_ref(), &[fix]);
+            let utc = "+00:00".parse().unwrap();
+
+            let fired = check_silent(store.as_ref(), RULES, utc, at(60)).unwrap();
+            assert!(fired.is_empty(), "{name}");
+            let fired = check_silent(store.as_ref(), RULES, utc, at(10 * 60)).unwrap();
+            assert_eq!(fired.len(), 1, "{name}");
+            assert_eq!(fired[0].kind, AlertKind::Silent, "{name}");
+        }
+    }
+}
@@ -1,48 +1,833 @@
-use crate::gps_data::StoredData;
+use crate::account::{LoginForm, User};
+use crate::alert::{Alert, AlertState};
+use crate::battery::BatteryChart;
+use crate::device::{Device, Freshness, LatestFix};
+use crate::email::EmailPrefs;
+use crate::geofence::{Geofence, GeofenceEvent, GeofenceShape, Transition};
+use crate::gps_data::{DataPage, DataQuery, SortOrder, StoredData, StoredDataRowRenderer};
+use crate::household::{Membership, Role, RoleChange};
+use crate::map::{MapSettings, TrackMap};
+use crate::playback::Playback;
+use crate::share::{NewShare, SharedLocation};
+use crate::webhook::{Delivery, DeliveryState, EventType};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
 use leptos::logging::log;
 use leptos::prelude::*;
 use leptos::*;
 use leptos_meta::*;
+use leptos_router::components::{Route, Router, Routes};
+use leptos_router::hooks::{use_navigate, use_params_map, use_query_map};
+use leptos_router::path;
 use leptos_struct_table::*;
 
+use futures_util::StreamExt;
+use gloo_net::eventsource::futures::EventSource;
 use gloo_net::http::Request;
 use wasm_bindgen::JsCast;
 use web_sys::BlobPropertyBag;
 
-/// Asynchronously fetches all data from the backend API
-async fn fetch_api_data() -> Result<Vec<StoredData>, ServerFnError<()>> {
+/// Asynchronously fetches one page of data matching `query` from the backend API
+async fn fetch_api_data(query: DataQuery) -> Result<DataPage, ServerFnError<()>> {
     let url = "/api/data";
 
+    // Only send the filters that are set; the server treats missing ones as "any"
+    let mut params = vec![(
+        "order",
+        match query.order {
+            SortOrder::Asc => "asc".to_string(),
+            SortOrder::Desc => "desc".to_string(),
+        },
+    )];
+    if let Some(device_id) = query.device_id {
+        params.push(("device_id", device_id));
+    }
+    if let Some(from) = query.from {
+        params.push(("from", from.to_rfc3339()));
+    }
+    if let Some(to) = query.to {
+        params.push(("to", to.to_rfc3339()));
+    }
+    if let Some(limit) = query.limit {
+        params.push(("limit", limit.to_string()));
+    }
+    if let Some(cursor) = query.cursor {
+        params.push(("cursor", cursor));
+    }
+
     // --- REPLACED reqwest WITH gloo_net::http ---
     let response = Request::get(url)
+        .query(params)
+        .send()
+        .await
+        .map_err(|e| ServerFnError::<()>::ServerError(format!("Fetch failed: {}", e)))?;
+
+    if !response.ok() {
+        return Err(ServerFnError::<()>::ServerError(format!(
+            "Server returned status code {}",
+            response.status()
+        )));
+    }
+
+    // gloo-net has a built-in method to deserialize the body
+    let page = response
+        .json::<DataPage>()
+        .await
+        .map_err(|e| ServerFnError::<()>::Deserialization(format!("JSON parsing failed: {}", e)))?;
+    log!("Data fetched successfully, rows: {}", page.data.len());
+    Ok(page)
+}
+
+/// Asynchronously fetches the device registry from the backend API
+async fn fetch_devices() -> Result<Vec<Device>, ServerFnError<()>> {
+    let response = Request::get("/api/devices")
+        .send()
+        .await
+        .map_err(|e| ServerFnError::<()>::ServerError(format!("Fetch failed: {}", e)))?;
+
+    if !response.ok() {
+        return Err(ServerFnError::<()>::ServerError(format!(
+            "Server returned status code {}",
+            response.status()
+        )));
+    }
+
+    response
+        .json::<Vec<Device>>()
+        .await
+        .map_err(|e| ServerFnError::<()>::Deserialization(format!("JSON parsing failed: {}", e)))
+}
+
+/// Asynchronously fetches each device's latest position from the backend API
+async fn fetch_latest() -> Result<Vec<LatestFix>, ServerFnError<()>> {
+    let response = Request::get("/api/devices/latest")
+        .send()
+        .await
+        .map_err(|e| ServerFnError::<()>::ServerError(format!("Fetch failed: {}", e)))?;
+
+    if !response.ok() {
+        return Err(ServerFnError::<()>::ServerError(format!(
+            "Server returned status code {}",
+            response.status()
+        )));
+    }
+
+    response
+        .json::<Vec<LatestFix>>()
+        .await
+        .map_err(|e| ServerFnError::<()>::Deserialization(format!("JSON parsing failed: {}", e)))
+}
+
+/// Asynchronously fetches the alerts about `device_id`, or about every device, from the backend API
+async fn fetch_alerts(device_id: Option<String>) -> Result<Vec<Alert>, ServerFnError<()>> {
+    let mut params = vec![("limit", ALERTS_SHOWN.to_string())];
+    if let Some(device_id) = device_id {
+        params.push(("device_id", device_id));
+    }
+    let response = Request::get("/api/alerts")
+        .query(params)
+        .send()
+        .await
+        .map_err(|e| ServerFnError::<()>::ServerError(format!("Fetch failed: {}", e)))?;
+
+    if !response.ok() {
+        return Err(ServerFnError::<()>::ServerError(format!(
+            "Server returned status code {}",
+            response.status()
+        )));
+    }
+
+    response
+        .json::<Vec<Alert>>()
+        .await
+        .map_err(|e| ServerFnError::<()>::Deserialization(format!("JSON parsing failed: {}", e)))
+}
+
+/// Asynchronously fetches `device_id`'s geofences from the backend API
+async fn fetch_geofences(device_id: String) -> Result<Vec<Geofence>, ServerFnError<()>> {
+    let response = Request::get(&format!("/api/devices/{}/geofences", device_id))
+        .send()
+        .await
+        .map_err(|e| ServerFnError::<()>::ServerError(format!("Fetch failed: {}", e)))?;
+
+    if !response.ok() {
+        return Err(ServerFnError::<()>::ServerError(format!(
+            "Server returned status code {}",
+            response.status()
+        )));
+    }
+
+    response
+        .json::<Vec<Geofence>>()
+        .await
+        .map_err(|e| ServerFnError::<()>::Deserialization(format!("JSON parsing failed: {}", e)))
+}
+
+/// Asynchronously fetches `device_id`'s latest geofence crossings from the backend API
+async fn fetch_geofence_events(device_id: String) -> Result<Vec<GeofenceEvent>, ServerFnError<()>> {
+    let response = Request::get("/api/geofence-events")
+        .query([
+            ("device_id", device_id),
+            ("limit", GEOFENCE_EVENTS_SHOWN.to_string()),
+        ])
+        .send()
+        .await
+        .map_err(|e| ServerFnError::<()>::ServerError(format!("Fetch failed: {}", e)))?;
+
+    if !response.ok() {
+        return Err(ServerFnError::<()>::ServerError(format!(
+            "Server returned status code {}",
+            response.status()
+        )));
+    }
+
+    response
+        .json::<Vec<GeofenceEvent>>()
+        .await
+        .map_err(|e| ServerFnError::<()>::Deserialization(format!("JSON parsing failed: {}", e)))
+}
+
+/// Acknowledges a firing alert
+async fn send_acknowledge(alert_id: i64) -> Result<Alert, String> {
+    let response = Request::post(&format!("/api/alerts/{}/acknowledge", alert_id))
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    if !response.ok() {
+        return Err(error_message(response).await);
+    }
+
+    response
+        .json::<Alert>()
+        .await
+        .map_err(|e| format!("JSON parsing failed: {}", e))
+}
+
+/// Asynchronously fetches the latest dead webhook deliveries of `household_id` from the backend API
+async fn fetch_dead_letters(household_id: i64) -> Result<Vec<Delivery>, ServerFnError<()>> {
+    let response = Request::get(&format!("/api/households/{}/deliveries", household_id))
+        .query([
+            ("state", DeliveryState::Dead.as_str().to_string()),
+            ("limit", DEAD_LETTERS_SHOWN.to_string()),
+        ])
+        .send()
+        .await
+        .map_err(|e| ServerFnError::<()>::ServerError(format!("Fetch failed: {}", e)))?;
+
+    if !response.ok() {
+        return Err(ServerFnError::<()>::ServerError(format!(
+            "Server returned status code {}",
+            response.status()
+        )));
+    }
+
+    response
+        .json::<Vec<Delivery>>()
+        .await
+        .map_err(|e| ServerFnError::<()>::Deserialization(format!("JSON parsing failed: {}", e)))
+}
+
+/// Puts a dead webhook delivery back in the outbox
+async fn send_retry(household_id: i64, delivery_id: i64) -> Result<Delivery, String> {
+    let response = Request::post(&format!(
+        "/api/households/{}/deliveries/{}/retry",
+        household_id, delivery_id
+    ))
+    .send()
+    .await
+    .map_err(|e| format!("Request failed: {}", e))?;
+
+    if !response.ok() {
+        return Err(error_message(response).await);
+    }
+
+    response
+        .json::<Delivery>()
+        .await
+        .map_err(|e| format!("JSON parsing failed: {}", e))
+}
+
+/// Asynchronously fetches how the map should be drawn from the backend API
+async fn fetch_map_settings() -> Result<MapSettings, ServerFnError<()>> {
+    let response = Request::get("/api/map")
+        .send()
+        .await
+        .map_err(|e| ServerFnError::<()>::ServerError(format!("Fetch failed: {}", e)))?;
+
+    if !response.ok() {
+        return Err(ServerFnError::<()>::ServerError(format!(
+            "Server returned status code {}",
+            response.status()
+        )));
+    }
+
+    response
+        .json::<MapSettings>()
+        .await
+        .map_err(|e| ServerFnError::<()>::Deserialization(format!("JSON parsing failed: {}", e)))
+}
+
+/// Asks the backend who is signed in; `None` if nobody is
+async fn fetch_me() -> Result<Option<User>, ServerFnError<()>> {
+    let response = Request::get("/api/auth/me")
         .send()
         .await
         .map_err(|e| ServerFnError::<()>::ServerError(format!("Fetch failed: {}", e)))?;
 
-    if !response.ok() {
-        return Err(ServerFnError::<()>::ServerError(format!(
-            "Server returned status code {}",
-            response.status()
-        )));
+    if response.status() == 401 {
+        return Ok(None);
+    }
+    if !response.ok() {
+        return Err(ServerFnError::<()>::ServerError(format!(
+            "Server returned status code {}",
+            response.status()
+        )));
+    }
+
+    response
+        .json::<User>()
+        .await
+        .map(Some)
+        .map_err(|e| ServerFnError::<()>::Deserialization(format!("JSON parsing failed: {}", e)))
+}
+
+/// Posts the login form to `url` (sign in or create an account), returning the signed-in
+/// user or the server's explanation of why not
+async fn send_login(url: &str, form: LoginForm) -> Result<User, String> {
+    let response = Request::post(url)
+        .json(&form)
+        .map_err(|e| e.to_string())?
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    if !response.ok() {
+        return Err(error_message(response).await);
+    }
+
+    response
+        .json::<User>()
+        .await
+        .map_err(|e| format!("JSON parsing failed: {}", e))
+}
+
+/// The `message` of an API error response, or its status code if it has none
+async fn error_message(response: gloo_net::http::Response) -> String {
+    let message = response
+        .json::<serde_json::Value>()
+        .await
+        .ok()
+        .and_then(|body| body["message"].as_str().map(str::to_string));
+    message.unwrap_or_else(|| format!("Server returned status code {}", response.status()))
+}
+
+/// Asynchronously fetches the signed-in user's households from the backend API
+async fn fetch_households() -> Result<Vec<Membership>, ServerFnError<()>> {
+    let response = Request::get("/api/households")
+        .send()
+        .await
+        .map_err(|e| ServerFnError::<()>::ServerError(format!("Fetch failed: {}", e)))?;
+
+    if !response.ok() {
+        return Err(ServerFnError::<()>::ServerError(format!(
+            "Server returned status code {}",
+            response.status()
+        )));
+    }
+
+    response
+        .json::<Vec<Membership>>()
+        .await
+        .map_err(|e| ServerFnError::<()>::Deserialization(format!("JSON parsing failed: {}", e)))
+}
+
+/// Asynchronously fetches which notification emails the current user wants from the backend API
+async fn fetch_email_prefs() -> Result<EmailPrefs, ServerFnError<()>> {
+    let response = Request::get("/api/auth/me/notifications")
+        .send()
+        .await
+        .map_err(|e| ServerFnError::<()>::ServerError(format!("Fetch failed: {}", e)))?;
+
+    if !response.ok() {
+        return Err(ServerFnError::<()>::ServerError(format!(
+            "Server returned status code {}",
+            response.status()
+        )));
+    }
+
+    response
+        .json::<EmailPrefs>()
+        .await
+        .map_err(|e| ServerFnError::<()>::Deserialization(format!("JSON parsing failed: {}", e)))
+}
+
+/// Saves which notification emails the current user wants
+async fn send_email_prefs(prefs: EmailPrefs) -> Result<EmailPrefs, String> {
+    let response = Request::put("/api/auth/me/notifications")
+        .json(&prefs)
+        .map_err(|e| e.to_string())?
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    if !response.ok() {
+        return Err(error_message(response).await);
+    }
+
+    response
+        .json::<EmailPrefs>()
+        .await
+        .map_err(|e| format!("JSON parsing failed: {}", e))
+}
+
+/// Creates an invitation to `household_id` and returns the link to share
+async fn send_invitation(household_id: i64, role: Role) -> Result<String, String> {
+    let response = Request::post(&format!("/api/households/{}/invitations", household_id))
+        .json(&RoleChange { role })
+        .map_err(|e| e.to_string())?
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    if !response.ok() {
+        return Err(error_message(response).await);
+    }
+
+    let body = response
+        .json::<serde_json::Value>()
+        .await
+        .map_err(|e| format!("JSON parsing failed: {}", e))?;
+    let link = body["link"].as_str().unwrap_or_default();
+    let origin = window().location().origin().unwrap_or_default();
+    Ok(format!("{}{}", origin, link))
+}
+
+/// Joins the household of the invitation with this token
+async fn send_accept(token: &str) -> Result<Membership, String> {
+    let response = Request::post(&format!("/api/invitations/{}/accept", token))
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    if !response.ok() {
+        return Err(error_message(response).await);
+    }
+
+    response
+        .json::<Membership>()
+        .await
+        .map_err(|e| format!("JSON parsing failed: {}", e))
+}
+
+/// Ends the session on the backend
+async fn send_logout() -> Result<(), String> {
+    Request::post("/api/auth/logout")
+        .send()
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("Request failed: {}", e))
+}
+
+/// Creates a share link for `device_id` with the default durations and returns the link
+async fn send_share(device_id: &str) -> Result<String, String> {
+    let response = Request::post(&format!("/api/devices/{}/shares", device_id))
+        .json(&NewShare::default())
+        .map_err(|e| e.to_string())?
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    if !response.ok() {
+        return Err(error_message(response).await);
+    }
+
+    let body = response
+        .json::<serde_json::Value>()
+        .await
+        .map_err(|e| format!("JSON parsing failed: {}", e))?;
+    let link = body["link"].as_str().unwrap_or_default();
+    let origin = window().location().origin().unwrap_or_default();
+    Ok(format!("{}{}", origin, link))
+}
+
+/// Resolves a share link's token; no session needed
+async fn fetch_shared(token: &str) -> Result<SharedLocation, String> {
+    let response = Request::get(&format!("/api/shared/{}", token))
+        .send()
+        .await
+        .map_err(|e| format!("Fetch failed: {}", e))?;
+
+    if !response.ok() {
+        return Err(error_message(response).await);
+    }
+
+    response
+        .json::<SharedLocation>()
+        .await
+        .map_err(|e| format!("JSON parsing failed: {}", e))
+}
+
+/// Longest wait between attempts to reconnect to the live feed.
+const LIVE_MAX_BACKOFF_MS: u64 = 30_000;
+
+/// Follows `/api/live` and collects the points it sends, newest first.
+///
+/// A dropped connection is retried after 1, 2, 4 ... up to 30 seconds, resuming after the
+/// last event id so nothing is missed. `follow` and `stop` bump `generation`, which tells
+/// the running connection and any pending retry that they are no longer wanted.
+#[derive(Clone, Copy)]
+struct LiveSubscription {
+    rows: RwSignal<Vec<StoredData>>,
+    last_id: StoredValue<Option<String>>,
+    source: StoredValue<Option<EventSource>, LocalStorage>,
+    generation: StoredValue<u64>,
+}
+
+impl LiveSubscription {
+    fn new() -> Self {
+        LiveSubscription {
+            rows: RwSignal::new(Vec::new()),
+            last_id: StoredValue::new(None),
+            source: StoredValue::new_local(None),
+            generation: StoredValue::new(0),
+        }
+    }
+
+    /// Starts over with the points of `device_id`, or every device, stored from now on.
+    fn follow(self, device_id: Option<String>) {
+        self.stop();
+        self.rows.set(Vec::new());
+        self.last_id.set_value(None);
+        self.connect(device_id, self.generation.get_value(), 0);
+    }
+
+    /// Closes the connection and cancels pending retries.
+    fn stop(self) {
+        self.generation.update_value(|g| *g += 1);
+        // Dropping the EventSource closes it, which ends its stream
+        self.source.set_value(None);
+    }
+
+    fn connect(self, device_id: Option<String>, generation: u64, attempt: u32) {
+        if self.generation.get_value() != generation {
+            return;
+        }
+        let mut params = Vec::new();
+        if let Some(device_id) = &device_id {
+            params.push(format!("device_id={}", device_id));
+        }
+        if let Some(last_id) = self.last_id.get_value() {
+            params.push(format!("last_id={}", last_id));
+        }
+        let url = format!("/api/live?{}", params.join("&"));
+
+        leptos::task::spawn_local(async move {
+            let mut attempt = attempt;
+            match EventSource::new(&url) {
+                Ok(mut source) => {
+                    match source.subscribe("fix") {
+                        Ok(mut fixes) => {
+                            self.source.set_value(Some(source));
+                            while let Some(Ok((_, message))) = fixes.next().await {
+                                let Some(data) = message.data().as_string().and_then(|json| {
+                                    serde_json::from_str::<StoredData>(&json).ok()
+                                }) else {
+                                    continue;
+                                };
+                                attempt = 0;
+                                self.last_id.set_value(Some(message.last_event_id()));
+                                self.rows.update(|rows| rows.insert(0, data));
+                            }
+                        }
+                        Err(e) => log!("Live feed: {}", e),
+                    }
+                }
+                Err(e) => log!("Live feed: {}", e),
+            }
+
+            // Closed by `stop`, or lost: retry, unless something newer took over
+            if self.generation.get_value() != generation {
+                return;
+            }
+            self.source.set_value(None);
+            let delay = (1000u64 << attempt.min(5)).min(LIVE_MAX_BACKOFF_MS);
+            log!("Live feed disconnected; reconnecting in {} ms", delay);
+            set_timeout(
+                move || self.connect(device_id, generation, attempt + 1),
+                std::time::Duration::from_millis(delay),
+            );
+        });
+    }
+}
+
+/// Human-friendly "how long ago", e.g. `5 min ago` or `2 d ago`.
+fn format_age(age_secs: i64) -> String {
+    match age_secs {
+        s if s < 60 => "just now".to_string(),
+        s if s < 3600 => format!("{} min ago", s / 60),
+        s if s < 86400 => format!("{} h ago", s / 3600),
+        s => format!("{} d ago", s / 86400),
+    }
+}
+
+/// Summary card for one pet: photo, battery, last position and how fresh it is.
+#[component]
+fn PetCard(fix: LatestFix) -> impl IntoView {
+    let (badge_class, badge_text) = match fix.freshness() {
+        Freshness::Fresh => ("bg-green-100 text-green-800", "Live"),
+        Freshness::Late => ("bg-amber-100 text-amber-800", "Late"),
+        Freshness::Stale => ("bg-red-100 text-red-800", "Stale"),
+    };
+    let battery_icon = match fix.battery {
+        Some(b) if b > 75 => "fas fa-battery-full text-green-600",
+        Some(b) if b > 40 => "fas fa-battery-half text-amber-500",
+        Some(_) => "fas fa-battery-quarter text-red-500",
+        None => "fas fa-battery-empty text-gray-400",
+    };
+
+    view! {
+        <div class="text-left bg-amber-50 hover:bg-amber-100 rounded-2xl shadow p-4 transition duration-300">
+            <div class="flex items-center gap-3 mb-3">
+                {match fix.device.as_ref().and_then(|d| d.photo_url.clone()) {
+                    Some(url) => view! { <img src=url alt="" class="w-12 h-12 rounded-full object-cover"/> }.into_any(),
+                    None => view! { <i class="fas fa-dog text-3xl text-amber-500 w-12 text-center"></i> }.into_any(),
+                }}
+                <div class="flex-1 min-w-0">
+                    <p class="text-lg font-bold text-teal-800 truncate">{fix.label().to_string()}</p>
+                    <p class="text-xs text-gray-500 truncate">{fix.device_id.clone()}</p>
+                </div>
+                <span class=format!("text-xs font-semibold px-2 py-1 rounded-full {}", badge_class)>{badge_text}</span>
+            </div>
+            <div class="flex justify-between text-sm text-gray-700">
+                <span>
+                    <i class=format!("{} mr-1", battery_icon)></i>
+                    {fix.battery.map_or("-".to_string(), |b| format!("{} %", b))}
+                </span>
+                <span>
+                    <i class="fas fa-clock mr-1 text-gray-400"></i>
+                    {fix.age_secs.map_or("never".to_string(), format_age)}
+                </span>
+            </div>
+            {fix.battery_forecast.map(|forecast| view! {
+                <p class="mt-2 text-xs text-gray-500">
+                    <i class="fas fa-hourglass-half mr-1 text-red-400"></i>
+                    {format!("Battery empty around {}", forecast.empty_at.with_timezone(&Local).format("%a %H:%M"))}
+                </p>
+            })}
+            {fix.data.map(|data| view! {
+                <p class="mt-2 text-xs text-gray-500">
+                    <i class="fas fa-map-marker-alt mr-1 text-red-400"></i>
+                    {format!("{:.5}, {:.5}", data.latitude_deg, data.longitude_deg)}
+                </p>
+            })}
+        </div>
+    }
+}
+
+/// The latest alerts about `device_id`, or about every device, unresolved ones first,
+/// with a button to acknowledge those still firing. Devices are named after their
+/// entry in `fixes`, and the list is fetched again whenever `fixes` is.
+/// Nothing is shown while there are no alerts.
+#[component]
+fn AlertList(
+    #[prop(into)] device_id: Signal<Option<String>>,
+    #[prop(into)] fixes: Signal<Vec<LatestFix>>,
+) -> impl IntoView {
+    let alerts_resource = LocalResource::new(move || {
+        fixes.track();
+        let device_id = device_id.get();
+        async move { fetch_alerts(device_id).await }
+    });
+    // Why the last acknowledgement failed
+    let error = RwSignal::new(None::<String>);
+
+    let acknowledge = move |alert_id: i64| {
+        leptos::task::spawn_local(async move {
+            match send_acknowledge(alert_id).await {
+                Ok(_) => {
+                    error.set(None);
+                    alerts_resource.refetch();
+                }
+                Err(message) => error.set(Some(message)),
+            }
+        });
+    };
+
+    let label = move |device_id: &str| {
+        fixes.with(|fixes| {
+            fixes
+                .iter()
+                .find(|fix| fix.device_id == device_id)
+                .map_or(device_id.to_string(), |fix| fix.label().to_string())
+        })
+    };
+
+    let rows = move || {
+        alerts_resource
+            .get()
+            .and_then(Result::ok)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|alert| {
+                let (row_class, icon_class) = match alert.state {
+                    AlertState::Firing => ("border-red-500 bg-red-50", "fas fa-bell text-red-500"),
+                    AlertState::Acknowledged => ("border-amber-400 bg-amber-50", "fas fa-bell-slash text-amber-500"),
+                    AlertState::Resolved => ("border-gray-300 bg-white text-gray-500", "fas fa-check text-gray-400"),
+                };
+                let alert_id = alert.alert_id;
+                view! {
+                    <li class=format!("flex items-center gap-3 border-l-4 rounded-r-xl shadow-sm px-4 py-2 text-sm {}", row_class)>
+                        <i class=icon_class></i>
+                        <div class="flex-1 min-w-0">
+                            <p class="font-semibold text-teal-800 truncate">
+                                {format!("{}: {}", label(&alert.device_id), alert.kind.title())}
+                            </p>
+                            <p class="text-xs truncate">{alert.message.clone()}</p>
+                        </div>
+                        <span class="text-xs text-gray-500 whitespace-nowrap">
+                            {alert.fired_at.with_timezone(&Local).format("%a %H:%M").to_string()}
+                        </span>
+                        <span class="text-xs font-semibold px-2 py-1 rounded-full bg-white/70">{alert.state.as_str()}</span>
+                        <Show when=move || alert.state == AlertState::Firing>
+                            <button on:click=move |_| acknowledge(alert_id) class="text-teal-700 hover:underline text-xs">
+                                "Acknowledge"
+                            </button>
+                        </Show>
+                    </li>
+                }
+            })
+            .collect_view()
+    };
+
+    view! {
+        <Show when=move || alerts_resource.get().and_then(Result::ok).is_some_and(|alerts| !alerts.is_empty())>
+            <h2 class="text-2xl font-bold text-teal-800 mb-4 border-l-4 border-amber-400 pl-3">
+                "Alerts"
+            </h2>
+            <ul class="space-y-2 mb-10">{rows}</ul>
+            {move || error.get().map(|message| view! { <p class="-mt-8 mb-10 text-sm text-red-600">{message}</p> })}
+        </Show>
     }
+    .into_any()
+}
 
-    // gloo-net has a built-in method to deserialize the body
-    let data = response
-        .json::<Vec<StoredData>>()
-        .await
-        .map_err(|e| ServerFnError::<()>::Deserialization(format!("JSON parsing failed: {}", e)))?;
-    log!("Data fetched successfully, rows: {}", data.len());
-    Ok(data)
+/// A device's geofences, which side of each it was last on, and its latest crossings.
+/// Nothing is shown for a device without geofences.
+#[component]
+fn GeofenceList(#[prop(into)] device_id: Signal<Option<String>>) -> impl IntoView {
+    let geofences_resource = LocalResource::new(move || {
+        let device_id = device_id.get();
+        async move {
+            match device_id {
+                Some(device_id) => fetch_geofences(device_id).await,
+                None => Ok(Vec::new()),
+            }
+        }
+    });
+    let events_resource = LocalResource::new(move || {
+        let device_id = device_id.get();
+        async move {
+            match device_id {
+                Some(device_id) => fetch_geofence_events(device_id).await,
+                None => Ok(Vec::new()),
+            }
+        }
+    });
+
+    let geofences = move || {
+        geofences_resource
+            .get()
+            .and_then(Result::ok)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|geofence| {
+                let (badge_class, badge_text) = match geofence.inside {
+                    Some(true) => ("bg-green-100 text-green-800", "Inside"),
+                    Some(false) => ("bg-amber-100 text-amber-800", "Outside"),
+                    None => ("bg-gray-100 text-gray-600", "Unknown"),
+                };
+                let size = match &geofence.shape {
+                    GeofenceShape::Circle { radius_m, .. } => format!("circle, {:.0} m radius", radius_m),
+                    GeofenceShape::Polygon { points } => format!("polygon, {} corners", points.len()),
+                };
+                view! {
+                    <li class="flex items-center justify-between gap-3 bg-amber-50 rounded-xl shadow-sm px-4 py-2 text-sm">
+                        <span class="min-w-0 truncate">
+                            <i class="fas fa-draw-polygon mr-2 text-teal-600"></i>
+                            <span class="font-semibold text-teal-800">{geofence.name.clone()}</span>
+                            <span class="ml-2 text-xs text-gray-500">{size}</span>
+                        </span>
+                        <span class=format!("text-xs font-semibold px-2 py-1 rounded-full {}", badge_class)>{badge_text}</span>
+                    </li>
+                }
+            })
+            .collect_view()
+    };
+
+    let events = move || {
+        events_resource
+            .get()
+            .and_then(Result::ok)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|event| {
+                let (icon_class, verb) = match event.transition {
+                    Transition::Enter => ("fas fa-sign-in-alt text-green-600", "Entered"),
+                    Transition::Exit => ("fas fa-sign-out-alt text-amber-600", "Left"),
+                };
+                view! {
+                    <li class="flex items-center gap-3 px-4 py-1 text-sm text-gray-700">
+                        <i class=icon_class></i>
+                        <span class="flex-1 min-w-0 truncate">{format!("{} {}", verb, event.geofence_name)}</span>
+                        <span class="text-xs text-gray-500 whitespace-nowrap">
+                            {event.fix.timestamp.with_timezone(&Local).format("%a %H:%M").to_string()}
+                        </span>
+                    </li>
+                }
+            })
+            .collect_view()
+    };
+
+    view! {
+        <Show when=move || geofences_resource.get().and_then(Result::ok).is_some_and(|geofences| !geofences.is_empty())>
+            <h2 class="text-2xl font-bold text-teal-800 mb-4 border-l-4 border-amber-400 pl-3">
+                "Geofences"
+            </h2>
+            <ul class="space-y-2 mb-4">{geofences}</ul>
+            <ul class="mb-10">{events}</ul>
+        </Show>
+    }
+    .into_any()
+}
+
+/// Converts the value of a `datetime-local` input (browser local time) to UTC.
+/// An empty or unparseable value clears the filter.
+fn parse_local_datetime(value: &str) -> Option<DateTime<Utc>> {
+    let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M").ok()?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|dt| dt.to_utc())
 }
 
 /// Triggers a client-side download of the provided data as a CSV file
 fn trigger_csv_download(data: Vec<StoredData>) {
     // 1. Build CSV content
-    let mut csv_content = "id,timestamp,longitude,latitude,battery\n".to_string();
+    let mut csv_content =
+        "id,timestamp,received_at,clock_skew_secs,longitude,latitude,raw_longitude,raw_latitude,battery\n"
+            .to_string();
     for entry in data {
         csv_content.push_str(&format!(
-            "{},{},{},{},{}\n",
-            entry.id, entry.timestamp, entry.longitude, entry.latitude, entry.battery
+            "{},{},{},{},{:.6},{:.6},{},{},{}\n",
+            entry.id,
+            entry.timestamp.to_rfc3339(),
+            entry.received_at.to_rfc3339(),
+            entry.clock_skew_secs,
+            entry.longitude_deg,
+            entry.latitude_deg,
+            entry.longitude,
+            entry.latitude,
+            entry.battery
         ));
     }
 
@@ -82,19 +867,590 @@ fn trigger_csv_download(data: Vec<StoredData>) {
     web_sys::Url::revoke_object_url(&url).unwrap();
 }
 
+/// Days of battery readings charted on a device's page.
+const BATTERY_CHART_DAYS: i64 = 7;
+
+/// Most alerts listed on a page.
+const ALERTS_SHOWN: usize = 10;
+
+/// Most geofence crossings listed on a device's page.
+const GEOFENCE_EVENTS_SHOWN: usize = 10;
+
+/// Most dead webhook deliveries listed per household on the settings page.
+const DEAD_LETTERS_SHOWN: usize = 10;
+
 #[component]
 pub fn App() -> impl IntoView {
     provide_meta_context();
-    // Resource to hold the data from the API
-    let data_resource = LocalResource::new(|| async move { fetch_api_data().await });
 
-    // This signal will be used to store the data for the download button
-    let (csv_data, set_csv_data) = signal(Vec::<StoredData>::new());
+    view! {
+        <Stylesheet id="leptos" href="/pkg/buddy_app.css"/>
+        <Router>
+            <Routes fallback=NotFound>
+                <Route path=path!("/") view=Overview/>
+                <Route path=path!("/devices/:id") view=DevicePage/>
+                <Route path=path!("/settings") view=SettingsPage/>
+                <Route path=path!("/login") view=LoginPage/>
+                <Route path=path!("/invite/:token") view=InvitePage/>
+                <Route path=path!("/share/:token") view=SharePage/>
+                // Registers every other path with the server, so it renders NotFound with a 404
+                <Route path=path!("/*any") view=NotFound/>
+            </Routes>
+        </Router>
+    }
+}
+
+/// Sign-in form. The same form creates an account, which works for the very first
+/// account, when sign-up is open, for someone already signed in, or with an invitation.
+/// An invitation link sends people here with `?invitation=<token>`; they join its
+/// household once they have signed in or created their account.
+#[component]
+fn LoginPage() -> impl IntoView {
+    let navigate = use_navigate();
+    let query_map = use_query_map();
+    let username = RwSignal::new(String::new());
+    let password = RwSignal::new(String::new());
+    let error = RwSignal::new(None::<String>);
+
+    let submit = move |url: &'static str| {
+        let navigate = navigate.clone();
+        let invitation = query_map.with_untracked(|q| q.get("invitation"));
+        let form = LoginForm {
+            username: username.get_untracked(),
+            password: password.get_untracked(),
+            invitation: invitation.clone(),
+        };
+        leptos::task::spawn_local(async move {
+            let user = match send_login(url, form).await {
+                Ok(user) => user,
+                Err(message) => return error.set(Some(message)),
+            };
+            log!("Signed in as {}", user.username);
+            // Registering with the token already joined the household; signing in hasn't
+            if url == "/api/auth/login"
+                && let Some(token) = invitation
+                && let Err(message) = send_accept(&token).await
+            {
+                return error.set(Some(message));
+            }
+            navigate("/", Default::default());
+        });
+    };
+    let sign_in = submit.clone();
+
+    view! {
+        <div class="min-h-screen bg-amber-50 p-4 font-sans antialiased flex items-center justify-center">
+            <main class="w-full max-w-sm bg-white rounded-3xl shadow-2xl p-8">
+                <header class="text-center mb-6">
+                    <h1 class="text-3xl font-extrabold text-teal-800 tracking-tight">
+                        <i class="fas fa-paw mr-2 text-amber-500"></i>
+                        "Sign in"
+                    </h1>
+                    <p class="text-gray-600 mt-2">"See where your furry companions are."</p>
+                    <Show when=move || query_map.with(|q| q.get("invitation").is_some())>
+                        <p class="mt-3 text-sm text-teal-700 bg-teal-50 rounded-xl p-2">
+                            <i class="fas fa-envelope-open-text mr-1"></i>
+                            "Sign in or create an account to accept your invitation."
+                        </p>
+                    </Show>
+                </header>
+                <form
+                    on:submit=move |ev| {
+                        ev.prevent_default();
+                        sign_in("/api/auth/login");
+                    }
+                    class="flex flex-col gap-4 text-sm text-gray-700"
+                >
+                    <label class="flex flex-col">
+                        "Username"
+                        <input
+                            type="text"
+                            autocomplete="username"
+                            bind:value=username
+                            class="mt-1 border border-amber-200 rounded-lg px-3 py-2 focus:outline-none focus:ring-2 focus:ring-teal-300"
+                        />
+                    </label>
+                    <label class="flex flex-col">
+                        "Password"
+                        <input
+                            type="password"
+                            autocomplete="current-password"
+                            bind:value=password
+                            class="mt-1 border border-amber-200 rounded-lg px-3 py-2 focus:outline-none focus:ring-2 focus:ring-teal-300"
+                        />
+                    </label>
+                    {move || error.get().map(|message| view! {
+                        <p class="p-3 text-center text-red-600 bg-red-50 rounded-xl">{message}</p>
+                    })}
+                    <button
+                        type="submit"
+                        class="bg-teal-600 hover:bg-teal-700 text-white font-semibold py-3 px-6 rounded-xl shadow-lg transition duration-300 focus:outline-none focus:ring-4 focus:ring-teal-300"
+                    >
+                        "Sign in"
+                    </button>
+                    <button
+                        type="button"
+                        on:click=move |_| submit("/api/auth/register")
+                        class="text-teal-700 hover:underline"
+                    >
+                        "Create account"
+                    </button>
+                </form>
+            </main>
+        </div>
+    }
+}
+
+/// Where an invitation link leads. Joins the household straight away for someone signed in,
+/// and sends anyone else to the login page with the token.
+#[component]
+fn InvitePage() -> impl IntoView {
+    let navigate = use_navigate();
+    let params = use_params_map();
+    let error = RwSignal::new(None::<String>);
+
+    Effect::new(move |_| {
+        let navigate = navigate.clone();
+        let token = params.with(|p| p.get("token")).unwrap_or_default();
+        leptos::task::spawn_local(async move {
+            match fetch_me().await {
+                Ok(Some(_)) => match send_accept(&token).await {
+                    Ok(membership) => {
+                        log!("Joined {}", membership.household.name);
+                        navigate("/", Default::default());
+                    }
+                    Err(message) => error.set(Some(message)),
+                },
+                Ok(None) => navigate(&format!("/login?invitation={}", token), Default::default()),
+                Err(e) => error.set(Some(format!("{:?}", e))),
+            }
+        });
+    });
+
+    view! {
+        <div class="min-h-screen bg-amber-50 p-4 font-sans antialiased flex items-center justify-center">
+            <main class="w-full max-w-sm bg-white rounded-3xl shadow-2xl p-8 text-center">
+                <h1 class="text-2xl font-extrabold text-teal-800 tracking-tight mb-4">
+                    <i class="fas fa-envelope-open-text mr-2 text-amber-500"></i>
+                    "Invitation"
+                </h1>
+                {move || match error.get() {
+                    Some(message) => view! {
+                        <p class="p-3 text-red-600 bg-red-50 rounded-xl">{message}</p>
+                        <a href="/" class="mt-4 inline-block text-teal-700 hover:underline">"Go to the dashboard"</a>
+                    }.into_any(),
+                    None => view! { <p class="text-gray-600">"Joining the household..."</p> }.into_any(),
+                }}
+            </main>
+        </div>
+    }
+}
+
+/// What a share link shows: one pet's card and its recent track, for anyone with the link.
+#[component]
+fn SharePage() -> impl IntoView {
+    let params = use_params_map();
+    let shared_resource = LocalResource::new(move || {
+        let token = params.with(|p| p.get("token")).unwrap_or_default();
+        async move { fetch_shared(&token).await }
+    });
+    let map_resource = LocalResource::new(|| async move { fetch_map_settings().await });
+    let map_settings =
+        Signal::derive(move || map_resource.get().and_then(Result::ok).unwrap_or_default());
+    let selected_row = RwSignal::new(None::<usize>);
+
+    view! {
+        <div class="min-h-screen bg-amber-50 p-4 font-sans antialiased">
+            <main class="max-w-4xl mx-auto bg-white rounded-3xl shadow-2xl p-6 md:p-10">
+                <header class="text-center mb-8">
+                    <h1 class="text-3xl font-extrabold text-teal-800 tracking-tight">
+                        <i class="fas fa-paw mr-3 text-amber-500"></i>
+                        "Have you seen this pet?"
+                    </h1>
+                </header>
+                {move || shared_resource.get().map(|result| match result {
+                    Ok(shared) => {
+                        let expires = shared.expires_at.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string();
+                        let track_hours = shared.track_hours;
+                        let rows = shared.track;
+                        let empty = rows.is_empty();
+                        view! {
+                            <div class="max-w-sm mx-auto mb-8">
+                                <PetCard fix=shared.fix/>
+                            </div>
+                            <h2 class="text-xl font-bold text-teal-800 mb-4 border-l-4 border-amber-400 pl-3">
+                                {format!("Track of the last {} hours", track_hours)}
+                            </h2>
+                            {if empty {
+                                view! { <p class="p-6 text-center text-gray-500">"No positions in this period."</p> }.into_any()
+                            } else {
+                                view! {
+                                    <div class="mb-6">
+                                        <TrackMap rows=Signal::stored(rows.clone()) selected=selected_row settings=map_settings/>
+                                    </div>
+                                    <div class="overflow-x-auto rounded-xl shadow-lg ring-1 ring-gray-200">
+                                        <table class="text-sm text-left text-gray-500 w-full">
+                                            <TableContent rows=rows scroll_container="html" selection=Selection::Single(selected_row)/>
+                                        </table>
+                                    </div>
+                                }.into_any()
+                            }}
+                            <p class="mt-6 text-center text-sm text-gray-500">{format!("This link works until {}.", expires)}</p>
+                        }.into_any()
+                    }
+                    Err(message) => view! {
+                        <p class="p-6 text-center text-red-600 bg-red-50 rounded-xl">{message}</p>
+                    }.into_any(),
+                })}
+            </main>
+        </div>
+    }
+}
+
+/// One of the signed-in user's households. Owners can create invitation links for it.
+#[component]
+fn HouseholdCard(membership: Membership) -> impl IntoView {
+    let household_id = membership.household.household_id;
+    let is_owner = membership.role == Role::Owner;
+    // The last invitation link created, or why it couldn't be
+    let invitation = RwSignal::new(None::<Result<String, String>>);
+
+    let invite = move |role: Role| {
+        leptos::task::spawn_local(async move {
+            invitation.set(Some(send_invitation(household_id, role).await));
+        });
+    };
+
+    view! {
+        <div class="bg-amber-50 rounded-2xl shadow p-4 text-sm">
+            <div class="flex items-center justify-between gap-3">
+                <p class="font-bold text-teal-800 truncate">
+                    <i class="fas fa-home mr-2 text-amber-500"></i>
+                    {membership.household.name.clone()}
+                </p>
+                <span class="text-xs font-semibold px-2 py-1 rounded-full bg-teal-100 text-teal-800">
+                    {membership.role.as_str()}
+                </span>
+            </div>
+            <Show when=move || is_owner>
+                <div class="flex gap-3 mt-3">
+                    <button on:click=move |_| invite(Role::Viewer) class="text-teal-700 hover:underline">"Invite viewer"</button>
+                    <button on:click=move |_| invite(Role::Owner) class="text-teal-700 hover:underline">"Invite owner"</button>
+                </div>
+            </Show>
+            {move || invitation.get().map(|result| match result {
+                Ok(link) => view! {
+                    <input type="text" readonly value=link class="mt-2 w-full border border-amber-200 rounded-lg px-2 py-1 text-xs"/>
+                }.into_any(),
+                Err(message) => view! { <p class="mt-2 text-red-600">{message}</p> }.into_any(),
+            })}
+            <Show when=move || is_owner>
+                <DeadLetters household_id=household_id/>
+            </Show>
+        </div>
+    }
+}
+
+/// Webhook deliveries of a household that ran out of attempts, each with a button to try
+/// it again. Nothing is shown while there are none.
+#[component]
+fn DeadLetters(household_id: i64) -> impl IntoView {
+    let dead_resource =
+        LocalResource::new(move || async move { fetch_dead_letters(household_id).await });
+    // Why the last retry failed
+    let error = RwSignal::new(None::<String>);
+
+    let retry = move |delivery_id: i64| {
+        leptos::task::spawn_local(async move {
+            match send_retry(household_id, delivery_id).await {
+                Ok(_) => {
+                    error.set(None);
+                    dead_resource.refetch();
+                }
+                Err(message) => error.set(Some(message)),
+            }
+        });
+    };
+
+    let rows = move || {
+        dead_resource
+            .get()
+            .and_then(Result::ok)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|delivery| {
+                let delivery_id = delivery.delivery_id;
+                view! {
+                    <li class="flex items-center gap-3 border-l-4 border-red-500 bg-white rounded-r-xl shadow-sm px-3 py-2">
+                        <div class="flex-1 min-w-0">
+                            <p class="font-semibold text-teal-800 truncate" title=delivery.url.clone()>
+                                {format!("{} to {}", delivery.event.as_str(), delivery.url)}
+                            </p>
+                            <p class="text-xs text-gray-500 truncate">
+                                {format!(
+                                    "{}, {} attempts: {}",
+                                    delivery.created_at.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                                    delivery.attempts,
+                                    delivery.last_error.clone().unwrap_or_default(),
+                                )}
+                            </p>
+                        </div>
+                        <button on:click=move |_| retry(delivery_id) class="text-teal-700 hover:underline text-xs">
+                            "Retry"
+                        </button>
+                    </li>
+                }
+            })
+            .collect_view()
+    };
+
+    view! {
+        <Show when=move || dead_resource.get().and_then(Result::ok).is_some_and(|dead| !dead.is_empty())>
+            <p class="mt-4 mb-2 font-semibold text-red-700">
+                <i class="fas fa-triangle-exclamation mr-2"></i>
+                "Undelivered webhook events"
+            </p>
+            <ul class="space-y-2">{rows}</ul>
+            {move || error.get().map(|message| view! { <p class="mt-2 text-red-600">{message}</p> })}
+        </Show>
+    }
+    .into_any()
+}
+
+/// Where the current user's notification emails go and which events send one.
+#[component]
+fn EmailSettings() -> impl IntoView {
+    let email = RwSignal::new(String::new());
+    let events = RwSignal::new(Vec::<EventType>::new());
+    // Outcome of the last save: a confirmation or why it failed
+    let status = RwSignal::new(None::<Result<String, String>>);
+
+    // The saved preferences fill in the form once they arrive
+    let prefs_resource = LocalResource::new(|| async move { fetch_email_prefs().await });
+    Effect::new(move |_| {
+        if let Some(Ok(prefs)) = prefs_resource.get() {
+            email.set(prefs.email.unwrap_or_default());
+            events.set(prefs.events);
+        }
+    });
+
+    let save = move |_| {
+        let prefs = EmailPrefs {
+            email: Some(email.get_untracked()),
+            events: events.get_untracked(),
+        };
+        leptos::task::spawn_local(async move {
+            status.set(Some(send_email_prefs(prefs).await.map(
+                |saved| match saved.email {
+                    Some(_) if !saved.events.is_empty() => "Saved".to_string(),
+                    _ => "Saved; no emails will be sent".to_string(),
+                },
+            )));
+        });
+    };
+
+    let checkboxes = EventType::ALL
+        .into_iter()
+        .map(|event| {
+            view! {
+                <label class="flex items-center gap-2">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || events.with(|events| events.contains(&event))
+                        on:change=move |ev| {
+                            let checked = event_target_checked(&ev);
+                            events.update(|events| {
+                                events.retain(|e| *e != event);
+                                if checked {
+                                    events.push(event);
+                                }
+                            });
+                        }
+                    />
+                    {event.title()}
+                </label>
+            }
+        })
+        .collect_view();
+
+    view! {
+        <div class="bg-amber-50 rounded-2xl shadow p-4 text-sm max-w-xl">
+            <label class="block font-semibold text-teal-800 mb-1" for="notification-email">
+                "Email address"
+            </label>
+            <input
+                id="notification-email"
+                type="email"
+                placeholder="you@example.com"
+                prop:value=email
+                on:input=move |ev| email.set(event_target_value(&ev))
+                class="w-full border border-amber-200 rounded-lg px-2 py-1"
+            />
+            <p class="mt-3 mb-1 font-semibold text-teal-800">"Send an email when"</p>
+            <div class="space-y-1">{checkboxes}</div>
+            <button
+                on:click=save
+                class="mt-3 bg-teal-700 hover:bg-teal-800 text-white py-1 px-4 rounded-lg shadow transition duration-300"
+            >
+                "Save"
+            </button>
+            {move || status.get().map(|result| match result {
+                Ok(message) => view! { <p class="mt-2 text-teal-700">{message}</p> }.into_any(),
+                Err(message) => view! { <p class="mt-2 text-red-600">{message}</p> }.into_any(),
+            })}
+        </div>
+    }
+    .into_any()
+}
+
+/// Header, navigation and frame shared by the pages of a signed-in user.
+/// Sends visitors without a session to the login page.
+#[component]
+fn PageFrame(children: Children) -> impl IntoView {
+    let navigate = use_navigate();
+
+    // Who is signed in; nobody means the API will refuse every read
+    let me_resource = LocalResource::new(|| async move { fetch_me().await });
+    Effect::new({
+        let navigate = navigate.clone();
+        move |_| {
+            if let Some(Ok(None)) = me_resource.get() {
+                navigate("/login", Default::default());
+            }
+        }
+    });
+
+    let on_logout = move |_| {
+        let navigate = navigate.clone();
+        leptos::task::spawn_local(async move {
+            if let Err(e) = send_logout().await {
+                log!("Error: {}", e);
+            }
+            navigate("/login", Default::default());
+        });
+    };
+
+    view! {
+        // Outer container: Soft, welcoming background and minimum screen height
+        <div class="min-h-screen bg-amber-50 p-4 font-sans antialiased">
+            // Main Card: Centered, rounded, shadowed container for the page content
+            <main class="max-w-4xl mx-auto bg-white rounded-3xl shadow-2xl p-6 md:p-10">
+
+                // Header: Large, bold, and themed
+                <header class="text-center mb-8">
+                    <h1 class="text-4xl font-extrabold text-teal-800 tracking-tight">
+                        <i class="fas fa-paw mr-3 text-amber-500"></i>
+                        "ESP32 Pet Tracker Dashboard"
+                    </h1>
+                    <p class="text-gray-600 mt-2">"GPS tracking for your furry companion."</p>
+                    <nav class="flex justify-center gap-6 mt-4 text-teal-700 font-semibold">
+                        <a href="/" class="hover:underline"><i class="fas fa-dog mr-1"></i>"Pets"</a>
+                        <a href="/settings" class="hover:underline"><i class="fas fa-cog mr-1"></i>"Settings"</a>
+                    </nav>
+                    {move || me_resource.get().and_then(Result::ok).flatten().map(|user| view! {
+                        <p class="mt-3 text-sm text-gray-500">
+                            <i class="fas fa-user mr-1"></i>
+                            {user.username}
+                            <button on:click=on_logout.clone() class="ml-3 text-teal-700 hover:underline">"Sign out"</button>
+                        </p>
+                    })}
+                </header>
+
+                {children()}
+
+                // Friendly Footer
+                <footer class="mt-8 pt-4 text-center text-sm text-gray-500 border-t border-amber-100">
+                    "Adventures tracked with love." <i class="fas fa-heart ml-1 text-red-400"></i>
+                </footer>
+            </main>
+        </div>
+    }
+}
+
+/// Stored points of one device (or all of them), newest first: filters, map, playback
+/// (for one device), the table and the CSV download. New points arrive live.
+/// `on_refresh` is run along with reloading the table, for the page's other data.
+#[component]
+fn TrackHistory(
+    #[prop(into)] device_id: Signal<Option<String>>,
+    #[prop(optional)] on_refresh: Option<Callback<()>>,
+) -> impl IntoView {
+    // Filters for the table; the newest points come first
+    let query = RwSignal::new(DataQuery {
+        device_id: device_id.get_untracked(),
+        order: SortOrder::Desc,
+        ..Default::default()
+    });
+    // Another device's page reuses this component
+    Effect::new(move |_| {
+        let device_id = device_id.get();
+        if query.with_untracked(|q| q.device_id != device_id) {
+            query.update(|q| q.device_id = device_id);
+        }
+    });
+
+    // Resource to hold the first page of data from the API, refetched whenever the filters change
+    let data_resource = LocalResource::new(move || {
+        let query = query.get();
+        async move { fetch_api_data(query).await }
+    });
+
+    // Tile server for the map, if the backend has one
+    let map_resource = LocalResource::new(|| async move { fetch_map_settings().await });
+    let map_settings =
+        Signal::derive(move || map_resource.get().and_then(Result::ok).unwrap_or_default());
+
+    // Rows pulled in by "Load more", and the cursor for the page after them
+    let extra_rows = RwSignal::new(Vec::<StoredData>::new());
+    let next_cursor = RwSignal::new(None::<String>);
+
+    // Points that arrived on the live feed since the table was loaded, newest first
+    let live = LiveSubscription::new();
+    Effect::new(move |_| live.follow(query.with(|q| q.device_id.clone())));
+    on_cleanup(move || live.stop());
+
+    // Everything currently shown in the table; this is also what the download button exports.
+    // Live points go on the newest end, unless the range ends in the past or there are
+    // older pages still to load in between.
+    let shown_rows = Memo::new(move |_| {
+        let mut rows = match data_resource.get() {
+            Some(Ok(page)) => page.data,
+            _ => Vec::new(),
+        };
+        rows.extend(extra_rows.get());
+        let (order, open_ended) = query.with(|q| (q.order, q.to.is_none()));
+        if open_ended {
+            let live_rows = live.rows.get();
+            match order {
+                SortOrder::Desc => {
+                    rows.splice(0..0, live_rows);
+                }
+                SortOrder::Asc if next_cursor.get().is_none() => {
+                    rows.extend(live_rows.into_iter().rev());
+                }
+                SortOrder::Asc => {}
+            }
+        }
+        rows
+    });
+
+    // Row picked in the table or on the map; indexes into `shown_rows`, so it is
+    // dropped whenever those change
+    let selected_row = RwSignal::new(None::<usize>);
+    Effect::new(move |_| {
+        shown_rows.track();
+        selected_row.set(None);
+    });
 
-    // When the resource loads, update the csv_data signal
+    // Where playback of the device's track has got to, for the map
+    let playback_cursor = RwSignal::new(None::<(f64, f64)>);
+
+    // When the resource loads, start paging again from its first page
     Effect::new(move |_| {
-        if let Some(Ok(data)) = data_resource.get() {
-            set_csv_data.set(data);
+        if let Some(Ok(page)) = data_resource.get() {
+            extra_rows.set(Vec::new());
+            live.rows.set(Vec::new());
+            next_cursor.set(page.next);
         } else {
             log!("Resource is still loading or None");
         }
@@ -104,7 +1460,7 @@ pub fn App() -> impl IntoView {
         if let Some(result) = data_resource.get() {
             log!("Resource state changed!");
             match result {
-                Ok(data) => log!("Fetched {} rows", data.len()),
+                Ok(page) => log!("Fetched {} rows", page.data.len()),
                 Err(e) => log!("Error: {:?}", e),
             }
         } else {
@@ -112,8 +1468,27 @@ pub fn App() -> impl IntoView {
         }
     });
 
+    let on_load_more = move |_| {
+        let Some(cursor) = next_cursor.get_untracked() else {
+            return;
+        };
+        let page_query = DataQuery {
+            cursor: Some(cursor),
+            ..query.get_untracked()
+        };
+        leptos::task::spawn_local(async move {
+            match fetch_api_data(page_query).await {
+                Ok(page) => {
+                    extra_rows.update(|rows| rows.extend(page.data));
+                    next_cursor.set(page.next);
+                }
+                Err(e) => log!("Error: {:?}", e),
+            }
+        });
+    };
+
     let on_download_click = move |_| {
-        let data_to_download = csv_data.get_untracked();
+        let data_to_download = shown_rows.get_untracked();
         if !data_to_download.is_empty() {
             trigger_csv_download(data_to_download);
         } else {
@@ -122,157 +1497,384 @@ pub fn App() -> impl IntoView {
     };
 
     view! {
-        <Stylesheet id="leptos" href="/pkg/buddy_app.css"/>
-        // Outer container: Soft, welcoming background and minimum screen height
-        <div class="min-h-screen bg-amber-50 p-4 font-sans antialiased">
-            // Main Card: Centered, rounded, shadowed container for the dashboard content
-            <main class="max-w-4xl mx-auto bg-white rounded-3xl shadow-2xl p-6 md:p-10">
+        // Action Buttons: Grouped, well-styled, and responsive
+        <div class="flex flex-wrap justify-center gap-4 mb-10 border-b pb-6 border-amber-200">
+            <button
+                on:click=move |_| {
+                    data_resource.refetch();
+                    if let Some(on_refresh) = on_refresh {
+                        on_refresh.run(());
+                    }
+                }
+                class="flex items-center space-x-2 bg-teal-600 hover:bg-teal-700 text-white font-semibold py-3 px-6 rounded-xl shadow-lg transition duration-300 transform hover:scale-[1.02] active:scale-[0.98] focus:outline-none focus:ring-4 focus:ring-teal-300"
+            >
+                <i class="fas fa-sync-alt"></i>
+                <span>"Refresh Data"</span>
+            </button>
+            <button
+                on:click=on_download_click
+                class="flex items-center space-x-2 bg-amber-500 hover:bg-amber-600 text-white font-semibold py-3 px-6 rounded-xl shadow-lg transition duration-300 transform hover:scale-[1.02] active:scale-[0.98] focus:outline-none focus:ring-4 focus:ring-amber-300"
+            >
+                <i class="fas fa-download"></i>
+                <span>"Download CSV"</span>
+            </button>
+        </div>
 
-                // Header: Large, bold, and themed
-                <header class="text-center mb-8">
-                    <h1 class="text-4xl font-extrabold text-teal-800 tracking-tight">
-                        <i class="fas fa-paw mr-3 text-amber-500"></i>
-                        "ESP32 Pet Tracker Dashboard"
-                    </h1>
-                    <p class="text-gray-600 mt-2">"GPS tracking for your furry companion."</p>
-                </header>
+        // Filter Bar: narrows the table down by time range
+        <div class="flex flex-wrap items-end gap-4 mb-8 text-sm text-gray-700">
+            <label class="flex flex-col">
+                "From"
+                <input
+                    type="datetime-local"
+                    on:change=move |ev| query.update(|q| q.from = parse_local_datetime(&event_target_value(&ev)))
+                    class="mt-1 border border-amber-200 rounded-lg px-3 py-2 focus:outline-none focus:ring-2 focus:ring-teal-300"
+                />
+            </label>
+            <label class="flex flex-col">
+                "To"
+                <input
+                    type="datetime-local"
+                    on:change=move |ev| query.update(|q| q.to = parse_local_datetime(&event_target_value(&ev)))
+                    class="mt-1 border border-amber-200 rounded-lg px-3 py-2 focus:outline-none focus:ring-2 focus:ring-teal-300"
+                />
+            </label>
+            <label class="flex flex-col">
+                "Order"
+                <select
+                    on:change=move |ev| {
+                        let order = if event_target_value(&ev) == "asc" { SortOrder::Asc } else { SortOrder::Desc };
+                        query.update(|q| q.order = order);
+                    }
+                    class="mt-1 border border-amber-200 rounded-lg px-3 py-2 focus:outline-none focus:ring-2 focus:ring-teal-300"
+                >
+                    <option value="desc" selected>"Newest first"</option>
+                    <option value="asc">"Oldest first"</option>
+                </select>
+            </label>
+        </div>
 
-                // Action Buttons: Grouped, well-styled, and responsive
-                <div class="flex flex-wrap justify-center gap-4 mb-10 border-b pb-6 border-amber-200">
-                    <button
-                        on:click=move |_| data_resource.refetch()
-                        class="flex items-center space-x-2 bg-teal-600 hover:bg-teal-700 text-white font-semibold py-3 px-6 rounded-xl shadow-lg transition duration-300 transform hover:scale-[1.02] active:scale-[0.98] focus:outline-none focus:ring-4 focus:ring-teal-300"
-                    >
-                        <i class="fas fa-sync-alt"></i>
-                        <span>"Refresh Data"</span>
-                    </button>
-                    <button
-                        on:click=on_download_click
-                        class="flex items-center space-x-2 bg-amber-500 hover:bg-amber-600 text-white font-semibold py-3 px-6 rounded-xl shadow-lg transition duration-300 transform hover:scale-[1.02] active:scale-[0.98] focus:outline-none focus:ring-4 focus:ring-amber-300"
-                    >
-                        <i class="fas fa-download"></i>
-                        <span>"Download CSV"</span>
-                    </button>
-                </div>
+        // Map: the tracks of the rows below; clicking a fix picks its row.
+        // For a single device, its track can be replayed under the map.
+        <Show when=move || !shown_rows.with(Vec::is_empty)>
+            <div class="mb-8">
+                <TrackMap rows=shown_rows selected=selected_row settings=map_settings cursor=playback_cursor/>
+                <Show when=move || device_id.with(Option::is_some)>
+                    <Playback rows=shown_rows selected=selected_row cursor=playback_cursor/>
+                </Show>
+            </div>
+        </Show>
 
-                // Data Section Header
-                <h2 class="text-2xl font-bold text-teal-800 mb-4 border-l-4 border-amber-400 pl-3">
-                    "Latest Refreshed Datas"
-                </h2>
-
-                // Data Display Area: Card-like container for the data table
-                <div class="overflow-x-auto rounded-xl shadow-lg ring-1 ring-gray-200">
-                    <Suspense fallback=move || view! {
-                        <p class="p-6 text-center text-gray-500 bg-gray-50 rounded-xl">"Fetching cuddly data..." <i class="fas fa-bone animate-pulse ml-2"></i></p>
-                    }>
-                        <ErrorBoundary
-                            fallback=|_| view! {
-                                <p class="p-6 text-center text-red-600 bg-red-50 rounded-xl">"Ruh-roh! Error loading data. Check the tracker connection." <i class="fas fa-exclamation-triangle ml-2"></i></p>
+        // Data Section Header
+        <h2 class="text-2xl font-bold text-teal-800 mb-4 border-l-4 border-amber-400 pl-3">
+            "Latest Refreshed Datas"
+        </h2>
+
+        // Data Display Area: Card-like container for the data table
+        <div class="overflow-x-auto rounded-xl shadow-lg ring-1 ring-gray-200">
+            <Suspense fallback=move || view! {
+                <p class="p-6 text-center text-gray-500 bg-gray-50 rounded-xl">"Fetching cuddly data..." <i class="fas fa-bone animate-pulse ml-2"></i></p>
+            }>
+                <ErrorBoundary
+                    fallback=|_| view! {
+                        <p class="p-6 text-center text-red-600 bg-red-50 rounded-xl">"Ruh-roh! Error loading data. Check the tracker connection." <i class="fas fa-exclamation-triangle ml-2"></i></p>
+                    }
+                >
+                    {
+                        move || data_resource.get().map(|data| match data {
+                            Ok(_) if shown_rows.get().is_empty() => {
+                                view! {
+                                    <p class="p-6 text-center text-gray-500">
+                                        "No sensor data received yet. Is the tracker awake?"
+                                    </p>
+                                }.into_any()
                             }
-                        >
-                            {
-                                move || data_resource.get().map(|data| match data {
-                                    Ok(data_vec) if data_vec.is_empty() => {
-                                        view! {
-                                            <p class="p-6 text-center text-gray-500">
-                                                "No sensor data received yet. Is the tracker awake?"
-                                            </p>
-                                        }.into_any()
-                                    }
-                                    Ok(data_vec) => {
-                                        view! {
-                                            <div class="rounded-md overflow-clip m-10 border dark:border-gray-700".to_string()>
-                                                <table class="text-sm text-left text-gray-500 dark:text-gray-400 mb-[-1px] w-[calc(100vw-5rem)]">
-                                                    <TableContent rows=data_vec scroll_container="html" />
-                                                </table>
-                                            </div>
-                                        }.into_any()
-                                    },
-                                    Err(_e) => {
-                                        view! {
-                                            <p class="p-6 text-center text-red-500">
-                                                {format!("Error: Failed to load data from the tracker.")}
-                                            </p>
-                                        }.into_any()
-                                    }
-                                })
+                            Ok(_) => {
+                                view! {
+                                    <div class="rounded-md overflow-clip m-10 border dark:border-gray-700".to_string()>
+                                        <table class="text-sm text-left text-gray-500 dark:text-gray-400 mb-[-1px] w-[calc(100vw-5rem)]">
+                                            <TableContent
+                                                rows=shown_rows.get()
+                                                scroll_container="html"
+                                                selection=Selection::Single(selected_row)
+                                                row_renderer=StoredDataRowRenderer
+                                            />
+                                        </table>
+                                    </div>
+                                    <Show when=move || next_cursor.get().is_some()>
+                                        <div class="flex justify-center mb-6">
+                                            <button
+                                                on:click=on_load_more
+                                                class="flex items-center space-x-2 bg-teal-600 hover:bg-teal-700 text-white font-semibold py-2 px-5 rounded-xl shadow transition duration-300 focus:outline-none focus:ring-4 focus:ring-teal-300"
+                                            >
+                                                <i class="fas fa-chevron-down"></i>
+                                                <span>"Load more"</span>
+                                            </button>
+                                        </div>
+                                    </Show>
+                                }.into_any()
+                            },
+                            Err(_e) => {
+                                view! {
+                                    <p class="p-6 text-center text-red-500">
+                                        "Error: Failed to load data from the tracker."
+                                    </p>
+                                }.into_any()
                             }
-                        </ErrorBoundary>
-                    </Suspense>
-                </div>
+                        })
+                    }
+                </ErrorBoundary>
+            </Suspense>
+        </div>
+    }
+    .into_any()
+}
 
-                // Friendly Footer
-                <footer class="mt-8 pt-4 text-center text-sm text-gray-500 border-t border-amber-100">
-                    "Adventures tracked with love." <i class="fas fa-heart ml-1 text-red-400"></i>
-                </footer>
+/// The overview at `/`: a card per pet, linking to its page, and the points of all of them.
+#[component]
+fn Overview() -> impl IntoView {
+    // Where each pet is right now, for the summary cards
+    let latest_resource = LocalResource::new(|| async move { fetch_latest().await });
+    let fixes = Signal::derive(move || {
+        latest_resource
+            .get()
+            .and_then(Result::ok)
+            .unwrap_or_default()
+    });
+    let all_devices = Signal::stored(None::<String>);
+
+    view! {
+        <Title text="Buddy"/>
+        <PageFrame>
+            // Pet Cards: where every pet is right now, at a glance
+            <div class="grid grid-cols-1 sm:grid-cols-2 lg:grid-cols-3 gap-4 mb-10">
+                {move || {
+                    fixes
+                        .get()
+                        .into_iter()
+                        .map(|fix| {
+                            let href = format!("/devices/{}", fix.device_id);
+                            view! {
+                                <a href=href class="block">
+                                    <PetCard fix=fix/>
+                                </a>
+                            }
+                        })
+                        .collect_view()
+                }}
+            </div>
+
+            <AlertList device_id=all_devices fixes=fixes/>
+
+            <TrackHistory device_id=all_devices on_refresh=Callback::new(move |_| latest_resource.refetch())/>
+        </PageFrame>
+    }
+}
+
+/// One pet's page at `/devices/:id`: its card, a share link, its battery and its track.
+#[component]
+fn DevicePage() -> impl IntoView {
+    let params = use_params_map();
+    let device_id = Memo::new(move |_| params.with(|p| p.get("id")));
+
+    // The pet's card; the device isn't in the list if it doesn't exist or isn't ours
+    let latest_resource = LocalResource::new(|| async move { fetch_latest().await });
+    let fix = Memo::new(move |_| {
+        let id = device_id.get()?;
+        latest_resource
+            .get()
+            .and_then(Result::ok)?
+            .into_iter()
+            .find(|fix| fix.device_id == id)
+    });
+
+    let fixes = Signal::derive(move || {
+        latest_resource
+            .get()
+            .and_then(Result::ok)
+            .unwrap_or_default()
+    });
+
+    // The battery chart covers the last few days
+    let battery_resource = LocalResource::new(move || {
+        let query = DataQuery {
+            device_id: device_id.get(),
+            from: Some(Utc::now() - chrono::Duration::days(BATTERY_CHART_DAYS)),
+            limit: Some(crate::gps_data::MAX_PAGE_SIZE),
+            ..Default::default()
+        };
+        async move { fetch_api_data(query).await }
+    });
+    let battery_rows = Signal::derive(move || {
+        battery_resource
+            .get()
+            .and_then(Result::ok)
+            .map(|page| page.data)
+            .unwrap_or_default()
+    });
+
+    // The last share link created for this device, or why it couldn't be
+    let share_link = RwSignal::new(None::<Result<String, String>>);
+    Effect::new(move |_| {
+        device_id.track();
+        share_link.set(None);
+    });
+    let on_share_click = move |_| {
+        let Some(device_id) = device_id.get_untracked() else {
+            return;
+        };
+        leptos::task::spawn_local(async move {
+            share_link.set(Some(send_share(&device_id).await));
+        });
+    };
+
+    view! {
+        <Title text=move || fix.get().map_or("Buddy".to_string(), |fix| format!("{} - Buddy", fix.label()))/>
+        <PageFrame>
+            {move || match (latest_resource.get(), fix.get()) {
+                (Some(_), Some(fix)) => view! {
+                    <div class="max-w-sm mx-auto mb-6">
+                        <PetCard fix=fix/>
+                    </div>
+                }.into_any(),
+                (Some(_), None) => view! {
+                    <p class="p-6 mb-6 text-center text-red-600 bg-red-50 rounded-xl">"This tracker doesn't exist or isn't yours."</p>
+                }.into_any(),
+                (None, _) => ().into_any(),
+            }}
+
+            // Share: a public link to this pet, for when it has gone missing
+            <div class="flex flex-wrap items-center gap-3 mb-6 text-sm">
+                <button
+                    on:click=on_share_click
+                    class="flex items-center space-x-2 bg-red-500 hover:bg-red-600 text-white font-semibold py-2 px-4 rounded-xl shadow transition duration-300 focus:outline-none focus:ring-4 focus:ring-red-300"
+                >
+                    <i class="fas fa-share-alt"></i>
+                    <span>"Share live location"</span>
+                </button>
+                {move || share_link.get().map(|result| match result {
+                    Ok(link) => view! {
+                        <input type="text" readonly value=link class="flex-1 border border-amber-200 rounded-lg px-2 py-1 text-xs"/>
+                    }.into_any(),
+                    Err(message) => view! { <span class="text-red-600">{message}</span> }.into_any(),
+                })}
+            </div>
+
+            <AlertList device_id=device_id fixes=fixes/>
+
+            <GeofenceList device_id=device_id/>
+
+            // Battery: how the charge went down over the last days
+            <h2 class="text-2xl font-bold text-teal-800 mb-4 border-l-4 border-amber-400 pl-3">
+                "Battery"
+            </h2>
+            <div class="mb-10">
+                <Show
+                    when=move || battery_rows.with(|rows| !rows.is_empty())
+                    fallback=|| view! { <p class="p-6 text-center text-gray-500">"No battery readings in the last days."</p> }
+                >
+                    <BatteryChart rows=battery_rows forecast=Signal::derive(move || fix.get().and_then(|fix| fix.battery_forecast))/>
+                </Show>
+            </div>
+
+            <TrackHistory
+                device_id=device_id
+                on_refresh=Callback::new(move |_| {
+                    latest_resource.refetch();
+                    battery_resource.refetch();
+                })
+            />
+        </PageFrame>
+    }
+}
+
+/// Settings at `/settings`: the user's households with their invitation links,
+/// and the trackers registered to them.
+#[component]
+fn SettingsPage() -> impl IntoView {
+    // Households whose devices are shown, and the user's role in each
+    let households_resource = LocalResource::new(|| async move { fetch_households().await });
+
+    // Registered devices, each linking to its page
+    let devices_resource = LocalResource::new(|| async move { fetch_devices().await });
+
+    view! {
+        <Title text="Settings - Buddy"/>
+        <PageFrame>
+            // Households: whose pets these are, with invitation links for owners
+            <h2 class="text-2xl font-bold text-teal-800 mb-4 border-l-4 border-amber-400 pl-3">
+                "Households"
+            </h2>
+            <div class="grid grid-cols-1 sm:grid-cols-2 gap-4 mb-10">
+                {move || {
+                    households_resource
+                        .get()
+                        .and_then(Result::ok)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|membership| view! { <HouseholdCard membership=membership/> })
+                        .collect_view()
+                }}
+            </div>
+
+            // Notifications: which events email the current user, and where to
+            <h2 class="text-2xl font-bold text-teal-800 mb-4 border-l-4 border-amber-400 pl-3">
+                "Email Notifications"
+            </h2>
+            <div class="mb-10">
+                <EmailSettings/>
+            </div>
+
+            // Device List: one entry per registered tracker
+            <h2 class="text-2xl font-bold text-teal-800 mb-4 border-l-4 border-amber-400 pl-3">
+                "Trackers"
+            </h2>
+            <div class="flex flex-wrap gap-3 mb-6">
+                {move || {
+                    devices_resource
+                        .get()
+                        .and_then(Result::ok)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|device| view! {
+                            <a
+                                href=format!("/devices/{}", device.id)
+                                class="flex items-center bg-amber-100 hover:bg-amber-200 text-teal-800 py-2 px-4 rounded-xl shadow transition duration-300"
+                                title=device.id.clone()
+                            >
+                                {device.photo_url.clone().map(|url| view! {
+                                    <img src=url alt="" class="w-6 h-6 rounded-full object-cover mr-2"/>
+                                })}
+                                <span class="font-semibold">{device.label().to_string()}</span>
+                                <span class="ml-2 text-xs opacity-75">{device.display_name.clone()}</span>
+                            </a>
+                        })
+                        .collect_view()
+                }}
+            </div>
+        </PageFrame>
+    }
+}
+
+/// 404 - Not Found
+#[component]
+fn NotFound() -> impl IntoView {
+    // Set the HTTP status code. This only works during the initial server-side render;
+    // navigating here within the app doesn't make a new request to the server.
+    #[cfg(feature = "ssr")]
+    {
+        let response = expect_context::<leptos_actix::ResponseOptions>();
+        response.set_status(actix_web::http::StatusCode::NOT_FOUND);
+    }
+
+    view! {
+        <Title text="Not Found - Buddy"/>
+        <div class="min-h-screen bg-amber-50 p-4 font-sans antialiased">
+            <main class="max-w-md mx-auto mt-16 bg-white rounded-3xl shadow-2xl p-8 text-center">
+                <i class="fas fa-bone text-5xl text-amber-400"></i>
+                <h1 class="mt-4 text-3xl font-extrabold text-teal-800">"Not Found"</h1>
+                <p class="mt-2 text-gray-600">"Nothing buried here. The page you're sniffing for doesn't exist."</p>
+                <a href="/" class="inline-block mt-6 text-teal-700 font-semibold hover:underline">"Back to the pets"</a>
             </main>
         </div>
     }
 }
-
-// use leptos::prelude::*;
-// use leptos_meta::{provide_meta_context, Stylesheet, Title};
-// use leptos_router::{
-//     components::{Route, Router, Routes},
-//     StaticSegment, WildcardSegment,
-// };
-
-// #[component]
-// pub fn App() -> impl IntoView {
-//     // Provides context that manages stylesheets, titles, meta tags, etc.
-//     provide_meta_context();
-
-//     view! {
-//         // injects a stylesheet into the document <head>
-//         // id=leptos means cargo-leptos will hot-reload this stylesheet
-//         <Stylesheet id="leptos" href="/pkg/buddy.css"/>
-
-//         // sets the document title
-//         <Title text="Welcome to Leptos"/>
-
-//         // content for this welcome page
-//         <Router>
-//             <main>
-//                 <Routes fallback=move || "Not found.">
-//                     <Route path=StaticSegment("") view=HomePage/>
-//                     <Route path=WildcardSegment("any") view=NotFound/>
-//                 </Routes>
-//             </main>
-//         </Router>
-//     }
-// }
-
-// /// Renders the home page of your application.
-// #[component]
-// fn HomePage() -> impl IntoView {
-//     // Creates a reactive value to update the button
-//     let count = RwSignal::new(0);
-//     let on_click = move |_| *count.write() += 1;
-
-//     view! {
-//         <h1>"Welcome to Leptos!"</h1>
-//         <button on:click=on_click>"Click Me: " {count}</button>
-//     }
-// }
-
-// /// 404 - Not Found
-// #[component]
-// fn NotFound() -> impl IntoView {
-//     // set an HTTP status code 404
-//     // this is feature gated because it can only be done during
-//     // initial server-side rendering
-//     // if you navigate to the 404 page subsequently, the status
-//     // code will not be set because there is not a new HTTP request
-//     // to the server
-//     #[cfg(feature = "ssr")]
-//     {
-//         // this can be done inline because it's synchronous
-//         // if it were async, we'd use a server function
-//         let resp = expect_context::<leptos_actix::ResponseOptions>();
-//         resp.set_status(actix_web::http::StatusCode::NOT_FOUND);
-//     }
-
-//     view! {
-//         <h1>"Not Found"</h1>
-//     }
-// }
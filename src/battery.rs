@@ -0,0 +1,428 @@
+use crate::gps_data::StoredData;
+use chrono::{DateTime, Local, Utc};
+use leptos::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssr")]
+use crate::device::ReportingSchedule;
+#[cfg(feature = "ssr")]
+use crate::gps_data::DeviceTimeZone;
+
+// --- Depletion Forecast ---
+//
+// The tracker spends its nights (outside START_HOUR..END_HOUR) in deep sleep, where it
+// draws next to nothing, so its battery goes down in steps: steadily through the day,
+// hardly at all overnight. The forecast therefore measures time in hours awake. It fits a
+// straight line through the levels reported since the last charge against the awake hours
+// between them, and walks that line forward through the coming awake windows to zero.
+
+/// Days of readings the forecast looks back over.
+pub const FORECAST_HISTORY_DAYS: i64 = 7;
+/// A rise of at least this many points means the battery was charged; only readings
+/// from after the last charge are used.
+#[cfg(feature = "ssr")]
+const CHARGE_JUMP: u8 = 5;
+/// Fewest readings, and shortest awake time they must span, to make a forecast from.
+#[cfg(feature = "ssr")]
+const MIN_READINGS: usize = 3;
+#[cfg(feature = "ssr")]
+const MIN_AWAKE_HOURS: f64 = 1.0;
+/// No forecast is given further ahead than this.
+#[cfg(feature = "ssr")]
+const MAX_FORECAST_DAYS: i64 = 60;
+/// Time is cut into slices of this length to tell awake from asleep.
+#[cfg(feature = "ssr")]
+const SLICE_SECS: i64 = 15 * 60;
+
+/// When a device's battery is expected to run out, as included in `GET /api/devices/latest`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BatteryForecast {
+    /// Percentage points used per hour the tracker is awake.
+    pub drain_per_awake_hour: f64,
+    /// Level the fitted line gives at the latest reading, in percent.
+    pub level: f64,
+    /// When the level is expected to reach zero.
+    pub empty_at: DateTime<Utc>,
+    /// How many readings the forecast was fitted to.
+    pub readings: usize,
+}
+
+/// Whether the tracker is awake during the slice starting at `at`.
+#[cfg(feature = "ssr")]
+fn is_awake(at: DateTime<Utc>, schedule: ReportingSchedule, tz: DeviceTimeZone) -> bool {
+    let middle = at + chrono::Duration::seconds(SLICE_SECS / 2);
+    (schedule.start_hour as u32..=schedule.end_hour as u32).contains(&tz.local_hour(middle))
+}
+
+/// Hours the tracker spends awake between `from` and `to`.
+#[cfg(feature = "ssr")]
+pub fn awake_hours(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    schedule: ReportingSchedule,
+    tz: DeviceTimeZone,
+) -> f64 {
+    let mut awake_secs = 0;
+    let mut at = from;
+    while at < to {
+        // Slices start on whole quarter hours (UTC), which zone offsets also fall on
+        let slice_start = at.timestamp() - at.timestamp().rem_euclid(SLICE_SECS);
+        let next = DateTime::from_timestamp(slice_start + SLICE_SECS, 0)
+            .unwrap_or(to)
+            .min(to);
+        if is_awake(
+            DateTime::from_timestamp(slice_start, 0).unwrap_or(at),
+            schedule,
+            tz,
+        ) {
+            awake_secs += (next - at).num_seconds();
+        }
+        at = next;
+    }
+    awake_secs as f64 / 3600.0
+}
+
+/// The time `hours` of awake time after `from`, if that is within `MAX_FORECAST_DAYS`.
+#[cfg(feature = "ssr")]
+fn after_awake_hours(
+    from: DateTime<Utc>,
+    hours: f64,
+    schedule: ReportingSchedule,
+    tz: DeviceTimeZone,
+) -> Option<DateTime<Utc>> {
+    let limit = from + chrono::Duration::days(MAX_FORECAST_DAYS);
+    let mut remaining_secs = hours * 3600.0;
+    let mut at = from;
+    while at < limit {
+        let slice_start = at.timestamp() - at.timestamp().rem_euclid(SLICE_SECS);
+        let next = DateTime::from_timestamp(slice_start + SLICE_SECS, 0)?;
+        if is_awake(DateTime::from_timestamp(slice_start, 0)?, schedule, tz) {
+            let slice_secs = (next - at).num_seconds() as f64;
+            if remaining_secs <= slice_secs {
+                return Some(at + chrono::Duration::milliseconds((remaining_secs * 1000.0) as i64));
+            }
+            remaining_secs -= slice_secs;
+        }
+        at = next;
+    }
+    None
+}
+
+/// Forecasts when the battery runs out from `readings` (time, percent) of one device,
+/// whose clock runs in `tz`. `None` when there are too few readings since the last charge,
+/// or the level isn't going down.
+#[cfg(feature = "ssr")]
+pub fn forecast(
+    readings: &[(DateTime<Utc>, u8)],
+    schedule: ReportingSchedule,
+    tz: DeviceTimeZone,
+) -> Option<BatteryForecast> {
+    let mut readings = readings.to_vec();
+    readings.sort_by_key(|(at, _)| *at);
+    let since_charge = readings
+        .windows(2)
+        .rposition(|pair| pair[1].1 >= pair[0].1.saturating_add(CHARGE_JUMP))
+        .map_or(0, |i| i + 1);
+    let readings = &readings[since_charge..];
+    if readings.len() < MIN_READINGS {
+        return None;
+    }
+
+    // Least squares fit of level against awake hours since the first reading
+    let first = readings[0].0;
+    let points: Vec<(f64, f64)> = readings
+        .iter()
+        .map(|(at, level)| (awake_hours(first, *at, schedule, tz), *level as f64))
+        .collect();
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let spread: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    if points[points.len() - 1].0 < MIN_AWAKE_HOURS || spread == 0.0 {
+        return None;
+    }
+    let slope = points
+        .iter()
+        .map(|p| (p.0 - mean_x) * (p.1 - mean_y))
+        .sum::<f64>()
+        / spread;
+    if slope >= 0.0 {
+        return None;
+    }
+
+    let (last_at, _) = readings[readings.len() - 1];
+    let level = (mean_y + slope * (points[points.len() - 1].0 - mean_x)).clamp(0.0, 100.0);
+    let drain = -slope;
+    Some(BatteryForecast {
+        drain_per_awake_hour: drain,
+        level,
+        empty_at: after_awake_hours(last_at, level / drain, schedule, tz)?,
+        readings: readings.len(),
+    })
+}
+
+// --- Battery Chart ---
+//
+// The battery level a device reported with each fix, plotted over time as SVG,
+// with the forecast drawn on to the expected empty time.
+
+/// Size of the chart's SVG view box, and the margins left for its axis labels.
+const CHART_WIDTH: f64 = 800.0;
+const CHART_HEIGHT: f64 = 220.0;
+const CHART_LEFT: f64 = 40.0;
+const CHART_RIGHT: f64 = 10.0;
+const CHART_TOP: f64 = 10.0;
+const CHART_BOTTOM: f64 = 24.0;
+
+/// Time axis of the chart, from `start` to `end`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TimeAxis {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+impl TimeAxis {
+    fn x(&self, at: DateTime<Utc>) -> f64 {
+        let span = (self.end - self.start).num_seconds().max(1) as f64;
+        CHART_LEFT
+            + (at - self.start).num_seconds() as f64 / span
+                * (CHART_WIDTH - CHART_LEFT - CHART_RIGHT)
+    }
+}
+
+/// Height in the view box of a battery level in percent.
+fn level_y(percent: f64) -> f64 {
+    CHART_TOP
+        + (100.0 - percent.clamp(0.0, 100.0)) / 100.0 * (CHART_HEIGHT - CHART_TOP - CHART_BOTTOM)
+}
+
+/// Battery level of one device over time, from the points in `rows`. A `forecast` is
+/// drawn as a dashed line from its fitted level down to zero at its `empty_at`.
+#[component]
+pub fn BatteryChart(
+    #[prop(into)] rows: Signal<Vec<StoredData>>,
+    #[prop(optional, into)] forecast: MaybeProp<BatteryForecast>,
+) -> impl IntoView {
+    // (time, level) oldest first
+    let levels = Memo::new(move |_| {
+        let mut levels: Vec<(DateTime<Utc>, f64)> = rows.with(|rows| {
+            rows.iter()
+                .map(|row| (row.timestamp, row.battery as f64))
+                .collect()
+        });
+        levels.sort_by_key(|(at, _)| *at);
+        levels
+    });
+    let axis = Memo::new(move |_| {
+        levels.with(|levels| {
+            let last = levels.last()?.0;
+            Some(TimeAxis {
+                start: levels.first()?.0,
+                end: forecast
+                    .with(|f| f.as_ref().map(|f| f.empty_at))
+                    .map_or(last, |empty_at| empty_at.max(last)),
+            })
+        })
+    });
+
+    let grid = [0.0, 25.0, 50.0, 75.0, 100.0]
+        .into_iter()
+        .map(|percent| {
+            let y = level_y(percent);
+            view! {
+                <line x1=CHART_LEFT y1=y x2=CHART_WIDTH - CHART_RIGHT y2=y stroke="#e5e7eb" stroke-width="1"/>
+                <text x=CHART_LEFT - 6.0 y=y + 4.0 font-size="11" text-anchor="end" fill="#6b7280">
+                    {format!("{}%", percent)}
+                </text>
+            }
+        })
+        .collect_view();
+
+    let time_labels = move || {
+        axis.get().map(|axis| {
+            [axis.start, axis.end]
+                .into_iter()
+                .enumerate()
+                .map(|(n, at)| {
+                    view! {
+                        <text
+                            x=axis.x(at)
+                            y=CHART_HEIGHT - 6.0
+                            font-size="11"
+                            text-anchor=if n == 0 { "start" } else { "end" }
+                            fill="#6b7280"
+                        >
+                            {at.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string()}
+                        </text>
+                    }
+                })
+                .collect_view()
+        })
+    };
+
+    let history = move || {
+        let axis = axis.get()?;
+        let points = levels.with(|levels| {
+            levels
+                .iter()
+                .map(|(at, level)| format!("{:.1},{:.1}", axis.x(*at), level_y(*level)))
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+        Some(view! {
+            <polyline points=points fill="none" stroke="#0f766e" stroke-width="2" stroke-linejoin="round"/>
+        })
+    };
+
+    let projection = move || {
+        let axis = axis.get()?;
+        let forecast = forecast.get()?;
+        let (last, _) = levels.with(|levels| levels.last().copied())?;
+        Some(view! {
+            <line
+                x1=axis.x(last)
+                y1=level_y(forecast.level)
+                x2=axis.x(forecast.empty_at)
+                y2=level_y(0.0)
+                stroke="#dc2626"
+                stroke-width="2"
+                stroke-dasharray="6 4"
+            />
+        })
+    };
+
+    view! {
+        <div class="rounded-xl shadow-lg ring-1 ring-gray-200 bg-white p-2">
+            <svg
+                viewBox=format!("0 0 {} {}", CHART_WIDTH, CHART_HEIGHT)
+                class="w-full h-auto block"
+                role="img"
+                aria-label="Battery level over time"
+            >
+                {grid}
+                {time_labels}
+                {history}
+                {projection}
+            </svg>
+            {move || forecast.get().map(|forecast| view! {
+                <p class="px-2 pt-1 text-sm text-gray-600">
+                    <i class="fas fa-hourglass-half mr-1 text-red-500"></i>
+                    {format!(
+                        "Empty around {} at {:.1} % per awake hour ({} readings since the last charge)",
+                        forecast.empty_at.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                        forecast.drain_per_awake_hour,
+                        forecast.readings,
+                    )}
+                </p>
+            })}
+        </div>
+    }
+    .into_any()
+}
+
+// --- Tests ---
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    /// Awake 08:00 to 19:59, twelve hours a day.
+    const DAYTIME: ReportingSchedule = ReportingSchedule {
+        start_hour: 8,
+        end_hour: 19,
+        interval_secs: 3600,
+    };
+
+    fn utc() -> DeviceTimeZone {
+        "+00:00".parse().unwrap()
+    }
+
+    /// `hour` o'clock, UTC, `day` days after 2025-11-01.
+    fn at(day: i64, hour: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_761_955_200 + day * 86400 + hour * 3600, 0).unwrap()
+    }
+
+    fn hourly(day: i64, first_hour: i64, levels: &[u8]) -> Vec<(DateTime<Utc>, u8)> {
+        levels
+            .iter()
+            .enumerate()
+            .map(|(i, level)| (at(day, first_hour + i as i64), *level))
+            .collect()
+    }
+
+    #[test]
+    fn only_the_hours_awake_count() {
+        assert_eq!(awake_hours(at(0, 0), at(1, 0), DAYTIME, utc()), 12.0);
+        assert_eq!(awake_hours(at(0, 18), at(1, 10), DAYTIME, utc()), 4.0);
+        assert_eq!(
+            after_awake_hours(at(0, 19), 2.0, DAYTIME, utc()),
+            Some(at(1, 9))
+        );
+    }
+
+    #[test]
+    fn a_steady_drain_is_walked_forward_through_the_awake_windows() {
+        let forecast = forecast(&hourly(0, 8, &[100, 99, 98, 97, 96]), DAYTIME, utc()).unwrap();
+        assert!(
+            (forecast.drain_per_awake_hour - 1.0).abs() < 1e-9,
+            "{forecast:?}"
+        );
+        assert!((forecast.level - 96.0).abs() < 1e-9, "{forecast:?}");
+        assert_eq!(forecast.readings, 5);
+        // 8 more hours today, then 7 full days of 12, then 4 hours on the 8th
+        assert_eq!(forecast.empty_at, at(8, 12));
+    }
+
+    #[test]
+    fn the_night_asleep_does_not_count_as_drain() {
+        let mut readings = hourly(0, 18, &[50, 49]);
+        readings.extend(hourly(1, 8, &[48, 47]));
+        let forecast = forecast(&readings, DAYTIME, utc()).unwrap();
+        assert!(
+            (forecast.drain_per_awake_hour - 1.0).abs() < 1e-9,
+            "{forecast:?}"
+        );
+    }
+
+    #[test]
+    fn only_readings_since_the_last_charge_are_used() {
+        let mut readings = hourly(0, 8, &[30, 29, 28]);
+        readings.extend(hourly(0, 11, &[90, 89, 88, 87]));
+        let forecast = forecast(&readings, DAYTIME, utc()).unwrap();
+        assert_eq!(forecast.readings, 4);
+        assert!((forecast.level - 87.0).abs() < 1e-9, "{forecast:?}");
+
+        // Only two readings left after the charge
+        readings.truncate(5);
+        assert_eq!(super::forecast(&readings, DAYTIME, utc()), None);
+    }
+
+    #[test]
+    fn no_forecast_without_enough_of_a_falling_level() {
+        for readings in [
+            hourly(0, 8, &[90, 89]),
+            vec![
+                (at(0, 8), 90),
+                (at(0, 8) + chrono::Duration::minutes(20), 89),
+                (at(0, 8) + chrono::Duration::minutes(40), 88),
+            ],
+            hourly(0, 8, &[80, 80, 80, 80]),
+            hourly(0, 8, &[80, 81, 82, 83]),
+        ] {
+            assert_eq!(forecast(&readings, DAYTIME, utc()), None, "{readings:?}");
+        }
+    }
+
+    #[test]
+    fn nothing_is_forecast_beyond_sixty_days() {
+        assert!(after_awake_hours(at(0, 8), 59.0 * 12.0, DAYTIME, utc()).is_some());
+        assert_eq!(
+            after_awake_hours(at(0, 8), 61.0 * 12.0, DAYTIME, utc()),
+            None
+        );
+
+        let mut levels = [100; 12];
+        levels[11] = 99;
+        assert_eq!(forecast(&hourly(0, 8, &levels), DAYTIME, utc()), None);
+    }
+}
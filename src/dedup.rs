@@ -0,0 +1,233 @@
+use crate::gps_data::StoredData;
+use crate::storage::{DataStore, StorageError};
+use chrono::{DateTime, Utc};
+
+// --- Retry Detection ---
+//
+// The firmware retries a post on its next poll when the previous attempt failed, even if
+// the server had already stored it and only the response was lost. Posts that carry a
+// `sequence` number or an `idempotency_key` are checked against what the same device
+// sent within the dedup window:
+//
+//   * same sequence number or idempotency key,
+//     and the same fix                        -> duplicate, answered with the stored record
+//   * sequence number more than SEQUENCE_WINDOW
+//     below the highest seen, and a fix no
+//     newer than that one's                    -> stale replay, refused
+//
+// Any other number is new, even if it is lower than one already stored: a device that was
+// offline sends a live fix first and uploads what it buffered after, out of order.
+// A device whose counter resets (e.g. after losing power) starts again from a low number
+// with fixes newer than anything it sent before, so those are stored too, and a reused
+// number only makes a duplicate when the fix is the same. Posts without either are
+// always stored.
+
+/// How far below the highest sequence number seen a post's number may be and still be
+/// taken for a fix the device buffered, rather than a stale replay.
+pub const SEQUENCE_WINDOW: u32 = 4096;
+
+/// Why a post wasn't stored.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Replay {
+    /// The same post was stored before; this is the stored record.
+    Duplicate(Box<StoredData>),
+    /// The sequence number is more than `SEQUENCE_WINDOW` below one the device already
+    /// sent within the window.
+    Stale { sequence: u32, latest: u32 },
+}
+
+/// Whether `data` and `earlier` are the same fix: same device timestamp and raw position.
+fn same_fix(data: &StoredData, earlier: &StoredData) -> bool {
+    data.timestamp == earlier.timestamp
+        && data.longitude == earlier.longitude
+        && data.latitude == earlier.latitude
+}
+
+/// Whether `data`, numbered `sequence`, is too far behind `latest` to be a fix the device
+/// buffered. A fix newer than `latest`'s is from a counter that started over.
+fn is_stale(data: &StoredData, sequence: u32, latest: &StoredData) -> bool {
+    latest
+        .sequence
+        .is_some_and(|latest| latest.saturating_sub(sequence) > SEQUENCE_WINDOW)
+        && data.timestamp <= latest.timestamp
+}
+
+/// Checks `data` against the points its device sent since `window_start`.
+pub fn check(
+    store: &dyn DataStore,
+    data: &StoredData,
+    window_start: DateTime<Utc>,
+) -> Result<Option<Replay>, StorageError> {
+    if data.sequence.is_none() && data.idempotency_key.is_none() {
+        return Ok(None);
+    }
+
+    if let Some(existing) = store.find_duplicate(data, window_start)? {
+        return Ok(Some(Replay::Duplicate(Box::new(existing))));
+    }
+
+    if let Some(sequence) = data.sequence
+        && let Some(latest) = store.highest_sequence(&data.id, window_start)?
+        && is_stale(data, sequence, &latest)
+    {
+        return Ok(Some(Replay::Stale {
+            sequence,
+            latest: latest.sequence.unwrap_or_default(),
+        }));
+    }
+
+    Ok(None)
+}
+
+/// Checks every point of a batch, in order, against the store and against the points
+/// before it in the same batch. Returns one verdict per point.
+pub fn check_batch(
+    store: &dyn DataStore,
+    batch: &[StoredData],
+    window_start: DateTime<Utc>,
+) -> Result<Vec<Option<Replay>>, StorageError> {
+    let mut accepted: Vec<&StoredData> = Vec::new();
+    let mut verdicts = Vec::with_capacity(batch.len());
+
+    for data in batch {
+        let earlier = accepted.iter().filter(|earlier| earlier.id == data.id);
+        let verdict = if let Some(original) = earlier.clone().find(|earlier| {
+            ((data.sequence.is_some() && earlier.sequence == data.sequence)
+                || (data.idempotency_key.is_some()
+                    && earlier.idempotency_key == data.idempotency_key))
+                && same_fix(data, earlier)
+        }) {
+            Some(Replay::Duplicate(Box::new((*original).clone())))
+        } else if let Some(sequence) = data.sequence
+            && let Some(latest) = earlier
+                .filter(|earlier| earlier.sequence.is_some())
+                .max_by_key(|earlier| earlier.sequence)
+            && is_stale(data, sequence, latest)
+        {
+            Some(Replay::Stale {
+                sequence,
+                latest: latest.sequence.unwrap_or_default(),
+            })
+        } else {
+            check(store, data, window_start)?
+        };
+
+        if verdict.is_none() {
+            accepted.push(data);
+        }
+        verdicts.push(verdict);
+    }
+    Ok(verdicts)
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStore;
+    use crate::storage::tests::{at, insert, point};
+
+    fn numbered(sequence: u32, minutes: i64) -> StoredData {
+        StoredData {
+            sequence: Some(sequence),
+            ..point("A", minutes)
+        }
+    }
+
+    #[test]
+    fn a_buffer_uploaded_after_a_live_fix_is_accepted() {
+        let store = MemoryStore::new();
+        insert(&store, &[numbered(10, 60)]);
+
+        let buffered: Vec<StoredData> = (1..10).map(|n| numbered(n, n as i64)).collect();
+        let verdicts = check_batch(&store, &buffered, at(0)).unwrap();
+        assert!(verdicts.iter().all(Option::is_none), "{verdicts:?}");
+    }
+
+    #[test]
+    fn numbers_already_seen_are_duplicates() {
+        let store = MemoryStore::new();
+        insert(&store, &[numbered(10, 60), numbered(4, 4)]);
+
+        let retry = StoredData {
+            received_at: at(70),
+            ..numbered(4, 4)
+        };
+        assert_eq!(
+            check(&store, &retry, at(0)).unwrap(),
+            Some(Replay::Duplicate(Box::new(numbered(4, 4))))
+        );
+        let verdicts = check_batch(&store, &[numbered(5, 5), numbered(5, 5)], at(0)).unwrap();
+        assert_eq!(verdicts[0], None);
+        assert_eq!(
+            verdicts[1],
+            Some(Replay::Duplicate(Box::new(numbered(5, 5))))
+        );
+        // Outside the dedup window the number is free again
+        assert_eq!(check(&store, &retry, at(30)).unwrap(), None);
+    }
+
+    #[test]
+    fn numbers_far_behind_the_newest_are_stale() {
+        let store = MemoryStore::new();
+        let latest = SEQUENCE_WINDOW + 100;
+        insert(&store, &[numbered(latest, 60)]);
+
+        assert_eq!(check(&store, &numbered(100, 1), at(0)).unwrap(), None);
+        assert_eq!(
+            check(&store, &numbered(99, 1), at(0)).unwrap(),
+            Some(Replay::Stale {
+                sequence: 99,
+                latest
+            })
+        );
+        let verdicts = check_batch(
+            &MemoryStore::new(),
+            &[numbered(latest, 2), numbered(1, 1)],
+            at(0),
+        )
+        .unwrap();
+        assert_eq!(
+            verdicts[1],
+            Some(Replay::Stale {
+                sequence: 1,
+                latest
+            })
+        );
+    }
+
+    #[test]
+    fn a_counter_that_starts_over_keeps_its_new_fixes() {
+        let store = MemoryStore::new();
+        let before_reset = SEQUENCE_WINDOW + 1000;
+        insert(&store, &[numbered(4, 4), numbered(before_reset, 60)]);
+
+        // After the reset, numbers come round again with newer fixes
+        assert_eq!(check(&store, &numbered(4, 61), at(0)).unwrap(), None);
+        assert_eq!(check(&store, &numbered(1, 62), at(0)).unwrap(), None);
+        let verdicts = check_batch(
+            &store,
+            &[numbered(2, 63), numbered(2, 63), numbered(3, 64)],
+            at(0),
+        )
+        .unwrap();
+        assert_eq!(
+            verdicts,
+            [
+                None,
+                Some(Replay::Duplicate(Box::new(numbered(2, 63)))),
+                None
+            ]
+        );
+
+        // An old fix replayed is still refused
+        assert_eq!(
+            check(&store, &numbered(1, 1), at(0)).unwrap(),
+            Some(Replay::Stale {
+                sequence: 1,
+                latest: before_reset
+            })
+        );
+    }
+}
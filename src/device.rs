@@ -0,0 +1,388 @@
+use crate::battery::BatteryForecast;
+use crate::gps_data::{CoordinateEncoding, IncomingData, StoredData};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[cfg(feature = "ssr")]
+use crate::gps_data::DeviceTimeZone;
+
+// --- Device Registry ---
+
+/// A registered tracker and the pet wearing it.
+///
+/// `timezone` and `encoding` override `BUDDY_DEFAULT_TZ` and `BUDDY_COORD_BBOX`
+/// for this device's posts; leave them unset to use the server defaults.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Device {
+    /// The `id` the tracker sends with every post. Taken from the URL on updates.
+    #[serde(default)]
+    pub id: String,
+    pub display_name: String,
+    #[serde(default)]
+    pub pet_name: Option<String>,
+    #[serde(default)]
+    pub photo_url: Option<String>,
+    #[serde(default)]
+    pub schedule: ReportingSchedule,
+    /// UTC offset (`+07:00`) or IANA name (`Asia/Bangkok`) of the device clock.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub encoding: Option<CoordinateEncoding>,
+    /// Household the device belongs to. Chosen when it is registered; updates can't move it.
+    #[serde(default)]
+    pub household_id: Option<i64>,
+}
+
+/// When the tracker is expected to report, mirroring the firmware's
+/// `START_HOUR`, `END_HOUR` and `POLLING_INTERVAL_SEC` settings.
+///
+/// Hours are in the device's local time and inclusive; outside them the tracker deep-sleeps.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ReportingSchedule {
+    pub start_hour: u8,
+    pub end_hour: u8,
+    pub interval_secs: u32,
+}
+
+impl Default for ReportingSchedule {
+    /// Around the clock, once an hour, like the firmware's default build.
+    fn default() -> Self {
+        Self {
+            start_hour: 0,
+            end_hour: 23,
+            interval_secs: 3600,
+        }
+    }
+}
+
+impl Device {
+    /// Checks the fields a client may have gotten wrong before the device is stored.
+    pub fn validate(&self) -> Result<(), DeviceError> {
+        if self.id.trim().is_empty() {
+            return Err(DeviceError::MissingId);
+        }
+        if self.display_name.trim().is_empty() {
+            return Err(DeviceError::MissingDisplayName);
+        }
+        if let Some(url) = &self.photo_url
+            && !(url.starts_with("https://") || url.starts_with("http://") || url.starts_with('/'))
+        {
+            return Err(DeviceError::InvalidPhotoUrl(url.clone()));
+        }
+
+        let schedule = self.schedule;
+        if schedule.start_hour > 23
+            || schedule.end_hour > 23
+            || schedule.start_hour > schedule.end_hour
+            || schedule.interval_secs == 0
+        {
+            return Err(DeviceError::InvalidSchedule(schedule));
+        }
+
+        #[cfg(feature = "ssr")]
+        if let Some(tz) = &self.timezone
+            && tz.parse::<DeviceTimeZone>().is_err()
+        {
+            return Err(DeviceError::InvalidTimeZone(tz.clone()));
+        }
+        if let Some(encoding) = &self.encoding {
+            encoding.validate().map_err(DeviceError::InvalidEncoding)?;
+        }
+        Ok(())
+    }
+
+    /// The zone this device's clock runs in, falling back to `default_tz`.
+    #[cfg(feature = "ssr")]
+    pub fn time_zone(&self, default_tz: DeviceTimeZone) -> DeviceTimeZone {
+        self.timezone
+            .as_deref()
+            .and_then(|tz| tz.parse().ok())
+            .unwrap_or(default_tz)
+    }
+
+    /// Name to show for this device: the pet's if there is one.
+    pub fn label(&self) -> &str {
+        self.pet_name.as_deref().unwrap_or(&self.display_name)
+    }
+}
+
+/// Why a device definition was rejected.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceError {
+    /// The device has no `id`.
+    MissingId,
+    /// The device has no `display_name`.
+    MissingDisplayName,
+    /// `photo_url` isn't an http(s) URL or a path on this server.
+    InvalidPhotoUrl(String),
+    /// The schedule hours aren't an ordered pair in 0..=23, or the interval is zero.
+    InvalidSchedule(ReportingSchedule),
+    /// `timezone` is neither a UTC offset nor a known IANA zone.
+    InvalidTimeZone(String),
+    /// `encoding` isn't a valid WGS84 bounding box.
+    InvalidEncoding(String),
+    /// The `id` in the body of an update doesn't match the one in the URL.
+    IdMismatch { path: String, body: String },
+}
+
+impl DeviceError {
+    /// Stable, machine-readable identifier returned in error responses.
+    pub fn code(&self) -> &'static str {
+        match self {
+            DeviceError::MissingId => "missing_id",
+            DeviceError::MissingDisplayName => "missing_display_name",
+            DeviceError::InvalidPhotoUrl(_) => "invalid_photo_url",
+            DeviceError::InvalidSchedule(_) => "invalid_schedule",
+            DeviceError::InvalidTimeZone(_) => "invalid_time_zone",
+            DeviceError::InvalidEncoding(_) => "invalid_encoding",
+            DeviceError::IdMismatch { .. } => "id_mismatch",
+        }
+    }
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::MissingId => write!(f, "Device id is required"),
+            DeviceError::MissingDisplayName => write!(f, "Device display_name is required"),
+            DeviceError::InvalidPhotoUrl(url) => write!(f, "Invalid photo_url {:?}", url),
+            DeviceError::InvalidSchedule(s) => write!(
+                f,
+                "Invalid schedule: hours {}..={} must be ordered within 0..=23 and interval_secs must be positive",
+                s.start_hour, s.end_hour
+            ),
+            DeviceError::InvalidTimeZone(tz) => write!(f, "Unknown time zone {:?}", tz),
+            DeviceError::InvalidEncoding(e) => write!(f, "{}", e),
+            DeviceError::IdMismatch { path, body } => {
+                write!(f, "Device id {:?} in body doesn't match {:?}", body, path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+// --- Device Keys ---
+
+/// A credential a device authenticates its posts with.
+///
+/// `secret` is only returned once, in the response that creates the key.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeviceKey {
+    pub key_id: i64,
+    pub device_id: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+// --- Latest Positions ---
+
+/// Where a device was last seen, as returned by `GET /api/devices/latest`.
+///
+/// Registered devices that haven't reported yet are listed with no `data`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LatestFix {
+    pub device_id: String,
+    /// Registry entry, if the device is registered.
+    pub device: Option<Device>,
+    /// The fix with the newest device timestamp.
+    pub data: Option<StoredData>,
+    /// Seconds between when the server received that fix and the time of the request,
+    /// both by the server's clock, so a device clock that is off doesn't skew it.
+    pub age_secs: Option<i64>,
+    pub battery: Option<u8>,
+    /// When the battery is expected to run out, if its recent readings say.
+    #[serde(default)]
+    pub battery_forecast: Option<BatteryForecast>,
+}
+
+/// How overdue a device's next report is, judged against its expected reporting interval.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Freshness {
+    /// Reported within about one interval.
+    Fresh,
+    /// Missed a report or two.
+    Late,
+    /// Silent for several intervals, or never reported.
+    Stale,
+}
+
+impl LatestFix {
+    /// Name to show for this device: the pet's, then the registered name, then the raw id.
+    pub fn label(&self) -> &str {
+        self.device.as_ref().map_or(&self.device_id, Device::label)
+    }
+
+    /// Classifies `age_secs` against the device's schedule (hourly for unregistered devices).
+    ///
+    /// Up to 1.5 intervals is fresh, to allow for GPS fix time; up to 3 is late.
+    pub fn freshness(&self) -> Freshness {
+        let interval = self
+            .device
+            .as_ref()
+            .map_or(ReportingSchedule::default(), |d| d.schedule)
+            .interval_secs as i64;
+        match self.age_secs {
+            Some(age) if age * 2 <= interval * 3 => Freshness::Fresh,
+            Some(age) if age <= interval * 3 => Freshness::Late,
+            _ => Freshness::Stale,
+        }
+    }
+}
+
+// --- Unregistered Devices ---
+
+/// What the ingestion endpoint does with posts from IDs that aren't in the registry.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnregisteredPolicy {
+    /// Store them like any other point (the behaviour before the registry existed).
+    Accept,
+    /// Refuse them with `403 Forbidden`.
+    Reject,
+    /// Set them aside for review without storing a point.
+    Quarantine,
+}
+
+#[cfg(feature = "ssr")]
+impl std::str::FromStr for UnregisteredPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "accept" => Ok(UnregisteredPolicy::Accept),
+            "reject" => Ok(UnregisteredPolicy::Reject),
+            "quarantine" => Ok(UnregisteredPolicy::Quarantine),
+            _ => Err(format!(
+                "Unknown unregistered device policy {:?} (expected accept, reject or quarantine)",
+                s
+            )),
+        }
+    }
+}
+
+/// A post from an unregistered device, held back under `UnregisteredPolicy::Quarantine`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QuarantinedPost {
+    #[serde(flatten)]
+    pub post: IncomingData,
+    pub received_at: DateTime<Utc>,
+}
+
+// --- Tests ---
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    /// A device registered with just the fields a client has to send.
+    fn rex() -> Device {
+        serde_json::from_value(serde_json::json!({"id": "A", "display_name": "Rex"})).unwrap()
+    }
+
+    fn with_schedule(start_hour: u8, end_hour: u8, interval_secs: u32) -> Device {
+        Device {
+            schedule: ReportingSchedule {
+                start_hour,
+                end_hour,
+                interval_secs,
+            },
+            ..rex()
+        }
+    }
+
+    #[test]
+    fn a_schedule_is_an_ordered_pair_of_hours_and_a_positive_interval() {
+        assert_eq!(rex().schedule, ReportingSchedule::default());
+        for (start, end, interval) in [(0, 23, 3600), (8, 8, 60), (23, 23, 1)] {
+            assert_eq!(
+                with_schedule(start, end, interval).validate(),
+                Ok(()),
+                "{start}..={end} every {interval}s"
+            );
+        }
+        for (start, end, interval) in [(9, 8, 60), (0, 24, 60), (24, 24, 60), (8, 19, 0)] {
+            let device = with_schedule(start, end, interval);
+            assert_eq!(
+                device.validate(),
+                Err(DeviceError::InvalidSchedule(device.schedule)),
+                "{start}..={end} every {interval}s"
+            );
+        }
+    }
+
+    #[test]
+    fn names_photos_zones_and_encodings_are_checked() {
+        let check = |device: Device| device.validate().map_err(|e| e.code());
+        assert_eq!(
+            check(Device {
+                id: " ".to_string(),
+                ..rex()
+            }),
+            Err("missing_id")
+        );
+        assert_eq!(
+            check(Device {
+                display_name: "\t".to_string(),
+                ..rex()
+            }),
+            Err("missing_display_name")
+        );
+
+        let photo = |url: &str| Device {
+            photo_url: Some(url.to_string()),
+            ..rex()
+        };
+        for url in [
+            "https://example.com/rex.jpg",
+            "http://example.com/rex.jpg",
+            "/photos/rex.jpg",
+        ] {
+            assert_eq!(check(photo(url)), Ok(()), "{url}");
+        }
+        for url in [
+            "javascript:alert(1)",
+            "photos/rex.jpg",
+            "ftp://example.com/rex.jpg",
+        ] {
+            assert_eq!(check(photo(url)), Err("invalid_photo_url"), "{url}");
+        }
+
+        let zone = |tz: &str| Device {
+            timezone: Some(tz.to_string()),
+            ..rex()
+        };
+        for tz in ["+07:00", "-03:30", "Asia/Bangkok"] {
+            assert_eq!(check(zone(tz)), Ok(()), "{tz}");
+        }
+        for tz in ["", "ICT", "Mars/Olympus_Mons"] {
+            assert_eq!(check(zone(tz)), Err("invalid_time_zone"), "{tz}");
+        }
+
+        let flipped = CoordinateEncoding {
+            min_longitude: 101.0,
+            max_longitude: 100.0,
+            ..CoordinateEncoding::default()
+        };
+        assert_eq!(
+            check(Device {
+                encoding: Some(flipped),
+                ..rex()
+            }),
+            Err("invalid_encoding")
+        );
+    }
+
+    #[test]
+    fn a_device_is_shown_by_its_pets_name_if_it_has_one() {
+        assert_eq!(rex().label(), "Rex");
+        let named = Device {
+            pet_name: Some("Biscuit".to_string()),
+            ..rex()
+        };
+        assert_eq!(named.label(), "Biscuit");
+    }
+}
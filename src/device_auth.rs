@@ -0,0 +1,306 @@
+use crate::device::DeviceKey;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use subtle::ConstantTimeEq;
+
+// --- Request Authentication ---
+//
+// A device proves who it is with one of its keys, in either of two ways:
+//
+//   Authorization: Bearer <secret>
+//
+// or, so the secret never travels over the wire:
+//
+//   X-Buddy-Timestamp: <unix seconds>
+//   X-Buddy-Signature: <hex HMAC-SHA256(secret, "<METHOD> <path>\n<timestamp>.<raw body>")>
+//
+// The method and path are signed along with the body, so a signed post to /api/data
+// can't be replayed to another endpoint that would read the same body differently.
+//
+// A device may hold several keys at once; any of them is accepted, which is how
+// keys are rotated: add the new key, reflash the device, then delete the old one.
+
+/// Header carrying the unix time a signature was made at.
+pub const TIMESTAMP_HEADER: &str = "X-Buddy-Timestamp";
+/// Header carrying the hex HMAC-SHA256 signature.
+pub const SIGNATURE_HEADER: &str = "X-Buddy-Signature";
+
+/// Random bytes in a generated secret (hex-encoded, so twice as many characters).
+const SECRET_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Which devices have to authenticate their posts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuthMode {
+    /// Devices that have keys must use one; devices without keys are let through.
+    /// Meant for rolling keys out one device at a time.
+    Optional,
+    /// Every post must authenticate, so devices without keys can't post at all.
+    Required,
+}
+
+impl std::str::FromStr for AuthMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "optional" => Ok(AuthMode::Optional),
+            "required" => Ok(AuthMode::Required),
+            _ => Err(format!(
+                "Unknown device auth mode {:?} (expected optional or required)",
+                s
+            )),
+        }
+    }
+}
+
+/// The credentials a request was sent with, taken from its headers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Credentials<'a> {
+    Bearer(&'a str),
+    Signature {
+        timestamp: &'a str,
+        signature: &'a str,
+    },
+}
+
+impl<'a> Credentials<'a> {
+    /// Picks the credentials out of the `Authorization`, `X-Buddy-Timestamp` and
+    /// `X-Buddy-Signature` header values. Returns `None` if the request has none.
+    pub fn from_headers(
+        authorization: Option<&'a str>,
+        timestamp: Option<&'a str>,
+        signature: Option<&'a str>,
+    ) -> Result<Option<Self>, AuthError> {
+        match (authorization, timestamp, signature) {
+            (Some(authorization), _, _) => authorization
+                .strip_prefix("Bearer ")
+                .map(|token| Some(Credentials::Bearer(token.trim())))
+                .ok_or(AuthError::MalformedCredentials("Authorization")),
+            (None, Some(timestamp), Some(signature)) => Ok(Some(Credentials::Signature {
+                timestamp: timestamp.trim(),
+                signature: signature.trim(),
+            })),
+            (None, Some(_), None) => Err(AuthError::MalformedCredentials(SIGNATURE_HEADER)),
+            (None, None, Some(_)) => Err(AuthError::MalformedCredentials(TIMESTAMP_HEADER)),
+            (None, None, None) => Ok(None),
+        }
+    }
+
+    /// Checks the credentials of a `method` request to `path` against the device's `keys`
+    /// (which must carry their secrets).
+    ///
+    /// Signatures are only accepted within `max_age_secs` of `now`, either way,
+    /// so a captured request can't be replayed later. Returns the id of the matching key.
+    pub fn verify(
+        &self,
+        method: &str,
+        path: &str,
+        body: &[u8],
+        keys: &[DeviceKey],
+        now: DateTime<Utc>,
+        max_age_secs: i64,
+    ) -> Result<i64, AuthError> {
+        let secrets = keys
+            .iter()
+            .filter_map(|key| Some((key.key_id, key.secret.as_deref()?)));
+
+        match *self {
+            Credentials::Bearer(token) => secrets
+                .filter(|(_, secret)| bool::from(secret.as_bytes().ct_eq(token.as_bytes())))
+                .map(|(key_id, _)| key_id)
+                .next()
+                .ok_or(AuthError::InvalidToken),
+            Credentials::Signature {
+                timestamp,
+                signature,
+            } => {
+                let signed_at = timestamp
+                    .parse::<i64>()
+                    .map_err(|_| AuthError::MalformedCredentials(TIMESTAMP_HEADER))?;
+                if now.timestamp().abs_diff(signed_at) > max_age_secs.unsigned_abs() {
+                    return Err(AuthError::StaleSignature { signed_at });
+                }
+                let signature = hex::decode(signature)
+                    .map_err(|_| AuthError::MalformedCredentials(SIGNATURE_HEADER))?;
+
+                secrets
+                    .filter(|(_, secret)| {
+                        signing_mac(secret, method, path, timestamp, body)
+                            .verify_slice(&signature)
+                            .is_ok()
+                    })
+                    .map(|(key_id, _)| key_id)
+                    .next()
+                    .ok_or(AuthError::InvalidSignature)
+            }
+        }
+    }
+}
+
+/// Hex HMAC-SHA256 signature of a `method` request to `path` with `body`, sent at
+/// `timestamp`, as a device computes it.
+pub fn sign(secret: &str, method: &str, path: &str, timestamp: &str, body: &[u8]) -> String {
+    hex::encode(
+        signing_mac(secret, method, path, timestamp, body)
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+fn signing_mac(secret: &str, method: &str, path: &str, timestamp: &str, body: &[u8]) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(method.as_bytes());
+    mac.update(b" ");
+    mac.update(path.as_bytes());
+    mac.update(b"\n");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+/// A fresh random secret for a new device key.
+pub fn generate_secret() -> String {
+    hex::encode(rand::random::<[u8; SECRET_LEN]>())
+}
+
+// --- Errors ---
+
+/// Why a device's post failed authentication. All of them are answered with `401 Unauthorized`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AuthError {
+    /// The post carries no credentials but the device needs them.
+    MissingCredentials,
+    /// The named header is missing its counterpart or isn't in the expected format.
+    MalformedCredentials(&'static str),
+    /// The bearer token isn't one of the device's keys.
+    InvalidToken,
+    /// The signature doesn't match the body under any of the device's keys.
+    InvalidSignature,
+    /// The signature was made too long ago (or too far in the future).
+    StaleSignature { signed_at: i64 },
+}
+
+impl AuthError {
+    /// Stable, machine-readable identifier returned to the device in error responses.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthError::MissingCredentials => "missing_credentials",
+            AuthError::MalformedCredentials(_) => "malformed_credentials",
+            AuthError::InvalidToken => "invalid_token",
+            AuthError::InvalidSignature => "invalid_signature",
+            AuthError::StaleSignature { .. } => "stale_signature",
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::MissingCredentials => write!(
+                f,
+                "A bearer token or {} and {} headers are required",
+                TIMESTAMP_HEADER, SIGNATURE_HEADER
+            ),
+            AuthError::MalformedCredentials(header) => {
+                write!(f, "Header {} is missing or malformed", header)
+            }
+            AuthError::InvalidToken => write!(f, "Bearer token is not valid for this device"),
+            AuthError::InvalidSignature => write!(f, "Signature is not valid for this device"),
+            AuthError::StaleSignature { signed_at } => {
+                write!(
+                    f,
+                    "Signature timestamp {} is too far from server time",
+                    signed_at
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<DeviceKey> {
+        vec![DeviceKey {
+            key_id: 3,
+            device_id: "A".to_string(),
+            created_at: DateTime::from_timestamp(0, 0).unwrap(),
+            secret: Some("s3cret".to_string()),
+        }]
+    }
+
+    #[test]
+    fn signatures_cover_method_path_timestamp_and_body() {
+        let now = DateTime::from_timestamp(1_000_000, 0).unwrap();
+        let body = br#"{"id":"A"}"#;
+        let signature = sign("s3cret", "POST", "/api/data", "1000000", body);
+        let credentials = Credentials::Signature {
+            timestamp: "1000000",
+            signature: &signature,
+        };
+
+        assert_eq!(
+            credentials.verify("POST", "/api/data", body, &keys(), now, 300),
+            Ok(3)
+        );
+        assert_eq!(
+            credentials.verify("POST", "/api/data/batch", body, &keys(), now, 300),
+            Err(AuthError::InvalidSignature)
+        );
+        assert_eq!(
+            credentials.verify("POST", "/api/data", br#"{"id":"B"}"#, &keys(), now, 300),
+            Err(AuthError::InvalidSignature)
+        );
+        let later = now + chrono::Duration::seconds(301);
+        assert_eq!(
+            credentials.verify("POST", "/api/data", body, &keys(), later, 300),
+            Err(AuthError::StaleSignature {
+                signed_at: 1_000_000
+            })
+        );
+    }
+
+    #[test]
+    fn timestamps_at_the_ends_of_the_range_are_stale_not_a_panic() {
+        let now = DateTime::from_timestamp(1_000_000, 0).unwrap();
+        for signed_at in [i64::MIN, i64::MIN + 1_000_000, i64::MAX] {
+            let timestamp = signed_at.to_string();
+            let credentials = Credentials::Signature {
+                timestamp: &timestamp,
+                signature: "00",
+            };
+            assert_eq!(
+                credentials.verify("POST", "/api/data", b"", &keys(), now, 300),
+                Err(AuthError::StaleSignature { signed_at })
+            );
+        }
+    }
+
+    #[test]
+    fn bearer_tokens_must_match_a_key() {
+        let now = DateTime::from_timestamp(0, 0).unwrap();
+        assert_eq!(
+            Credentials::Bearer("s3cret").verify("POST", "/api/data", b"", &keys(), now, 300),
+            Ok(3)
+        );
+        assert_eq!(
+            Credentials::Bearer("guess").verify("POST", "/api/data", b"", &keys(), now, 300),
+            Err(AuthError::InvalidToken)
+        );
+        assert_eq!(
+            Credentials::from_headers(None, Some("1"), None),
+            Err(AuthError::MalformedCredentials(SIGNATURE_HEADER))
+        );
+    }
+}
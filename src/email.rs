@@ -0,0 +1,725 @@
+use crate::webhook::EventType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[cfg(feature = "ssr")]
+use crate::alert::{Alert, AlertState};
+#[cfg(feature = "ssr")]
+use crate::device::Device;
+#[cfg(feature = "ssr")]
+use crate::geofence::{GeofenceEvent, Transition};
+#[cfg(feature = "ssr")]
+use crate::gps_data::{DeviceTimeZone, StoredData};
+#[cfg(feature = "ssr")]
+use crate::storage::{DataStore, StorageError};
+#[cfg(feature = "ssr")]
+use chrono::{DateTime, Utc};
+#[cfg(feature = "ssr")]
+use lettre::message::Mailbox;
+#[cfg(feature = "ssr")]
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+#[cfg(feature = "ssr")]
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "ssr")]
+use std::sync::Mutex;
+
+// --- Email Notifications ---
+//
+// Everyone in a household can have its alerts and geofence crossings emailed to them:
+// each user picks an address and the event types they want, the same ones webhooks
+// subscribe to. Subjects and bodies are rendered from templates, which can mention
+//
+//   {pet} {device_id} {message} {alert} {geofence}
+//   {position} {latitude} {longitude} {battery} {seen_at} {map_url}
+//
+// where the position is the device's last known one (for a crossing, the fix that crossed).
+// A user gets at most one email per BUDDY_EMAIL_RATE_LIMIT_SECS about the same thing, such
+// as one device's low battery alert firing, so an alert that keeps firing and resolving
+// doesn't flood their inbox.
+
+/// Longest email address accepted.
+pub const MAX_EMAIL_LEN: usize = 254;
+
+/// A user's email settings, as read and written at `/api/auth/me/notifications`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct EmailPrefs {
+    /// Where to send them; nothing is sent without one.
+    #[serde(default)]
+    pub email: Option<String>,
+    /// The event types to be emailed about.
+    #[serde(default)]
+    pub events: Vec<EventType>,
+}
+
+impl EmailPrefs {
+    /// Checks the address looks like one.
+    pub fn validate(&self) -> Result<(), EmailError> {
+        let Some(email) = &self.email else {
+            return Ok(());
+        };
+        let valid = match email.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && domain.contains('.')
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+                    && !domain.contains('@')
+            }
+            None => false,
+        };
+        if !valid || email.len() > MAX_EMAIL_LEN || email.chars().any(char::is_whitespace) {
+            return Err(EmailError::InvalidAddress(email.clone()));
+        }
+        Ok(())
+    }
+
+    /// The address to email about `event`, if the user wants it.
+    pub fn address_for(&self, event: EventType) -> Option<&str> {
+        self.email
+            .as_deref()
+            .filter(|_| self.events.contains(&event))
+    }
+}
+
+// --- Templates ---
+
+/// Subject and body of one kind of email, with `{placeholder}`s.
+#[cfg(feature = "ssr")]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Template {
+    pub subject: String,
+    pub body: String,
+}
+
+/// Templates by name: an event type (`geofence.exit`), or for alerts, optionally an event
+/// type and alert kind (`alert.fired.low_battery`), which is used over the plain one.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Templates(BTreeMap<String, Template>);
+
+/// Ends every default body.
+#[cfg(feature = "ssr")]
+const POSITION_FOOTER: &str =
+    "Last known position: {position}, at {seen_at} (battery {battery}%)\n{map_url}\n";
+
+#[cfg(feature = "ssr")]
+impl Default for Templates {
+    fn default() -> Self {
+        let template = |subject: &str, body: &str| Template {
+            subject: subject.to_string(),
+            body: format!("{}\n\n{}", body, POSITION_FOOTER),
+        };
+        Templates(BTreeMap::from([
+            (
+                "alert.fired".to_string(),
+                template("{pet}: {alert}", "{message}"),
+            ),
+            (
+                "alert.fired.low_battery".to_string(),
+                template("{pet}'s battery at {battery}%", "{message}"),
+            ),
+            (
+                "alert.fired.silent".to_string(),
+                template("{pet}'s tracker stopped reporting", "{message}"),
+            ),
+            (
+                "alert.fired.clock_skew".to_string(),
+                template("{pet}'s tracker clock is off", "{message}"),
+            ),
+            (
+                "alert.resolved".to_string(),
+                template("{pet}: {alert} cleared", "This has cleared: {message}"),
+            ),
+            (
+                "geofence.enter".to_string(),
+                template("{pet} arrived at {geofence}", "{message}"),
+            ),
+            (
+                "geofence.exit".to_string(),
+                template("{pet} left {geofence}", "{message}"),
+            ),
+        ]))
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl Templates {
+    /// The defaults, with the templates in the JSON file at `path` put over them.
+    /// The file maps template names to `{"subject", "body"}`.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read email templates {:?}: {}", path, e))?;
+        let overrides: BTreeMap<String, Template> = serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid email templates {:?}: {}", path, e))?;
+        let mut templates = Templates::default();
+        for (name, template) in overrides {
+            let known = EventType::ALL.iter().any(|event| {
+                name == event.as_str()
+                    || name
+                        .strip_prefix(event.as_str())
+                        .and_then(|kind| kind.strip_prefix('.'))
+                        .is_some_and(|kind| {
+                            event.as_str().starts_with("alert.")
+                                && kind.parse::<crate::alert::AlertKind>().is_ok()
+                        })
+            });
+            if !known {
+                return Err(format!(
+                    "Unknown email template {:?} in {:?} (expected an event type like geofence.exit, or alert.fired.<kind>)",
+                    name, path
+                ));
+            }
+            templates.0.insert(name, template);
+        }
+        Ok(templates)
+    }
+
+    /// The first of `names` there is a template for.
+    fn find(&self, names: &[String]) -> Option<&Template> {
+        names.iter().find_map(|name| self.0.get(name))
+    }
+}
+
+// --- Notices ---
+
+/// Something to email a household about, with the values its templates can use.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notice {
+    pub event: EventType,
+    pub household_id: i64,
+    /// Templates to render it with, most specific first.
+    templates: Vec<String>,
+    /// What the rate limit counts emails by.
+    rate_key: String,
+    values: BTreeMap<&'static str, String>,
+}
+
+#[cfg(feature = "ssr")]
+impl Notice {
+    /// The values every notice about `device` has, from the fix it was last seen at.
+    fn new(
+        event: EventType,
+        household_id: i64,
+        device: &Device,
+        fix: Option<&StoredData>,
+        default_tz: DeviceTimeZone,
+    ) -> Self {
+        let mut values = BTreeMap::from([
+            ("pet", device.label().to_string()),
+            ("device_id", device.id.clone()),
+        ]);
+        let unknown = "unknown".to_string();
+        let (position, latitude, longitude, battery, seen_at, map_url) = match fix {
+            Some(fix) => (
+                format!("{:.5}, {:.5}", fix.latitude_deg, fix.longitude_deg),
+                format!("{:.5}", fix.latitude_deg),
+                format!("{:.5}", fix.longitude_deg),
+                fix.battery.to_string(),
+                device
+                    .time_zone(default_tz)
+                    .local_time(fix.timestamp)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string(),
+                format!(
+                    "https://www.openstreetmap.org/?mlat={lat:.5}&mlon={lon:.5}#map=17/{lat:.5}/{lon:.5}",
+                    lat = fix.latitude_deg,
+                    lon = fix.longitude_deg
+                ),
+            ),
+            None => (
+                unknown.clone(),
+                unknown.clone(),
+                unknown.clone(),
+                unknown.clone(),
+                unknown,
+                String::new(),
+            ),
+        };
+        values.extend([
+            ("position", position),
+            ("latitude", latitude),
+            ("longitude", longitude),
+            ("battery", battery),
+            ("seen_at", seen_at),
+            ("map_url", map_url),
+        ]);
+        Notice {
+            event,
+            household_id,
+            templates: vec![event.as_str().to_string()],
+            rate_key: String::new(),
+            values,
+        }
+    }
+
+    /// The notice for an alert that fired or resolved, with the device's latest fix.
+    /// `None` for acknowledgements and devices outside any household.
+    pub fn for_alert(
+        store: &dyn DataStore,
+        alert: &Alert,
+        default_tz: DeviceTimeZone,
+    ) -> Result<Option<Self>, StorageError> {
+        let event = match alert.state {
+            AlertState::Firing => EventType::AlertFired,
+            AlertState::Resolved => EventType::AlertResolved,
+            AlertState::Acknowledged => return Ok(None),
+        };
+        let Some(device) = store.device(&alert.device_id)? else {
+            return Ok(None);
+        };
+        let Some(household_id) = device.household_id else {
+            return Ok(None);
+        };
+        let latest = store.latest(&[household_id])?;
+        let fix = latest.iter().find(|fix| fix.id == device.id);
+
+        let mut notice = Notice::new(event, household_id, &device, fix, default_tz);
+        notice
+            .templates
+            .insert(0, format!("{}.{}", event.as_str(), alert.kind.as_str()));
+        notice.rate_key = format!(
+            "{} {} {}",
+            event.as_str(),
+            alert.device_id,
+            alert.kind.as_str()
+        );
+        notice
+            .values
+            .insert("alert", alert.kind.title().to_string());
+        notice.values.insert("message", alert.message.clone());
+        Ok(Some(notice))
+    }
+
+    /// The notice for a geofence crossing, positioned at the fix that crossed.
+    /// `None` for devices outside any household.
+    pub fn for_geofence_event(
+        store: &dyn DataStore,
+        geofence_event: &GeofenceEvent,
+        default_tz: DeviceTimeZone,
+    ) -> Result<Option<Self>, StorageError> {
+        let Some(device) = store.device(&geofence_event.device_id)? else {
+            return Ok(None);
+        };
+        let Some(household_id) = device.household_id else {
+            return Ok(None);
+        };
+        let (event, verb) = match geofence_event.transition {
+            Transition::Enter => (EventType::GeofenceEnter, "arrived at"),
+            Transition::Exit => (EventType::GeofenceExit, "left"),
+        };
+        let mut notice = Notice::new(
+            event,
+            household_id,
+            &device,
+            Some(&geofence_event.fix),
+            default_tz,
+        );
+        notice.rate_key = format!(
+            "{} {} {}",
+            event.as_str(),
+            geofence_event.device_id,
+            geofence_event.geofence_id
+        );
+        let message = format!(
+            "{} {} {} at {}",
+            device.label(),
+            verb,
+            geofence_event.geofence_name,
+            notice.values["seen_at"]
+        );
+        notice
+            .values
+            .insert("geofence", geofence_event.geofence_name.clone());
+        notice.values.insert("message", message);
+        Ok(Some(notice))
+    }
+
+    /// Fills the notice's values into `text`. Placeholders it has no value for are left as they are.
+    pub fn render(&self, text: &str) -> String {
+        self.values
+            .iter()
+            .fold(text.to_string(), |text, (name, value)| {
+                text.replace(&format!("{{{}}}", name), value)
+            })
+    }
+}
+
+// --- Sending ---
+
+/// An email to one household member about one notice.
+#[cfg(feature = "ssr")]
+pub struct Email {
+    pub user_id: i64,
+    pub address: String,
+    /// What it counts against in the rate limit.
+    pub rate_key: String,
+    /// The message, or why it couldn't be written.
+    pub message: Result<Message, String>,
+}
+
+/// Sends notices to the household members who asked for them, over SMTP.
+#[cfg(feature = "ssr")]
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    templates: Templates,
+    rate_limit: chrono::Duration,
+    /// When each user was last emailed about each thing, by user id and rate key,
+    /// including emails composed but still being sent.
+    last_sent: Mutex<HashMap<(i64, String), DateTime<Utc>>>,
+}
+
+#[cfg(feature = "ssr")]
+impl Mailer {
+    /// A mailer sending through the server at `smtp_url` (`smtp://`, or `smtps://` for TLS,
+    /// with optional `user:password@` and `?tls=required` for STARTTLS) as `from`.
+    pub fn new(
+        smtp_url: &str,
+        from: &str,
+        templates: Templates,
+        rate_limit_secs: i64,
+    ) -> Result<Self, String> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(smtp_url)
+            .map_err(|e| format!("Invalid SMTP URL: {}", e))?
+            .build();
+        let from = from
+            .parse()
+            .map_err(|e| format!("Invalid sender {:?}: {}", from, e))?;
+        Ok(Mailer {
+            transport,
+            from,
+            templates,
+            rate_limit: chrono::Duration::seconds(rate_limit_secs),
+            last_sent: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The emails to send about `notice` at `now`: one to each member of its household
+    /// who wants its event type and wasn't sent an email about the same thing within the
+    /// rate limit. Each email takes its place in the rate limit straight away, so notices
+    /// composed while it is being sent don't email the same user again.
+    pub fn compose(
+        &self,
+        store: &dyn DataStore,
+        notice: &Notice,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Email>, StorageError> {
+        let Some(template) = self.templates.find(&notice.templates) else {
+            return Ok(Vec::new());
+        };
+        let subject = notice.render(&template.subject);
+        let body = notice.render(&template.body);
+
+        let mut last_sent = self.last_sent();
+        last_sent.retain(|_, sent_at| now - *sent_at < self.rate_limit);
+        let mut emails = Vec::new();
+        for member in store.members(notice.household_id)? {
+            let Some(prefs) = store.email_prefs(member.user_id)? else {
+                continue;
+            };
+            let Some(address) = prefs.address_for(notice.event) else {
+                continue;
+            };
+            if last_sent.contains_key(&(member.user_id, notice.rate_key.clone())) {
+                continue;
+            }
+            let message = address
+                .parse::<Mailbox>()
+                .map_err(|e| e.to_string())
+                .and_then(|to| {
+                    Message::builder()
+                        .from(self.from.clone())
+                        .to(to)
+                        .subject(subject.clone())
+                        .body(body.clone())
+                        .map_err(|e| e.to_string())
+                });
+            if message.is_ok() {
+                last_sent.insert((member.user_id, notice.rate_key.clone()), now);
+            }
+            emails.push(Email {
+                user_id: member.user_id,
+                address: address.to_string(),
+                rate_key: notice.rate_key.clone(),
+                message,
+            });
+        }
+        Ok(emails)
+    }
+
+    /// Sends `email`, composed at `now`, to the SMTP server. Only an email the server
+    /// accepted counts against the rate limit: a failed one gives its place back, so it
+    /// doesn't hold back the next.
+    pub async fn send(&self, email: Email, now: DateTime<Utc>) -> Result<(), String> {
+        let message = email.message?;
+        let sent = self.transport.send(message).await;
+        if let Err(e) = sent {
+            let key = (email.user_id, email.rate_key);
+            let mut last_sent = self.last_sent();
+            // Unless a later notice has taken the place since
+            if last_sent.get(&key) == Some(&now) {
+                last_sent.remove(&key);
+            }
+            return Err(e.to_string());
+        }
+        Ok(())
+    }
+
+    /// When each user was last sent an email about each thing. Nothing is left half-done
+    /// under this lock, so a panic elsewhere can't poison it.
+    fn last_sent(&self) -> std::sync::MutexGuard<'_, HashMap<(i64, String), DateTime<Utc>>> {
+        self.last_sent.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// --- Errors ---
+
+/// Why email settings were refused.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EmailError {
+    /// The address doesn't look like one, or is too long.
+    InvalidAddress(String),
+}
+
+impl EmailError {
+    /// Stable, machine-readable identifier returned in error responses.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EmailError::InvalidAddress(_) => "invalid_email",
+        }
+    }
+}
+
+impl fmt::Display for EmailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmailError::InvalidAddress(email) => write!(
+                f,
+                "{:?} is not an email address of at most {} characters",
+                email, MAX_EMAIL_LEN
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EmailError {}
+
+// --- Tests ---
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::alert::AlertKind;
+    use crate::household::{Invitation, Role};
+    use crate::storage::tests::{at, backends, device, household, insert, point};
+    use std::io::{BufRead, BufReader, Write};
+    use std::sync::Arc;
+
+    /// An SMTP server on a local port that answers each message's data with `replies` in
+    /// turn, the last one from then on, and keeps the messages it accepted.
+    struct Sink {
+        url: String,
+        messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Sink {
+        fn start(replies: &[u16]) -> Self {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("smtp://{}", listener.local_addr().unwrap());
+            let messages = Arc::new(Mutex::new(Vec::new()));
+            let accepted = messages.clone();
+            let replies = replies.to_vec();
+            std::thread::spawn(move || {
+                let mut received = 0;
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    write!(stream, "220 sink ESMTP\r\n").unwrap();
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap() > 0 {
+                        let command = line.trim_end().to_ascii_uppercase();
+                        line.clear();
+                        if command == "DATA" {
+                            write!(stream, "354 Go ahead\r\n").unwrap();
+                            let mut message = String::new();
+                            while reader.read_line(&mut line).unwrap() > 0 && line != ".\r\n" {
+                                message.push_str(&line);
+                                line.clear();
+                            }
+                            line.clear();
+                            let reply = replies[received.min(replies.len() - 1)];
+                            received += 1;
+                            if reply == 250 {
+                                accepted.lock().unwrap().push(message);
+                            }
+                            write!(stream, "{reply} Done\r\n").unwrap();
+                        } else if command == "QUIT" {
+                            write!(stream, "221 Bye\r\n").unwrap();
+                            break;
+                        } else {
+                            write!(stream, "250 OK\r\n").unwrap();
+                        }
+                    }
+                }
+            });
+            Self { url, messages }
+        }
+
+        fn messages(&self) -> Vec<String> {
+            self.messages.lock().unwrap().clone()
+        }
+    }
+
+    /// Ann's household with device `A` (Rex), which last reported at 80% battery, and ann
+    /// and bob, who both want fired alerts emailed to them.
+    fn household_with_rex(store: &dyn DataStore) -> (i64, i64) {
+        let (household_id, ann) = household(store, "ann");
+        let mut rex = device("A", household_id);
+        rex.pet_name = Some("Rex".to_string());
+        store.create_device(&rex).unwrap();
+        insert(store, &[point("A", 0)]);
+
+        let bob = store.create_user("bob", "hash", at(0)).unwrap().unwrap();
+        let invitation = Invitation {
+            household_id,
+            role: Role::Viewer,
+            created_at: at(0),
+            expires_at: at(60),
+            token: None,
+        };
+        store.create_invitation("t1", &invitation, ann).unwrap();
+        store.accept_invitation("t1", bob.user_id, at(1)).unwrap();
+        for (user_id, email) in [(ann, "ann@example.com"), (bob.user_id, "bob@example.com")] {
+            let prefs = EmailPrefs {
+                email: Some(email.to_string()),
+                events: vec![EventType::AlertFired],
+            };
+            store.set_email_prefs(user_id, &prefs).unwrap();
+        }
+        (ann, bob.user_id)
+    }
+
+    fn fired(store: &dyn DataStore, kind: AlertKind, message: &str) -> Notice {
+        let alert = Alert::firing("A", kind, message.to_string(), at(5));
+        let utc = "+00:00".parse().unwrap();
+        Notice::for_alert(store, &alert, utc).unwrap().unwrap()
+    }
+
+    /// Sends every email in `emails` and returns how many the server accepted.
+    async fn send_all(mailer: &Mailer, emails: Vec<Email>, now: DateTime<Utc>) -> usize {
+        let mut sent = 0;
+        for email in emails {
+            if mailer.send(email, now).await.is_ok() {
+                sent += 1;
+            }
+        }
+        sent
+    }
+
+    #[actix_web::test]
+    async fn alerts_use_their_kinds_template_filled_in_with_the_notice() {
+        for (name, store) in backends() {
+            household_with_rex(store.as_ref());
+            let sink = Sink::start(&[250]);
+            let template = |subject: &str, body: &str| Template {
+                subject: subject.to_string(),
+                body: body.to_string(),
+            };
+            let templates = Templates(BTreeMap::from([
+                (
+                    "alert.fired".to_string(),
+                    template("Something about {pet}", "{message}"),
+                ),
+                (
+                    "alert.fired.low_battery".to_string(),
+                    template(
+                        "{pet} is at {battery}%",
+                        "{message} near {position} ({unknown})",
+                    ),
+                ),
+            ]));
+            let mailer =
+                Mailer::new(&sink.url, "Buddy <buddy@example.com>", templates, 3600).unwrap();
+
+            let low = fired(store.as_ref(), AlertKind::LowBattery, "Battery below 20%");
+            let emails = mailer.compose(store.as_ref(), &low, at(5)).unwrap();
+            let addresses: Vec<&str> = emails.iter().map(|e| e.address.as_str()).collect();
+            assert_eq!(addresses, ["ann@example.com", "bob@example.com"], "{name}");
+            assert_eq!(send_all(&mailer, emails, at(5)).await, 2, "{name}");
+            let silent = fired(store.as_ref(), AlertKind::Silent, "No report");
+            let emails = mailer.compose(store.as_ref(), &silent, at(5)).unwrap();
+            assert_eq!(send_all(&mailer, emails, at(5)).await, 2, "{name}");
+
+            let messages = sink.messages();
+            assert!(messages[0].contains("To: ann@example.com\r\n"), "{name}");
+            assert!(messages[0].contains("Subject: Rex is at 80%\r\n"), "{name}");
+            assert!(
+                messages[0].contains("Battery below 20% near 13.75000, 100.50000 ({unknown})"),
+                "{name}"
+            );
+            // No template for silent alerts, so the one for every fired alert
+            assert!(
+                messages[2].contains("Subject: Something about Rex\r\n"),
+                "{name}"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn each_user_gets_one_email_per_thing_within_the_rate_limit() {
+        for (name, store) in backends() {
+            let (ann, bob) = household_with_rex(store.as_ref());
+            // The first message is refused, the rest accepted
+            let sink = Sink::start(&[554, 250]);
+            let mailer =
+                Mailer::new(&sink.url, "buddy@example.com", Templates::default(), 3600).unwrap();
+            let low = fired(store.as_ref(), AlertKind::LowBattery, "Battery below 20%");
+            let users = |emails: &[Email]| emails.iter().map(|e| e.user_id).collect::<Vec<_>>();
+
+            let emails = mailer.compose(store.as_ref(), &low, at(5)).unwrap();
+            assert_eq!(users(&emails), [ann, bob], "{name}");
+            assert_eq!(send_all(&mailer, emails, at(5)).await, 1, "{name}");
+
+            // Ann's email was refused, so she is still owed one; bob already has his
+            let emails = mailer.compose(store.as_ref(), &low, at(10)).unwrap();
+            assert_eq!(users(&emails), [ann], "{name}");
+            assert_eq!(send_all(&mailer, emails, at(10)).await, 1, "{name}");
+            let emails = mailer.compose(store.as_ref(), &low, at(20)).unwrap();
+            assert!(emails.is_empty(), "{name}");
+
+            // Something else about the same device isn't held back
+            let silent = fired(store.as_ref(), AlertKind::Silent, "No report");
+            let emails = mailer.compose(store.as_ref(), &silent, at(20)).unwrap();
+            assert_eq!(users(&emails), [ann, bob], "{name}");
+
+            // Nor is the same thing once the limit has passed since bob's email
+            let emails = mailer.compose(store.as_ref(), &low, at(65)).unwrap();
+            assert_eq!(users(&emails), [bob], "{name}");
+            assert_eq!(sink.messages().len(), 2, "{name}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn an_email_being_sent_already_counts_against_the_rate_limit() {
+        for (name, store) in backends() {
+            let (ann, bob) = household_with_rex(store.as_ref());
+            // Ann's is refused, bob's accepted
+            let sink = Sink::start(&[554, 250]);
+            let mailer =
+                Mailer::new(&sink.url, "buddy@example.com", Templates::default(), 3600).unwrap();
+            let low = fired(store.as_ref(), AlertKind::LowBattery, "Battery below 20%");
+            let users = |emails: &[Email]| emails.iter().map(|e| e.user_id).collect::<Vec<_>>();
+
+            // Composed at once, say by two batches checked side by side
+            let first = mailer.compose(store.as_ref(), &low, at(5)).unwrap();
+            let second = mailer.compose(store.as_ref(), &low, at(6)).unwrap();
+            assert_eq!(users(&first), [ann, bob], "{name}");
+            assert!(second.is_empty(), "{name}");
+
+            assert_eq!(send_all(&mailer, first, at(5)).await, 1, "{name}");
+            let retry = mailer.compose(store.as_ref(), &low, at(7)).unwrap();
+            assert_eq!(users(&retry), [ann], "{name}");
+        }
+    }
+}
@@ -0,0 +1,530 @@
+use crate::gps_data::StoredData;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[cfg(feature = "ssr")]
+use crate::storage::{DataStore, StorageError};
+#[cfg(feature = "ssr")]
+use std::collections::BTreeMap;
+
+// --- Geofences ---
+//
+// An owner can draw areas such as "home" or "yard" around a device, as a circle or a
+// polygon, and the server records an `enter` or `exit` event, with the fix that caused it,
+// whenever a new point lands on the other side of the boundary.
+//
+// GPS fixes wander by a few metres even when the pet lies still, so a point near the
+// boundary would flip in and out. Each geofence has a hysteresis margin instead: the pet
+// has to be that far inside to enter, and that far outside to leave. The first fix after
+// a geofence is created or changed only settles which side the pet is on.
+
+/// Mean Earth radius in metres, for distances between coordinates.
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+/// Longest geofence name.
+pub const MAX_NAME_LEN: usize = 64;
+/// Largest circle radius, and largest polygon, in metres and corners.
+pub const MAX_RADIUS_M: f64 = 100_000.0;
+pub const MAX_POLYGON_POINTS: usize = 100;
+/// Hysteresis margin used when a geofence doesn't say, and the largest allowed.
+pub const DEFAULT_HYSTERESIS_M: f64 = 15.0;
+pub const MAX_HYSTERESIS_M: f64 = 500.0;
+/// Events listed when the request doesn't say how many, and the most listed at once.
+pub const DEFAULT_EVENT_LIMIT: usize = 50;
+pub const MAX_EVENT_LIMIT: usize = 500;
+
+/// A position in WGS84 degrees.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct GeoPoint {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl GeoPoint {
+    fn is_valid(&self) -> bool {
+        (-90.0..=90.0).contains(&self.latitude) && (-180.0..=180.0).contains(&self.longitude)
+    }
+
+    /// Great-circle distance to `other` in metres.
+    pub fn distance_m(&self, other: GeoPoint) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.longitude - self.longitude).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+    }
+
+    /// Where `self` lies in metres east and north of `origin`. Accurate enough over the
+    /// few kilometres a geofence spans.
+    fn offset_from(&self, origin: GeoPoint) -> (f64, f64) {
+        let x = (self.longitude - origin.longitude).to_radians()
+            * origin.latitude.to_radians().cos()
+            * EARTH_RADIUS_M;
+        let y = (self.latitude - origin.latitude).to_radians() * EARTH_RADIUS_M;
+        (x, y)
+    }
+}
+
+/// The area a geofence covers.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum GeofenceShape {
+    Circle {
+        center: GeoPoint,
+        radius_m: f64,
+    },
+    /// Corners in order; the last one joins back to the first.
+    Polygon {
+        points: Vec<GeoPoint>,
+    },
+}
+
+impl GeofenceShape {
+    /// How far `point` is from the boundary in metres: negative inside, positive outside.
+    pub fn signed_distance_m(&self, point: GeoPoint) -> f64 {
+        match self {
+            GeofenceShape::Circle { center, radius_m } => center.distance_m(point) - radius_m,
+            GeofenceShape::Polygon { points } => {
+                // Work in metres around the point, so it sits at the origin
+                let corners: Vec<(f64, f64)> =
+                    points.iter().map(|p| p.offset_from(point)).collect();
+                let mut inside = false;
+                let mut nearest = f64::INFINITY;
+                for (i, &(x1, y1)) in corners.iter().enumerate() {
+                    let (x2, y2) = corners[(i + 1) % corners.len()];
+                    // Does the edge cross the ray going east from the origin?
+                    if (y1 > 0.0) != (y2 > 0.0) && x1 + (0.0 - y1) * (x2 - x1) / (y2 - y1) > 0.0 {
+                        inside = !inside;
+                    }
+                    // Distance from the origin to the edge
+                    let (dx, dy) = (x2 - x1, y2 - y1);
+                    let length_sq = dx * dx + dy * dy;
+                    let t = if length_sq > 0.0 {
+                        (-(x1 * dx + y1 * dy) / length_sq).clamp(0.0, 1.0)
+                    } else {
+                        0.0
+                    };
+                    nearest = nearest.min((x1 + t * dx).hypot(y1 + t * dy));
+                }
+                if inside { -nearest } else { nearest }
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), GeofenceError> {
+        match self {
+            GeofenceShape::Circle { center, radius_m } => {
+                if !center.is_valid() {
+                    return Err(GeofenceError::InvalidCoordinate(*center));
+                }
+                if !(*radius_m > 0.0 && *radius_m <= MAX_RADIUS_M) {
+                    return Err(GeofenceError::InvalidRadius(*radius_m));
+                }
+            }
+            GeofenceShape::Polygon { points } => {
+                if !(3..=MAX_POLYGON_POINTS).contains(&points.len()) {
+                    return Err(GeofenceError::InvalidPolygon(points.len()));
+                }
+                if let Some(point) = points.iter().find(|p| !p.is_valid()) {
+                    return Err(GeofenceError::InvalidCoordinate(*point));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An area around which a device's comings and goings are recorded.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Geofence {
+    pub geofence_id: i64,
+    pub device_id: String,
+    pub name: String,
+    pub shape: GeofenceShape,
+    /// How far past the boundary, in metres, the device has to be to count as crossing it.
+    pub hysteresis_m: f64,
+    pub created_at: DateTime<Utc>,
+    /// Which side the device was on at its last fix; `None` until one arrives.
+    pub inside: Option<bool>,
+    /// Device timestamp of the last fix checked against this geofence.
+    pub checked_at: Option<DateTime<Utc>>,
+}
+
+fn default_hysteresis_m() -> f64 {
+    DEFAULT_HYSTERESIS_M
+}
+
+/// Body of `POST /api/devices/{id}/geofences` and `PUT /api/devices/{id}/geofences/{geofence_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NewGeofence {
+    pub name: String,
+    pub shape: GeofenceShape,
+    #[serde(default = "default_hysteresis_m")]
+    pub hysteresis_m: f64,
+}
+
+impl NewGeofence {
+    /// Checks the name, the shape's coordinates and size, and the hysteresis margin,
+    /// which has to be smaller than a circle's radius or the circle could never be entered.
+    pub fn validate(&self) -> Result<(), GeofenceError> {
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            return Err(GeofenceError::InvalidName);
+        }
+        self.shape.validate()?;
+        let too_wide = match self.shape {
+            GeofenceShape::Circle { radius_m, .. } => self.hysteresis_m >= radius_m,
+            GeofenceShape::Polygon { .. } => false,
+        };
+        if !(0.0..=MAX_HYSTERESIS_M).contains(&self.hysteresis_m) || too_wide {
+            return Err(GeofenceError::InvalidHysteresis(self.hysteresis_m));
+        }
+        Ok(())
+    }
+}
+
+/// Which way a boundary was crossed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Transition {
+    Enter,
+    Exit,
+}
+
+impl Transition {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Transition::Enter => "enter",
+            Transition::Exit => "exit",
+        }
+    }
+}
+
+impl std::str::FromStr for Transition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "enter" => Ok(Transition::Enter),
+            "exit" => Ok(Transition::Exit),
+            _ => Err(format!(
+                "Unknown transition {:?} (expected enter or exit)",
+                s
+            )),
+        }
+    }
+}
+
+/// A device crossing a geofence's boundary, as listed by `GET /api/geofence-events`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GeofenceEvent {
+    pub event_id: i64,
+    pub geofence_id: i64,
+    /// The geofence's name when the event was recorded.
+    pub geofence_name: String,
+    pub device_id: String,
+    pub transition: Transition,
+    /// The fix that crossed the boundary; its `timestamp` is when it happened.
+    pub fix: StoredData,
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// Query parameters of `GET /api/geofence-events`. Every filter is optional.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct GeofenceEventQuery {
+    /// Only events of this device.
+    pub device_id: Option<String>,
+    /// Only events of this geofence.
+    pub geofence_id: Option<i64>,
+    /// How many to list, clamped to `MAX_EVENT_LIMIT`.
+    pub limit: Option<usize>,
+}
+
+impl GeofenceEventQuery {
+    /// The effective number of events to list.
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_EVENT_LIMIT)
+            .clamp(1, MAX_EVENT_LIMIT)
+    }
+}
+
+// --- Engine ---
+
+/// Checks newly stored `fixes` against their devices' geofences, oldest first, and records
+/// an event for every boundary crossed. Returns the events recorded.
+///
+/// Fixes older than the last one a geofence was checked against are skipped, so buffered
+/// points arriving late can't make the device flap. So are fixes flagged for clock skew,
+/// whose time can't be trusted.
+#[cfg(feature = "ssr")]
+pub fn on_fixes(
+    store: &dyn DataStore,
+    fixes: &[&StoredData],
+    now: DateTime<Utc>,
+) -> Result<Vec<GeofenceEvent>, StorageError> {
+    let mut fixes: Vec<&StoredData> = fixes
+        .iter()
+        .copied()
+        .filter(|fix| !fix.clock_skew_flagged)
+        .collect();
+    fixes.sort_by_key(|fix| fix.timestamp);
+
+    let mut geofences: BTreeMap<&str, Vec<Geofence>> = BTreeMap::new();
+    let mut events = Vec::new();
+    for fix in fixes {
+        if !geofences.contains_key(fix.id.as_str()) {
+            geofences.insert(&fix.id, store.geofences(&fix.id)?);
+        }
+        let point = GeoPoint {
+            latitude: fix.latitude_deg,
+            longitude: fix.longitude_deg,
+        };
+        for geofence in geofences.get_mut(fix.id.as_str()).into_iter().flatten() {
+            if geofence.checked_at.is_some_and(|at| at >= fix.timestamp) {
+                continue;
+            }
+            let distance = geofence.shape.signed_distance_m(point);
+            let inside = match geofence.inside {
+                None => distance <= 0.0,
+                Some(true) => distance < geofence.hysteresis_m,
+                Some(false) => distance <= -geofence.hysteresis_m,
+            };
+            let event = match geofence.inside {
+                Some(was_inside) if was_inside != inside => Some(GeofenceEvent {
+                    event_id: 0,
+                    geofence_id: geofence.geofence_id,
+                    geofence_name: geofence.name.clone(),
+                    device_id: fix.id.clone(),
+                    transition: if inside {
+                        Transition::Enter
+                    } else {
+                        Transition::Exit
+                    },
+                    fix: fix.clone(),
+                    recorded_at: now,
+                }),
+                _ => None,
+            };
+            events.extend(store.record_geofence_check(
+                geofence.geofence_id,
+                inside,
+                fix.timestamp,
+                event.as_ref(),
+            )?);
+            geofence.inside = Some(inside);
+            geofence.checked_at = Some(fix.timestamp);
+        }
+    }
+    Ok(events)
+}
+
+// --- Errors ---
+
+/// Why a geofence request was refused.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GeofenceError {
+    /// A blank name, or one longer than `MAX_NAME_LEN`.
+    InvalidName,
+    /// A latitude outside -90..=90 or longitude outside -180..=180.
+    InvalidCoordinate(GeoPoint),
+    /// A circle radius outside 0..=`MAX_RADIUS_M` metres.
+    InvalidRadius(f64),
+    /// A polygon with fewer than 3 or more than `MAX_POLYGON_POINTS` corners.
+    InvalidPolygon(usize),
+    /// A margin outside 0..=`MAX_HYSTERESIS_M`, or as wide as the circle.
+    InvalidHysteresis(f64),
+    /// The device has no geofence with this id.
+    NotFound(i64),
+}
+
+impl GeofenceError {
+    /// Stable, machine-readable identifier returned in error responses.
+    pub fn code(&self) -> &'static str {
+        match self {
+            GeofenceError::InvalidName => "invalid_geofence_name",
+            GeofenceError::InvalidCoordinate(_) => "invalid_coordinate",
+            GeofenceError::InvalidRadius(_) => "invalid_radius",
+            GeofenceError::InvalidPolygon(_) => "invalid_polygon",
+            GeofenceError::InvalidHysteresis(_) => "invalid_hysteresis",
+            GeofenceError::NotFound(_) => "geofence_not_found",
+        }
+    }
+}
+
+impl fmt::Display for GeofenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeofenceError::InvalidName => {
+                write!(f, "Geofence name must be 1 to {} characters", MAX_NAME_LEN)
+            }
+            GeofenceError::InvalidCoordinate(point) => write!(
+                f,
+                "Invalid coordinate {}, {}: latitude must be -90 to 90 and longitude -180 to 180",
+                point.latitude, point.longitude
+            ),
+            GeofenceError::InvalidRadius(radius) => write!(
+                f,
+                "Invalid radius {} m: use more than 0 and at most {} m",
+                radius, MAX_RADIUS_M
+            ),
+            GeofenceError::InvalidPolygon(points) => write!(
+                f,
+                "A polygon needs 3 to {} points, not {}",
+                MAX_POLYGON_POINTS, points
+            ),
+            GeofenceError::InvalidHysteresis(margin) => write!(
+                f,
+                "Invalid hysteresis {} m: use 0 to {} m, and less than a circle's radius",
+                margin, MAX_HYSTERESIS_M
+            ),
+            GeofenceError::NotFound(id) => write!(f, "Geofence {} does not exist", id),
+        }
+    }
+}
+
+impl std::error::Error for GeofenceError {}
+
+// --- Tests ---
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{at, backends, device, household, point};
+
+    const HOME: GeoPoint = GeoPoint {
+        latitude: 13.75,
+        longitude: 100.5,
+    };
+
+    /// The point `metres` due north of `HOME` (south if negative).
+    fn north(metres: f64) -> GeoPoint {
+        GeoPoint {
+            latitude: HOME.latitude + (metres / EARTH_RADIUS_M).to_degrees(),
+            longitude: HOME.longitude,
+        }
+    }
+
+    /// A fix from "A" `minutes` after `at(0)`, `metres` north of `HOME`.
+    fn fix(minutes: i64, metres: f64) -> StoredData {
+        StoredData {
+            latitude_deg: north(metres).latitude,
+            ..point("A", minutes)
+        }
+    }
+
+    fn home(store: &dyn DataStore) -> Geofence {
+        store
+            .create_geofence(&Geofence {
+                geofence_id: 0,
+                device_id: "A".to_string(),
+                name: "Home".to_string(),
+                shape: GeofenceShape::Circle {
+                    center: HOME,
+                    radius_m: 100.0,
+                },
+                hysteresis_m: 15.0,
+                created_at: at(0),
+                inside: None,
+                checked_at: None,
+            })
+            .unwrap()
+    }
+
+    fn transitions(events: &[GeofenceEvent]) -> Vec<Transition> {
+        events.iter().map(|event| event.transition).collect()
+    }
+
+    #[test]
+    fn a_circle_is_measured_from_its_edge() {
+        let circle = GeofenceShape::Circle {
+            center: HOME,
+            radius_m: 100.0,
+        };
+        assert!((circle.signed_distance_m(HOME) + 100.0).abs() < 0.5);
+        assert!((circle.signed_distance_m(north(60.0)) + 40.0).abs() < 0.5);
+        assert!((circle.signed_distance_m(north(-250.0)) - 150.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn a_polygon_knows_inside_from_outside() {
+        // A square 200 m across, centred on HOME
+        let half = (100.0 / EARTH_RADIUS_M).to_degrees();
+        let wide = half / HOME.latitude.to_radians().cos();
+        let corner = |north: f64, east: f64| GeoPoint {
+            latitude: HOME.latitude + north * half,
+            longitude: HOME.longitude + east * wide,
+        };
+        let square = GeofenceShape::Polygon {
+            points: vec![
+                corner(-1.0, -1.0),
+                corner(-1.0, 1.0),
+                corner(1.0, 1.0),
+                corner(1.0, -1.0),
+            ],
+        };
+        assert!((square.signed_distance_m(HOME) + 100.0).abs() < 0.5);
+        assert!((square.signed_distance_m(north(70.0)) + 30.0).abs() < 0.5);
+        assert!((square.signed_distance_m(north(130.0)) - 30.0).abs() < 0.5);
+        let east = GeoPoint {
+            latitude: HOME.latitude,
+            longitude: HOME.longitude + 3.0 * wide,
+        };
+        assert!((square.signed_distance_m(east) - 200.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn crossing_takes_the_hysteresis_margin_both_ways() {
+        for (name, store) in backends() {
+            let (ann, _) = household(store.as_ref(), "ann");
+            store.create_device(&device("A", ann)).unwrap();
+            home(store.as_ref());
+
+            let mut seen = Vec::new();
+            for (minutes, metres) in [(1, 0.0), (2, 110.0), (3, 120.0), (4, 90.0), (5, 80.0)] {
+                let events = on_fixes(store.as_ref(), &[&fix(minutes, metres)], at(10)).unwrap();
+                seen.push(transitions(&events));
+            }
+            assert_eq!(
+                seen,
+                vec![
+                    vec![],
+                    // 10 m out is within the margin; 20 m out is past it
+                    vec![],
+                    vec![Transition::Exit],
+                    // Likewise on the way back in
+                    vec![],
+                    vec![Transition::Enter],
+                ],
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_late_buffered_fix_is_not_checked() {
+        for (name, store) in backends() {
+            let (ann, _) = household(store.as_ref(), "ann");
+            store.create_device(&device("A", ann)).unwrap();
+            let geofence = home(store.as_ref());
+
+            let events = on_fixes(store.as_ref(), &[&fix(1, 0.0), &fix(5, 500.0)], at(10)).unwrap();
+            assert_eq!(transitions(&events), vec![Transition::Exit], "{name}");
+
+            // Taken at home before the exit, but only uploaded now
+            let late = on_fixes(store.as_ref(), &[&fix(3, 0.0)], at(11)).unwrap();
+            assert!(late.is_empty(), "{name}");
+            let stored = store.geofence(geofence.geofence_id).unwrap().unwrap();
+            assert_eq!(stored.inside, Some(false), "{name}");
+            assert_eq!(stored.checked_at, Some(at(5)), "{name}");
+
+            // Out of order within one batch, they are still taken oldest first
+            let events = on_fixes(store.as_ref(), &[&fix(7, 500.0), &fix(6, 0.0)], at(12)).unwrap();
+            assert_eq!(
+                transitions(&events),
+                vec![Transition::Enter, Transition::Exit],
+                "{name}"
+            );
+        }
+    }
+}
@@ -1,54 +1,1029 @@
+// The `TableRow` derive renders `Option` fields with `options={()}`, which clippy flags
+// at the derive site; the lint can't be scoped to the generated impl, so allow it here.
+#![allow(clippy::unused_unit)]
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Utc};
+use leptos::prelude::*;
 use leptos_struct_table::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 // --- Data Structures ---
 
 /// The structure of the JSON payload we receive from the ESP32.
-#[derive(Deserialize, Debug)]
+///
+/// `date` (`YYYY-MM-DD`) and `time` (`HH:MM:SS`) are the device's local wall-clock time;
+/// `tz` optionally names its zone as a UTC offset (`+07:00`) or IANA name (`Asia/Bangkok`).
+/// `sequence` (for payload versions that don't carry one) and `idempotency_key` let the
+/// server recognise a retried post; see `dedup`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct IncomingData {
     pub id: String,
     pub payload: String,
     pub date: String,
     pub time: String,
+    #[serde(default)]
+    pub tz: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
 }
 
 impl IncomingData {
-    /// A `Result` containing a tuple of (Longitude, Latitude, Battery) as u16,
-    /// or a boxed error if parsing fails or the payload length is incorrect.
-    pub fn parse_hex_payload(&self) -> Result<(u16, u16, u8), Box<dyn std::error::Error>> {
-        // Expected length: 4 (Long) + 4 (Lat) + 2 (Batt) = 10 hex digits
-        if self.payload.len() != 10 {
+    /// Resolves `date`/`time` to a UTC instant, using the device's own `tz` if it sent one
+    /// and `default_tz` otherwise.
+    ///
+    /// Fails on impossible dates/times and on local times skipped or repeated by a DST change.
+    #[cfg(feature = "ssr")]
+    pub fn parse_timestamp(
+        &self,
+        default_tz: DeviceTimeZone,
+    ) -> Result<DateTime<Utc>, PayloadError> {
+        let invalid = |field: &'static str, value: &str| PayloadError::InvalidTimestamp {
+            field,
+            value: value.to_string(),
+        };
+
+        let date = chrono::NaiveDate::parse_from_str(&self.date, "%Y-%m-%d")
+            .map_err(|_| invalid("date", &self.date))?;
+        let time = chrono::NaiveTime::parse_from_str(&self.time, "%H:%M:%S")
+            .map_err(|_| invalid("time", &self.time))?;
+        let tz = match &self.tz {
+            Some(tz) => tz
+                .parse::<DeviceTimeZone>()
+                .map_err(|_| invalid("tz", tz))?,
+            None => default_tz,
+        };
+
+        tz.to_utc(&date.and_time(time))
+            .ok_or_else(|| invalid("time", &self.time))
+    }
+
+    /// The post's sequence number: from the payload if its version carries one,
+    /// otherwise the `sequence` field. Fails if both are given and disagree.
+    pub fn resolve_sequence(&self, decoded: &DecodedPayload) -> Result<Option<u32>, PayloadError> {
+        match (decoded.sequence, self.sequence) {
+            (Some(payload), Some(field)) if payload != field => {
+                Err(PayloadError::SequenceMismatch { payload, field })
+            }
+            (payload, field) => Ok(payload.or(field)),
+        }
+    }
+
+    /// Decodes the hex `payload` string into its fields.
+    ///
+    /// # Payload versions
+    ///
+    /// * **Version 0** (legacy): `LLLL AAAA BB` — 4 hex longitude, 4 hex latitude, 2 hex battery %.
+    ///   Sent bare as exactly 10 characters, or prefixed with the version byte `00`.
+    /// * **Version 1**: `01` + the version 0 fields + a 2 hex presence bitmask, followed by
+    ///   each optional field whose bit is set, in bit order:
+    ///
+    ///   | Bit | Field | Hex digits | Encoding |
+    ///   |---|---|---|---|
+    ///   | 0 | altitude | 4 | `i16`, metres above sea level |
+    ///   | 1 | hdop | 2 | `u8`, tenths |
+    ///   | 2 | satellites | 2 | `u8`, count |
+    ///   | 3 | speed | 4 | `u16`, tenths of km/h |
+    ///   | 4 | sequence | 8 | `u32`, per-device counter |
+    ///
+    /// Any other leading version byte is rejected, as is a battery above 100 %.
+    pub fn parse_hex_payload(&self) -> Result<DecodedPayload, PayloadError> {
+        let mut reader = HexReader::new(&self.payload);
+
+        // Legacy layout without a version byte
+        if self.payload.len() == V0_LEN {
+            return decode_v0(&mut reader);
+        }
+
+        // Shorter than the smallest versioned payload: most likely a truncated legacy one
+        if self.payload.len() < V0_LEN + 2 {
+            reader.expect_remaining(V0_LEN)?;
+        }
+        let version = reader.u8("version")?;
+        match version {
+            0 => decode_v0(&mut reader),
+            1 => decode_v1(&mut reader),
+            v => Err(PayloadError::UnsupportedVersion(v)),
+        }
+    }
+}
+
+// --- Payload Errors ---
+
+/// Why a payload was rejected.
+///
+/// `WrongLength` and `InvalidHex` usually point at transport corruption (a truncated
+/// or garbled string), while `UnsupportedVersion`, `UnknownFields`, `BatteryOutOfRange`,
+/// `InvalidTimestamp` and `SequenceMismatch` point at firmware producing data the server
+/// doesn't accept.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PayloadError {
+    /// The payload doesn't have the number of hex digits its version requires.
+    WrongLength { expected: usize, actual: usize },
+    /// A field contains something other than hex digits.
+    InvalidHex { field: &'static str, value: String },
+    /// The leading version byte doesn't match any known decoder.
+    UnsupportedVersion(u8),
+    /// A version 1 presence bitmask sets bits for fields that don't exist.
+    UnknownFields(u8),
+    /// The battery percentage is above 100.
+    BatteryOutOfRange(u8),
+    /// The `date`, `time` or `tz` field next to the payload doesn't describe a real instant.
+    InvalidTimestamp { field: &'static str, value: String },
+    /// The payload and the `sequence` field carry different sequence numbers.
+    SequenceMismatch { payload: u32, field: u32 },
+}
+
+impl PayloadError {
+    /// Stable, machine-readable identifier returned to the device in error responses.
+    pub fn code(&self) -> &'static str {
+        match self {
+            PayloadError::WrongLength { .. } => "wrong_length",
+            PayloadError::InvalidHex { .. } => "invalid_hex",
+            PayloadError::UnsupportedVersion(_) => "unsupported_version",
+            PayloadError::UnknownFields(_) => "unknown_fields",
+            PayloadError::BatteryOutOfRange(_) => "battery_out_of_range",
+            PayloadError::InvalidTimestamp { .. } => "invalid_timestamp",
+            PayloadError::SequenceMismatch { .. } => "sequence_mismatch",
+        }
+    }
+
+    /// The payload field the error was found in, if it concerns a single field.
+    pub fn field(&self) -> Option<&'static str> {
+        match self {
+            PayloadError::WrongLength { .. } => None,
+            PayloadError::InvalidHex { field, .. } => Some(field),
+            PayloadError::UnsupportedVersion(_) => Some("version"),
+            PayloadError::UnknownFields(_) => Some("flags"),
+            PayloadError::BatteryOutOfRange(_) => Some("battery"),
+            PayloadError::InvalidTimestamp { field, .. } => Some(field),
+            PayloadError::SequenceMismatch { .. } => Some("sequence"),
+        }
+    }
+}
+
+impl std::fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayloadError::WrongLength { expected, actual } => write!(
+                f,
+                "Payload must be exactly {} characters long, got {}",
+                expected, actual
+            ),
+            PayloadError::InvalidHex { field, value } => {
+                write!(f, "Field '{}' is not valid hex: {:?}", field, value)
+            }
+            PayloadError::UnsupportedVersion(v) => write!(f, "Unsupported payload version {}", v),
+            PayloadError::UnknownFields(bits) => {
+                write!(f, "Unknown optional field bits {:#04x}", bits)
+            }
+            PayloadError::BatteryOutOfRange(b) => {
+                write!(f, "Battery must be between 0 and 100 %, got {}", b)
+            }
+            PayloadError::InvalidTimestamp { field, value } => {
+                write!(f, "Field '{}' is not a valid date/time: {:?}", field, value)
+            }
+            PayloadError::SequenceMismatch { payload, field } => write!(
+                f,
+                "Payload sequence number {} doesn't match field 'sequence' ({})",
+                payload, field
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+// --- Payload Decoding ---
+
+/// Length of the version 0 body: 4 (Long) + 4 (Lat) + 2 (Batt) = 10 hex digits
+const V0_LEN: usize = 10;
+
+/// Version 1 optional field presence bits, and (bit, hex digits) for each in bit order.
+const V1_ALTITUDE: u8 = 1 << 0;
+const V1_HDOP: u8 = 1 << 1;
+const V1_SATELLITES: u8 = 1 << 2;
+const V1_SPEED: u8 = 1 << 3;
+const V1_SEQUENCE: u8 = 1 << 4;
+const V1_OPTIONAL_FIELDS: [(u8, usize); 5] = [
+    (V1_ALTITUDE, 4),
+    (V1_HDOP, 2),
+    (V1_SATELLITES, 2),
+    (V1_SPEED, 4),
+    (V1_SEQUENCE, 8),
+];
+const V1_KNOWN_FIELDS: u8 = V1_ALTITUDE | V1_HDOP | V1_SATELLITES | V1_SPEED | V1_SEQUENCE;
+
+/// The fields decoded from a tracker payload, whatever its version.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DecodedPayload {
+    pub version: u8,
+    pub longitude: u16,
+    pub latitude: u16,
+    pub battery: u8,
+    pub altitude: Option<i16>,
+    pub hdop: Option<f32>,
+    pub satellites: Option<u8>,
+    pub speed: Option<f32>,
+    pub sequence: Option<u32>,
+}
+
+fn decode_v0(reader: &mut HexReader) -> Result<DecodedPayload, PayloadError> {
+    reader.expect_remaining(V0_LEN)?;
+    decode_base(reader, 0)
+}
+
+fn decode_v1(reader: &mut HexReader) -> Result<DecodedPayload, PayloadError> {
+    // The base fields plus the presence bitmask must be there before we know the full length
+    if reader.remaining() < V0_LEN + 2 {
+        return Err(PayloadError::WrongLength {
+            expected: reader.pos + V0_LEN + 2,
+            actual: reader.payload.len(),
+        });
+    }
+    let mut decoded = decode_base(reader, 1)?;
+
+    let flags = reader.u8("flags")?;
+    if flags & !V1_KNOWN_FIELDS != 0 {
+        return Err(PayloadError::UnknownFields(flags & !V1_KNOWN_FIELDS));
+    }
+    let optional_len = V1_OPTIONAL_FIELDS
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, digits)| digits)
+        .sum();
+    reader.expect_remaining(optional_len)?;
+
+    if flags & V1_ALTITUDE != 0 {
+        decoded.altitude = Some(reader.u16("altitude")? as i16);
+    }
+    if flags & V1_HDOP != 0 {
+        decoded.hdop = Some(reader.u8("hdop")? as f32 / 10.0);
+    }
+    if flags & V1_SATELLITES != 0 {
+        decoded.satellites = Some(reader.u8("satellites")?);
+    }
+    if flags & V1_SPEED != 0 {
+        decoded.speed = Some(reader.u16("speed")? as f32 / 10.0);
+    }
+    if flags & V1_SEQUENCE != 0 {
+        decoded.sequence = Some(reader.u32("sequence")?);
+    }
+    Ok(decoded)
+}
+
+/// Longitude, latitude and battery: the fields shared by every version.
+fn decode_base(reader: &mut HexReader, version: u8) -> Result<DecodedPayload, PayloadError> {
+    let longitude = reader.u16("longitude")?;
+    let latitude = reader.u16("latitude")?;
+    let battery = reader.u8("battery")?;
+    if battery > 100 {
+        return Err(PayloadError::BatteryOutOfRange(battery));
+    }
+
+    Ok(DecodedPayload {
+        version,
+        longitude,
+        latitude,
+        battery,
+        ..Default::default()
+    })
+}
+
+/// Reads fixed-width big-endian hex fields from the front of a payload string.
+struct HexReader<'a> {
+    payload: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> HexReader<'a> {
+    fn new(payload: &'a str) -> Self {
+        Self {
+            payload: payload.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        self.payload.len() - self.pos
+    }
+
+    /// Fails with `WrongLength` unless exactly `digits` characters are left.
+    fn expect_remaining(&self, digits: usize) -> Result<(), PayloadError> {
+        if self.remaining() != digits {
+            return Err(PayloadError::WrongLength {
+                expected: self.pos + digits,
+                actual: self.payload.len(),
+            });
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, field: &'static str, digits: usize) -> Result<u32, PayloadError> {
+        let bytes =
+            self.payload
+                .get(self.pos..self.pos + digits)
+                .ok_or(PayloadError::WrongLength {
+                    expected: self.pos + digits,
+                    actual: self.payload.len(),
+                })?;
+        self.pos += digits;
+
+        // `from_str_radix` alone would also accept a leading '+'
+        let invalid = || PayloadError::InvalidHex {
+            field,
+            value: String::from_utf8_lossy(bytes).into_owned(),
+        };
+        if !bytes.iter().all(u8::is_ascii_hexdigit) {
+            return Err(invalid());
+        }
+        let hex = std::str::from_utf8(bytes).map_err(|_| invalid())?;
+        u32::from_str_radix(hex, 16).map_err(|_| invalid())
+    }
+
+    fn u8(&mut self, field: &'static str) -> Result<u8, PayloadError> {
+        Ok(self.take(field, 2)? as u8)
+    }
+
+    fn u16(&mut self, field: &'static str) -> Result<u16, PayloadError> {
+        Ok(self.take(field, 4)? as u16)
+    }
+
+    fn u32(&mut self, field: &'static str) -> Result<u32, PayloadError> {
+        self.take(field, 8)
+    }
+}
+
+// --- Time Zones ---
+
+/// The zone a device's local `date`/`time` are expressed in.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceTimeZone {
+    /// A fixed UTC offset such as `+07:00`.
+    Offset(FixedOffset),
+    /// An IANA zone such as `Asia/Bangkok`, with its DST rules.
+    Named(chrono_tz::Tz),
+}
+
+#[cfg(feature = "ssr")]
+impl DeviceTimeZone {
+    /// Converts a local wall-clock time in this zone to UTC.
+    /// `None` if the local time doesn't exist or is ambiguous (DST transitions).
+    pub fn to_utc(&self, local: &NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            DeviceTimeZone::Offset(offset) => offset
+                .from_local_datetime(local)
+                .single()
+                .map(|dt| dt.with_timezone(&Utc)),
+            DeviceTimeZone::Named(tz) => tz
+                .from_local_datetime(local)
+                .single()
+                .map(|dt| dt.with_timezone(&Utc)),
+        }
+    }
+
+    /// The hour (0 to 23) the device clock shows at `at`.
+    pub fn local_hour(&self, at: DateTime<Utc>) -> u32 {
+        use chrono::Timelike;
+        match self {
+            DeviceTimeZone::Offset(offset) => at.with_timezone(offset).hour(),
+            DeviceTimeZone::Named(tz) => at.with_timezone(tz).hour(),
+        }
+    }
+
+    /// The wall-clock time the device clock shows at `at`.
+    pub fn local_time(&self, at: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            DeviceTimeZone::Offset(offset) => at.with_timezone(offset).naive_local(),
+            DeviceTimeZone::Named(tz) => at.with_timezone(tz).naive_local(),
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl std::str::FromStr for DeviceTimeZone {
+    type Err = String;
+
+    /// Parses `+HH:MM` / `-HH:MM` as a fixed offset and anything else as an IANA zone name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with('+') || s.starts_with('-') {
+            s.parse::<FixedOffset>()
+                .map(DeviceTimeZone::Offset)
+                .map_err(|e| format!("Invalid UTC offset {:?}: {}", s, e))
+        } else {
+            s.parse::<chrono_tz::Tz>()
+                .map(DeviceTimeZone::Named)
+                .map_err(|e| format!("Invalid time zone {:?}: {}", s, e))
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Display for DeviceTimeZone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceTimeZone::Offset(offset) => write!(f, "{}", offset),
+            DeviceTimeZone::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+/// Fixed-point encoding of the 4-hex-digit longitude/latitude fields.
+///
+/// Each raw `u16` is a linear position inside a bounding box: `0x0000` maps to the
+/// minimum and `0xFFFF` to the maximum, so the resolution is `(max - min) / 65535` degrees.
+/// The default box covers the whole globe (~600 m steps); a tighter box around the area
+/// the pet actually roams gives proportionally finer positions.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CoordinateEncoding {
+    pub min_longitude: f64,
+    pub min_latitude: f64,
+    pub max_longitude: f64,
+    pub max_latitude: f64,
+}
+
+impl Default for CoordinateEncoding {
+    fn default() -> Self {
+        Self {
+            min_longitude: -180.0,
+            min_latitude: -90.0,
+            max_longitude: 180.0,
+            max_latitude: 90.0,
+        }
+    }
+}
+
+impl CoordinateEncoding {
+    /// Maps raw (longitude, latitude) fields to WGS84 decimal degrees.
+    pub fn decode(&self, raw_longitude: u16, raw_latitude: u16) -> (f64, f64) {
+        let scale =
+            |raw: u16, min: f64, max: f64| min + (raw as f64 / u16::MAX as f64) * (max - min);
+        (
+            scale(raw_longitude, self.min_longitude, self.max_longitude),
+            scale(raw_latitude, self.min_latitude, self.max_latitude),
+        )
+    }
+
+    /// Checks that the box lies within WGS84 bounds and has a positive size.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(-180.0..=180.0).contains(&self.min_longitude)
+            || !(-180.0..=180.0).contains(&self.max_longitude)
+            || !(-90.0..=90.0).contains(&self.min_latitude)
+            || !(-90.0..=90.0).contains(&self.max_latitude)
+            || self.min_longitude >= self.max_longitude
+            || self.min_latitude >= self.max_latitude
+        {
             return Err(format!(
-                "Payload must be exactly 10 characters long, got {}",
-                self.payload.len()
-            )
-            .into());
+                "Bounding box {},{},{},{} is not a valid WGS84 area",
+                self.min_longitude, self.min_latitude, self.max_longitude, self.max_latitude
+            ));
         }
+        Ok(())
+    }
+}
 
-        // 1. Longitude (first 4 hex digits)
-        let long_hex = &self.payload[0..4];
-        let longitude = u16::from_str_radix(long_hex, 16)?;
+impl std::str::FromStr for CoordinateEncoding {
+    type Err = String;
 
-        // 2. Latitude (next 4 hex digits)
-        let lat_hex = &self.payload[4..8];
-        let latitude = u16::from_str_radix(lat_hex, 16)?;
+    /// Parses a bounding box written as `min_lon,min_lat,max_lon,max_lat`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid bounding box value: {}", e))?;
 
-        // 3. Battery (last 2 hex digits)
-        let batt_hex = &self.payload[8..10];
-        let battery = u8::from_str_radix(batt_hex, 16)?;
+        let [min_longitude, min_latitude, max_longitude, max_latitude] = values[..] else {
+            return Err(format!(
+                "Bounding box must have 4 values (min_lon,min_lat,max_lon,max_lat), got {}",
+                values.len()
+            ));
+        };
 
-        Ok((longitude, latitude, battery))
+        let encoding = Self {
+            min_longitude,
+            min_latitude,
+            max_longitude,
+            max_latitude,
+        };
+        encoding.validate()?;
+        Ok(encoding)
     }
 }
 
 /// The structure we store in our "database" and send to the frontend.
+///
+/// `longitude`/`latitude` are the raw fields as sent by the tracker;
+/// `longitude_deg`/`latitude_deg` are the same position decoded to WGS84 degrees.
+/// The trailing optional fields are only filled in by payload versions that carry them.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TableRow)]
 #[table(impl_vec_data_provider)]
 #[table(classes_provider = "TailwindClassesPreset")]
 pub struct StoredData {
     pub id: String,
+    #[table(skip)]
     pub longitude: u16,
+    #[table(skip)]
     pub latitude: u16,
+    #[serde(default)]
+    #[table(title = "Longitude", format(precision = 6usize))]
+    pub longitude_deg: f64,
+    #[serde(default)]
+    #[table(title = "Latitude", format(precision = 6usize))]
+    pub latitude_deg: f64,
     pub battery: u8,
-    pub timestamp: String,
+    /// When the fix was taken, as reported by the device. Serialized as RFC 3339.
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    #[table(title = "Timestamp", renderer = "LocalTimestampCellRenderer")]
+    pub timestamp: DateTime<Utc>,
+    /// When the server received the fix, by the server's own clock.
+    #[serde(default)]
+    #[table(title = "Received", renderer = "LocalTimestampCellRenderer")]
+    pub received_at: DateTime<Utc>,
+    /// `timestamp - received_at` in seconds: positive when the device clock runs ahead.
+    #[serde(default)]
+    #[table(title = "Clock skew", renderer = "ClockSkewCellRenderer")]
+    pub clock_skew_secs: i64,
+    /// Set when `clock_skew_secs` exceeded the server's threshold, i.e. `timestamp` is suspect.
+    #[serde(default)]
+    #[table(skip)]
+    pub clock_skew_flagged: bool,
+    #[serde(default)]
+    #[table(skip)]
+    pub payload_version: u8,
+    #[serde(default)]
+    #[table(title = "Altitude (m)", none_value = "-")]
+    pub altitude: Option<i16>,
+    #[serde(default)]
+    #[table(title = "HDOP", none_value = "-", format(precision = 1usize))]
+    pub hdop: Option<f32>,
+    #[serde(default)]
+    #[table(title = "Satellites", none_value = "-")]
+    pub satellites: Option<u8>,
+    #[serde(default)]
+    #[table(title = "Speed (km/h)", none_value = "-", format(precision = 1usize))]
+    pub speed: Option<f32>,
+    #[serde(default)]
+    #[table(title = "Seq", none_value = "-")]
+    pub sequence: Option<u32>,
+    #[serde(default)]
+    #[table(skip)]
+    pub idempotency_key: Option<String>,
+}
+
+// --- Query Types ---
+
+/// Page size used by `GET /api/data` when no `limit` is given.
+pub const DEFAULT_PAGE_SIZE: usize = 500;
+/// Largest page `GET /api/data` will return, whatever `limit` asks for.
+pub const MAX_PAGE_SIZE: usize = 5000;
+
+/// Order of the points returned by `GET /api/data`, by device timestamp.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Query parameters of `GET /api/data`. Every filter is optional.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct DataQuery {
+    /// Only points from this device.
+    pub device_id: Option<String>,
+    /// Only points taken at or after this instant.
+    pub from: Option<DateTime<Utc>>,
+    /// Only points taken before this instant.
+    pub to: Option<DateTime<Utc>>,
+    /// Page size, clamped to `MAX_PAGE_SIZE`.
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// The `next` token of the previous page, to continue where it left off.
+    pub cursor: Option<String>,
+}
+
+impl DataQuery {
+    /// The effective page size.
+    pub fn page_size(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+/// One page of `GET /api/data` results.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct DataPage {
+    pub data: Vec<StoredData>,
+    /// Pass back as `cursor` to fetch the following page; `None` on the last page.
+    pub next: Option<String>,
+}
+
+/// Table cell showing a UTC timestamp in the viewer's local time zone.
+#[component]
+#[allow(unused_variables)]
+fn LocalTimestampCellRenderer(
+    class: String,
+    value: Signal<DateTime<Utc>>,
+    row: RwSignal<StoredData>,
+    index: usize,
+) -> impl IntoView {
+    let local = move || {
+        value
+            .get()
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    };
+    view! { <td class=class>{local}</td> }
+}
+
+/// Table cell showing a clock skew, highlighted when it was flagged as beyond the threshold.
+#[component]
+#[allow(unused_variables)]
+fn ClockSkewCellRenderer(
+    class: String,
+    value: Signal<i64>,
+    row: RwSignal<StoredData>,
+    index: usize,
+) -> impl IntoView {
+    let flagged = move || row.read().clock_skew_flagged;
+    view! {
+        <td class=class>
+            <span class:text-red-600=flagged class:font-semibold=flagged>
+                {move || format!("{:+} s", value.get())}
+            </span>
+            <Show when=flagged>
+                <i class="fas fa-exclamation-triangle ml-1 text-red-500" title="Device clock is off"></i>
+            </Show>
+        </td>
+    }
+}
+
+/// Table row that carries its index as `data-row`, so the page can be scrolled to it.
+#[allow(non_snake_case, unused_variables)]
+pub fn StoredDataRowRenderer(
+    class: Signal<String>,
+    row: RwSignal<StoredData>,
+    index: usize,
+    selected: Signal<bool>,
+    on_select: EventHandler<web_sys::MouseEvent>,
+) -> impl IntoView {
+    view! {
+        <tr class=class data-row=index on:click=move |mouse_event| on_select.run(mouse_event)>
+            {TableRow::render_row(row, index)}
+        </tr>
+    }
+}
+
+/// UTC offset of the free-form `"{date} {time}"` timestamps stored before timestamps were parsed.
+/// The firmware's `sync_time` hard-coded `ICT-7`, so those are all Indochina Time.
+const LEGACY_TIMESTAMP_OFFSET_SECS: i32 = 7 * 3600;
+
+/// Accepts RFC 3339 as well as the legacy `"YYYY-MM-DD HH:MM:SS"` strings still found in
+/// write-ahead logs written before timestamps were parsed.
+fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = String::deserialize(deserializer)?;
+    if let Ok(dt) = DateTime::parse_from_rfc3339(&raw) {
+        return Ok(dt.with_timezone(&Utc));
+    }
+
+    let legacy_offset = FixedOffset::east_opt(LEGACY_TIMESTAMP_OFFSET_SECS).unwrap();
+    NaiveDateTime::parse_from_str(&raw, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .and_then(|naive| legacy_offset.from_local_datetime(&naive).single())
+        .map(|dt| dt.with_timezone(&Utc))
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid timestamp {:?}", raw)))
+}
+
+// --- Tests ---
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    fn incoming(payload: &str) -> IncomingData {
+        IncomingData {
+            id: "A".to_string(),
+            payload: payload.to_string(),
+            date: "2025-11-01".to_string(),
+            time: "12:00:00".to_string(),
+            tz: None,
+            sequence: None,
+            idempotency_key: None,
+        }
+    }
+
+    #[test]
+    fn raw_fields_span_the_bounding_box_from_edge_to_edge() {
+        let globe = CoordinateEncoding::default();
+        assert_eq!(globe.decode(0x0000, 0x0000), (-180.0, -90.0));
+        assert_eq!(globe.decode(0xFFFF, 0xFFFF), (180.0, 90.0));
+
+        let bangkok: CoordinateEncoding = "100.3,13.5,100.9,14.1".parse().unwrap();
+        let (longitude, latitude) = bangkok.decode(0x0000, 0xFFFF);
+        assert_eq!((longitude, latitude), (100.3, 14.1));
+        let (longitude, latitude) = bangkok.decode(0x8000, 0x8000);
+        assert!((longitude - 100.6).abs() < 1e-4, "{longitude}");
+        assert!((latitude - 13.8).abs() < 1e-4, "{latitude}");
+    }
+
+    #[test]
+    fn bounding_boxes_must_be_real_areas() {
+        assert!("-180,-90,180,90".parse::<CoordinateEncoding>().is_ok());
+        for bad in [
+            "100.9,13.5,100.3,14.1",
+            "100,13,100,14",
+            "-181,-90,180,90",
+            "0,-91,10,10",
+            "1,2,3",
+            "a,b,c,d",
+        ] {
+            assert!(bad.parse::<CoordinateEncoding>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn version_0_is_read_bare_or_with_its_version_byte() {
+        let expected = DecodedPayload {
+            version: 0,
+            longitude: 0x1A2B,
+            latitude: 0x3C4D,
+            battery: 0x5F,
+            ..Default::default()
+        };
+        assert_eq!(
+            incoming("1A2B3C4D5F").parse_hex_payload(),
+            Ok(expected.clone())
+        );
+        assert_eq!(incoming("001A2B3C4D5F").parse_hex_payload(), Ok(expected));
+    }
+
+    #[test]
+    fn version_1_reads_the_optional_fields_its_flags_select() {
+        let bare = incoming("011A2B3C4D5F00").parse_hex_payload().unwrap();
+        assert_eq!(bare.version, 1);
+        assert_eq!(
+            (bare.longitude, bare.altitude, bare.sequence),
+            (0x1A2B, None, None)
+        );
+
+        // Altitude -2 m, HDOP 1.5, 9 satellites, 12.3 km/h, sequence 42
+        let full = incoming("011A2B3C4D5F1FFFFE0F09007B0000002A")
+            .parse_hex_payload()
+            .unwrap();
+        assert_eq!(full.altitude, Some(-2));
+        assert_eq!(full.hdop, Some(1.5));
+        assert_eq!(full.satellites, Some(9));
+        assert_eq!(full.speed, Some(12.3));
+        assert_eq!(full.sequence, Some(42));
+
+        // Only satellites and sequence
+        let some = incoming("011A2B3C4D5F14090000002A")
+            .parse_hex_payload()
+            .unwrap();
+        assert_eq!((some.satellites, some.sequence), (Some(9), Some(42)));
+        assert_eq!((some.altitude, some.hdop, some.speed), (None, None, None));
+    }
+
+    #[test]
+    fn every_payload_error_has_its_code_and_field() {
+        let cases = [
+            (
+                "1A2B3C4D5",
+                PayloadError::WrongLength {
+                    expected: 10,
+                    actual: 9,
+                },
+                "wrong_length",
+                None,
+            ),
+            (
+                "011A2B3C4D5F01FFF",
+                PayloadError::WrongLength {
+                    expected: 18,
+                    actual: 17,
+                },
+                "wrong_length",
+                None,
+            ),
+            (
+                "1A2B3G4D5F",
+                PayloadError::InvalidHex {
+                    field: "latitude",
+                    value: "3G4D".to_string(),
+                },
+                "invalid_hex",
+                Some("latitude"),
+            ),
+            (
+                "+A2B3C4D5F",
+                PayloadError::InvalidHex {
+                    field: "longitude",
+                    value: "+A2B".to_string(),
+                },
+                "invalid_hex",
+                Some("longitude"),
+            ),
+            (
+                "021A2B3C4D5F",
+                PayloadError::UnsupportedVersion(2),
+                "unsupported_version",
+                Some("version"),
+            ),
+            (
+                "011A2B3C4D5FE0",
+                PayloadError::UnknownFields(0xE0),
+                "unknown_fields",
+                Some("flags"),
+            ),
+            (
+                "1A2B3C4D65",
+                PayloadError::BatteryOutOfRange(101),
+                "battery_out_of_range",
+                Some("battery"),
+            ),
+        ];
+        for (payload, error, code, field) in cases {
+            assert_eq!(
+                incoming(payload).parse_hex_payload(),
+                Err(error.clone()),
+                "{payload}"
+            );
+            assert_eq!((error.code(), error.field()), (code, field), "{payload}");
+        }
+
+        let timestamp = PayloadError::InvalidTimestamp {
+            field: "date",
+            value: "2025-02-30".to_string(),
+        };
+        assert_eq!(
+            (timestamp.code(), timestamp.field()),
+            ("invalid_timestamp", Some("date"))
+        );
+        let mismatch = PayloadError::SequenceMismatch {
+            payload: 1,
+            field: 2,
+        };
+        assert_eq!(
+            (mismatch.code(), mismatch.field()),
+            ("sequence_mismatch", Some("sequence"))
+        );
+    }
+
+    #[test]
+    fn odd_lengths_and_non_hex_input_are_refused_without_panicking() {
+        for payload in [
+            "",
+            "1",
+            "1A2B3C4D5F0",
+            "1A2B3C4D5F00FF",
+            "é1A2B3C4D5",
+            "1A2B 3C4D5F",
+            "0x2B3C4D5F",
+        ] {
+            let error = incoming(payload).parse_hex_payload().unwrap_err();
+            assert!(
+                matches!(
+                    error,
+                    PayloadError::WrongLength { .. }
+                        | PayloadError::InvalidHex { .. }
+                        | PayloadError::UnsupportedVersion(_)
+                ),
+                "{payload:?}: {error:?}"
+            );
+        }
+    }
+
+    fn at_local(date: &str, time: &str, tz: &str) -> Result<DateTime<Utc>, PayloadError> {
+        IncomingData {
+            date: date.to_string(),
+            time: time.to_string(),
+            tz: Some(tz.to_string()),
+            ..incoming("1A2B3C4D5F")
+        }
+        .parse_timestamp(DeviceTimeZone::Offset(FixedOffset::east_opt(0).unwrap()))
+    }
+
+    #[test]
+    fn local_times_resolve_through_offsets_and_named_zones() {
+        let expected = "2025-11-01T05:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(at_local("2025-11-01", "12:00:00", "+07:00"), Ok(expected));
+        assert_eq!(
+            at_local("2025-11-01", "12:00:00", "Asia/Bangkok"),
+            Ok(expected)
+        );
+
+        let default_tz = "Asia/Bangkok".parse::<DeviceTimeZone>().unwrap();
+        assert_eq!(
+            incoming("1A2B3C4D5F").parse_timestamp(default_tz),
+            Ok(expected)
+        );
+
+        assert_eq!(
+            at_local("2025-02-30", "12:00:00", "+07:00"),
+            Err(PayloadError::InvalidTimestamp {
+                field: "date",
+                value: "2025-02-30".to_string()
+            })
+        );
+        assert_eq!(
+            at_local("2025-11-01", "12:00:00", "Mars/Olympus_Mons").map_err(|e| e.field()),
+            Err(Some("tz"))
+        );
+    }
+
+    #[test]
+    fn times_skipped_or_repeated_by_dst_are_refused() {
+        // Clocks in Berlin jump from 02:00 to 03:00 on 2025-03-30 and back on 2025-10-26
+        let gap = at_local("2025-03-30", "02:30:00", "Europe/Berlin");
+        let overlap = at_local("2025-10-26", "02:30:00", "Europe/Berlin");
+        for result in [gap, overlap] {
+            assert_eq!(
+                result,
+                Err(PayloadError::InvalidTimestamp {
+                    field: "time",
+                    value: "02:30:00".to_string()
+                })
+            );
+        }
+        let after_gap = at_local("2025-03-30", "03:30:00", "Europe/Berlin").unwrap();
+        assert_eq!(
+            after_gap,
+            "2025-03-30T01:30:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[test]
+    fn legacy_timestamps_are_read_as_indochina_time() {
+        let legacy: StoredData = serde_json::from_value(serde_json::json!({
+            "id": "A", "longitude": 1, "latitude": 2, "battery": 80,
+            "timestamp": "2025-11-01 12:00:00",
+        }))
+        .unwrap();
+        assert_eq!(
+            legacy.timestamp,
+            "2025-11-01T05:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        let rfc3339: StoredData = serde_json::from_value(serde_json::json!({
+            "id": "A", "longitude": 1, "latitude": 2, "battery": 80,
+            "timestamp": "2025-11-01T12:00:00+02:00",
+        }))
+        .unwrap();
+        assert_eq!(
+            rfc3339.timestamp,
+            "2025-11-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[test]
+    fn records_logged_before_receipt_times_read_as_unskewed() {
+        let old: StoredData = serde_json::from_value(serde_json::json!({
+            "id": "A", "longitude": 1, "latitude": 2, "battery": 80,
+            "timestamp": "2025-11-01T05:00:00Z",
+        }))
+        .unwrap();
+        assert_eq!(old.received_at, DateTime::<Utc>::default());
+        assert_eq!((old.clock_skew_secs, old.clock_skew_flagged), (0, false));
+
+        let flagged: StoredData = serde_json::from_value(serde_json::json!({
+            "id": "A", "longitude": 1, "latitude": 2, "battery": 80,
+            "timestamp": "2025-11-01T05:00:00Z",
+            "received_at": "2025-11-01T04:00:00Z",
+            "clock_skew_secs": 3600, "clock_skew_flagged": true,
+        }))
+        .unwrap();
+        assert_eq!(flagged.clock_skew_secs, 3600);
+        assert!(flagged.clock_skew_flagged);
+    }
+
+    #[test]
+    fn the_payload_and_field_sequence_numbers_must_agree() {
+        let decoded = incoming("011A2B3C4D5F100000002A")
+            .parse_hex_payload()
+            .unwrap();
+        let mut data = incoming("011A2B3C4D5F100000002A");
+        assert_eq!(data.resolve_sequence(&decoded), Ok(Some(42)));
+        data.sequence = Some(42);
+        assert_eq!(data.resolve_sequence(&decoded), Ok(Some(42)));
+        data.sequence = Some(7);
+        assert_eq!(
+            data.resolve_sequence(&decoded),
+            Err(PayloadError::SequenceMismatch {
+                payload: 42,
+                field: 7
+            })
+        );
+        assert_eq!(
+            data.resolve_sequence(&DecodedPayload::default()),
+            Ok(Some(7))
+        );
+    }
 }
@@ -0,0 +1,189 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[cfg(feature = "ssr")]
+use sha2::{Digest, Sha256};
+
+// --- Households ---
+//
+// Devices belong to a household rather than to a person. Everyone in a household
+// sees its devices and their points; owners also manage the devices, their keys and
+// the members. People join a household through an invitation link an owner creates.
+// Every account starts out owning a household of its own, unless it was created
+// through an invitation.
+
+/// Random bytes in an invitation token (hex-encoded, so twice as many characters).
+#[cfg(feature = "ssr")]
+const INVITATION_TOKEN_LEN: usize = 32;
+
+/// A group of people sharing a set of trackers, e.g. a family.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Household {
+    pub household_id: i64,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What a member may do in a household.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Manages devices, keys, members and invitations, and sees everything.
+    Owner,
+    /// Sees the household's devices and their points.
+    #[default]
+    Viewer,
+}
+
+impl Role {
+    /// Whether this role may do what `required` may.
+    pub fn allows(self, required: Role) -> bool {
+        self == Role::Owner || required == Role::Viewer
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Viewer => "viewer",
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "owner" => Ok(Role::Owner),
+            "viewer" => Ok(Role::Viewer),
+            _ => Err(format!("Unknown role {:?} (expected owner or viewer)", s)),
+        }
+    }
+}
+
+/// A household the signed-in user belongs to, and their role in it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Membership {
+    #[serde(flatten)]
+    pub household: Household,
+    pub role: Role,
+}
+
+/// Someone in a household, as listed by `GET /api/households/{id}/members`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Member {
+    pub user_id: i64,
+    pub username: String,
+    pub role: Role,
+    pub joined_at: DateTime<Utc>,
+}
+
+/// Body of `POST /api/households`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct NewHousehold {
+    pub name: String,
+}
+
+/// Body of `POST /api/households/{id}/invitations` and `PUT /api/households/{id}/members/{user_id}`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct RoleChange {
+    #[serde(default)]
+    pub role: Role,
+}
+
+/// A pending invitation to join a household.
+///
+/// `token` is only returned once, in the response that creates the invitation.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Invitation {
+    pub household_id: i64,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// A fresh random token for an invitation link.
+#[cfg(feature = "ssr")]
+pub fn generate_invitation_token() -> String {
+    hex::encode(rand::random::<[u8; INVITATION_TOKEN_LEN]>())
+}
+
+/// What the store keeps of an invitation token, so a leaked database can't be used to join.
+#[cfg(feature = "ssr")]
+pub fn invitation_token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// --- Errors ---
+
+/// Why a household request was refused.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HouseholdError {
+    /// The household has no `name`.
+    MissingName,
+    /// The user isn't in this household (or it doesn't exist).
+    NotFound(i64),
+    /// Only owners may do this.
+    OwnerRequired,
+    /// The change would leave the household without an owner.
+    LastOwner,
+    /// The invitation doesn't exist, has expired or was already used.
+    InvitationNotFound,
+    /// The user is already in the household they were invited to.
+    AlreadyMember(i64),
+    /// The user owns several households, so the request has to say which one.
+    HouseholdRequired,
+    /// The user isn't in the household.
+    MemberNotFound(i64),
+}
+
+impl HouseholdError {
+    /// Stable, machine-readable identifier returned in error responses.
+    pub fn code(&self) -> &'static str {
+        match self {
+            HouseholdError::MissingName => "missing_name",
+            HouseholdError::NotFound(_) => "household_not_found",
+            HouseholdError::OwnerRequired => "owner_required",
+            HouseholdError::LastOwner => "last_owner",
+            HouseholdError::InvitationNotFound => "invitation_not_found",
+            HouseholdError::AlreadyMember(_) => "already_member",
+            HouseholdError::HouseholdRequired => "household_required",
+            HouseholdError::MemberNotFound(_) => "member_not_found",
+        }
+    }
+}
+
+impl fmt::Display for HouseholdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HouseholdError::MissingName => write!(f, "Household name is required"),
+            HouseholdError::NotFound(id) => write!(f, "Household {} not found", id),
+            HouseholdError::OwnerRequired => {
+                write!(f, "Only household owners can do this")
+            }
+            HouseholdError::LastOwner => {
+                write!(f, "A household needs at least one owner")
+            }
+            HouseholdError::InvitationNotFound => {
+                write!(f, "Invitation is invalid, expired or already used")
+            }
+            HouseholdError::AlreadyMember(id) => {
+                write!(f, "Already a member of household {}", id)
+            }
+            HouseholdError::HouseholdRequired => {
+                write!(
+                    f,
+                    "Several households are yours; say which one with household_id"
+                )
+            }
+            HouseholdError::MemberNotFound(id) => {
+                write!(f, "User {} is not a member of this household", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HouseholdError {}
@@ -1,5 +1,26 @@
+pub mod account;
+pub mod alert;
 pub mod app;
+pub mod battery;
+#[cfg(feature = "ssr")]
+pub mod dedup;
+pub mod device;
+#[cfg(feature = "ssr")]
+pub mod device_auth;
+pub mod email;
+pub mod geofence;
 pub mod gps_data;
+pub mod household;
+#[cfg(feature = "ssr")]
+pub mod live;
+pub mod map;
+pub mod playback;
+pub mod share;
+#[cfg(feature = "ssr")]
+pub mod storage;
+#[cfg(feature = "ssr")]
+pub mod wal;
+pub mod webhook;
 
 #[cfg(feature = "hydrate")]
 #[wasm_bindgen::prelude::wasm_bindgen]
@@ -0,0 +1,249 @@
+use crate::gps_data::{MAX_PAGE_SIZE, StoredData};
+use crate::storage::{DataStore, StorageError};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// --- Live Feed ---
+//
+// Every point that is stored is also broadcast to the dashboards following
+// `GET /api/live`, as Server-Sent Events:
+//
+//   id: <write-ahead log sequence number>
+//   event: fix
+//   data: <StoredData as JSON>
+//
+// The log sequence number only grows, so a client that lost its connection reconnects
+// with the last id it saw and is sent whatever it missed from the store before it
+// rejoins the broadcast.
+
+/// Points held for subscribers that fall behind. One that falls further behind
+/// catches up from the store instead.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// How long a quiet feed waits before sending a comment, so proxies don't time it out.
+pub const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// Query parameters of `GET /api/live`.
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+pub struct LiveQuery {
+    /// Only points from this device.
+    pub device_id: Option<String>,
+    /// Resume after this event id, for clients that can't send `Last-Event-ID`.
+    pub last_id: Option<u64>,
+}
+
+/// A stored point and the log sequence number it was stored under.
+type Fix = Arc<(u64, StoredData)>;
+
+/// Fans every newly stored point out to the live feed's subscribers.
+pub struct LiveFeed {
+    sender: broadcast::Sender<Fix>,
+}
+
+impl Default for LiveFeed {
+    fn default() -> Self {
+        LiveFeed {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl LiveFeed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Announces a point just stored under `lsn`. Nobody listening is fine.
+    pub fn publish(&self, lsn: u64, data: &StoredData) {
+        let _ = self.sender.send(Arc::new((lsn, data.clone())));
+    }
+
+    /// Follows the feed for the devices of `household_ids` (only `device_id` if given),
+    /// starting after `after_lsn` if the client is resuming, or with the next point stored.
+    pub fn subscribe(
+        &self,
+        store: Arc<dyn DataStore>,
+        household_ids: Vec<i64>,
+        device_id: Option<String>,
+        after_lsn: Option<u64>,
+    ) -> Result<Subscription, StorageError> {
+        let last_lsn = match after_lsn {
+            Some(lsn) => lsn,
+            None => store.applied_lsn()?,
+        };
+        // Read the backlog after subscribing so nothing stored in between is lost;
+        // anything seen twice is skipped by its sequence number
+        let receiver = self.sender.subscribe();
+        let mut subscription = Subscription {
+            store,
+            receiver,
+            household_ids,
+            device_id,
+            last_lsn,
+            backlog: VecDeque::new(),
+        };
+        subscription.catch_up()?;
+        Ok(subscription)
+    }
+}
+
+/// One client's view of the feed.
+pub struct Subscription {
+    store: Arc<dyn DataStore>,
+    receiver: broadcast::Receiver<Fix>,
+    household_ids: Vec<i64>,
+    device_id: Option<String>,
+    /// Newest sequence number sent, or asked to resume after.
+    last_lsn: u64,
+    /// Stored points to send before going back to the broadcast.
+    backlog: VecDeque<(u64, StoredData)>,
+}
+
+impl Subscription {
+    /// Waits for the next event for this client, as SSE text: a point, or a keepalive
+    /// comment after `KEEPALIVE` of silence. `None` once the server is shutting down.
+    pub async fn next_event(&mut self) -> Option<String> {
+        loop {
+            if let Some((lsn, data)) = self.backlog.pop_front() {
+                self.last_lsn = lsn;
+                return Some(fix_event(lsn, &data));
+            }
+            match tokio::time::timeout(KEEPALIVE, self.receiver.recv()).await {
+                Err(_) => return Some(": keepalive\n\n".to_string()),
+                Ok(Ok(fix)) => {
+                    let (lsn, data) = fix.as_ref();
+                    if *lsn > self.last_lsn && self.wants(data) {
+                        self.last_lsn = *lsn;
+                        return Some(fix_event(*lsn, data));
+                    }
+                }
+                // Missed some of the broadcast; the store still has them
+                Ok(Err(broadcast::error::RecvError::Lagged(_))) => {
+                    if self.catch_up().is_err() {
+                        return None;
+                    }
+                }
+                Ok(Err(broadcast::error::RecvError::Closed)) => return None,
+            }
+        }
+    }
+
+    /// Whether a broadcast point is one this client may and wants to see.
+    fn wants(&self, data: &StoredData) -> bool {
+        if self.device_id.as_ref().is_some_and(|id| *id != data.id) {
+            return false;
+        }
+        // Looked up each time, so devices registered after the client connected show up too
+        matches!(
+            self.store.device(&data.id),
+            Ok(Some(device)) if device.household_id.is_some_and(|id| self.household_ids.contains(&id))
+        )
+    }
+
+    /// Queues the stored points this client hasn't been sent yet.
+    fn catch_up(&mut self) -> Result<(), StorageError> {
+        let missed = self.store.since(
+            self.last_lsn,
+            &self.household_ids,
+            self.device_id.as_deref(),
+            MAX_PAGE_SIZE,
+        )?;
+        self.backlog.extend(missed);
+        Ok(())
+    }
+}
+
+/// A point as an SSE `fix` event.
+fn fix_event(lsn: u64, data: &StoredData) -> String {
+    format!(
+        "id: {}\nevent: fix\ndata: {}\n\n",
+        lsn,
+        serde_json::to_string(data).unwrap_or_default()
+    )
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{backends, device, household, insert, point};
+
+    /// The id of the next event, which has to be a fix.
+    async fn next_id(subscription: &mut Subscription) -> u64 {
+        let event = subscription.next_event().await.unwrap();
+        let (id, rest) = event
+            .strip_prefix("id: ")
+            .and_then(|event| event.split_once('\n'))
+            .unwrap_or_else(|| panic!("not a fix: {event:?}"));
+        assert!(rest.starts_with("event: fix\n"), "{event:?}");
+        id.parse().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn a_client_resuming_gets_what_it_missed_then_the_broadcast() {
+        for (name, store) in backends() {
+            let store: Arc<dyn DataStore> = Arc::from(store);
+            let (ann, _) = household(store.as_ref(), "ann");
+            store.create_device(&device("A", ann)).unwrap();
+            insert(
+                store.as_ref(),
+                &[point("A", 0), point("A", 1), point("A", 2)],
+            );
+            let feed = LiveFeed::new();
+
+            let mut resumed = feed
+                .subscribe(store.clone(), vec![ann], None, Some(1))
+                .unwrap();
+            let mut fresh = feed
+                .subscribe(store.clone(), vec![ann], None, None)
+                .unwrap();
+            // Stored while the backlog was read, so broadcast again; sent only once
+            feed.publish(3, &point("A", 2));
+            feed.publish(4, &point("A", 3));
+
+            let mut ids = Vec::new();
+            for _ in 0..3 {
+                ids.push(next_id(&mut resumed).await);
+            }
+            assert_eq!(ids, [2, 3, 4], "{name}");
+            // Without an id to resume from, only what is stored from now on
+            assert_eq!(next_id(&mut fresh).await, 4, "{name}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_client_only_sees_its_households_devices() {
+        for (name, store) in backends() {
+            let store: Arc<dyn DataStore> = Arc::from(store);
+            let (ann, _) = household(store.as_ref(), "ann");
+            let (bob, _) = household(store.as_ref(), "bob");
+            store.create_device(&device("A", ann)).unwrap();
+            store.create_device(&device("B", bob)).unwrap();
+            insert(store.as_ref(), &[point("B", 0), point("A", 1)]);
+            let feed = LiveFeed::new();
+
+            let mut all = feed
+                .subscribe(store.clone(), vec![ann], None, Some(0))
+                .unwrap();
+            let mut only_c = feed
+                .subscribe(store.clone(), vec![ann], Some("C".to_string()), Some(0))
+                .unwrap();
+            feed.publish(3, &point("B", 2));
+            // Not registered, so nobody's
+            feed.publish(4, &point("D", 3));
+            // Registered after the client connected
+            store.create_device(&device("C", ann)).unwrap();
+            feed.publish(5, &point("C", 4));
+
+            let mut ids = Vec::new();
+            for _ in 0..2 {
+                ids.push(next_id(&mut all).await);
+            }
+            assert_eq!(ids, [2, 5], "{name}");
+            assert_eq!(next_id(&mut only_c).await, 5, "{name}");
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use crate::gps_data::StoredData;
+use chrono::Local;
+use leptos::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+// --- Track Map ---
+//
+// Tracks are drawn as SVG in Web Mercator, the projection web map tiles use, so they line
+// up with a tile layer when BUDDY_MAP_TILES names one. Without it (or offline) a grid of
+// latitude and longitude lines is drawn instead, and the map needs nothing but the app.
+
+/// Size of the map's SVG view box, in pixels.
+const MAP_WIDTH: f64 = 800.0;
+const MAP_HEIGHT: f64 = 480.0;
+/// Space kept free around the outermost fixes.
+const MAP_PADDING: f64 = 40.0;
+/// Side of a map tile, in pixels.
+const TILE_SIZE: f64 = 256.0;
+/// Closest zoom level; tile servers seldom go further, and a lone fix is shown at it.
+const MAX_ZOOM: i32 = 18;
+/// Web Mercator stops short of the poles.
+const MAX_LATITUDE: f64 = 85.051_128_78;
+
+/// Line colours for the tracks of different devices.
+const TRACK_COLOURS: [&str; 5] = ["#0f766e", "#b45309", "#7c3aed", "#be123c", "#1d4ed8"];
+
+/// Map settings the server hands the dashboard, as returned by `GET /api/map`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MapSettings {
+    /// Tile URL template with `{z}`, `{x}` and `{y}` placeholders; `None` draws a grid.
+    pub tile_url: Option<String>,
+    /// Credit shown under the map, as most tile providers require.
+    pub attribution: Option<String>,
+}
+
+/// Checks a tile URL template has all of `{z}`, `{x}` and `{y}`.
+pub fn validate_tile_url(template: &str) -> Result<(), String> {
+    match ["{z}", "{x}", "{y}"]
+        .into_iter()
+        .find(|placeholder| !template.contains(placeholder))
+    {
+        Some(placeholder) => Err(format!(
+            "Tile URL {:?} has no {} placeholder",
+            template, placeholder
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Position on the Web Mercator world square, from 0 to 1 eastwards and southwards.
+fn world_point(latitude: f64, longitude: f64) -> (f64, f64) {
+    let latitude = latitude.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = (longitude + 180.0) / 360.0;
+    let y = (1.0 - (latitude.tan() + 1.0 / latitude.cos()).ln() / PI) / 2.0;
+    (x, y)
+}
+
+/// Latitude of a point `y` down the world square.
+fn world_latitude(y: f64) -> f64 {
+    (PI * (1.0 - 2.0 * y)).sinh().atan().to_degrees()
+}
+
+/// Smallest of 1, 2 and 5 times a power of ten that is at least `raw`, for grid spacing.
+fn nice_step(raw: f64) -> f64 {
+    let magnitude = 10f64.powf(raw.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|m| m * magnitude)
+        .find(|step| *step >= raw)
+        .unwrap_or(10.0 * magnitude)
+}
+
+/// Multiples of `step` between `from` and `to`.
+fn multiples(from: f64, to: f64, step: f64) -> Vec<f64> {
+    let first = (from / step).ceil() as i64;
+    let last = (to / step).floor() as i64;
+    (first..=last).map(|i| i as f64 * step).collect()
+}
+
+/// Colour of a fix `fraction` of the way from the oldest (blue) to the newest (red) shown.
+fn time_colour(fraction: f64) -> String {
+    format!(
+        "hsl({:.0}, 75%, 45%)",
+        220.0 - 215.0 * fraction.clamp(0.0, 1.0)
+    )
+}
+
+/// The part of the world the map shows: a zoom level and the top left corner, in pixels
+/// of the world at that zoom.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Viewport {
+    zoom: i32,
+    left: f64,
+    top: f64,
+}
+
+impl Viewport {
+    /// The closest view that has every one of `points` (latitude, longitude) in it.
+    fn fit(points: &[(f64, f64)]) -> Option<Self> {
+        let world: Vec<(f64, f64)> = points
+            .iter()
+            .map(|(latitude, longitude)| world_point(*latitude, *longitude))
+            .collect();
+        let min_x = world.iter().map(|p| p.0).reduce(f64::min)?;
+        let max_x = world.iter().map(|p| p.0).reduce(f64::max)?;
+        let min_y = world.iter().map(|p| p.1).reduce(f64::min)?;
+        let max_y = world.iter().map(|p| p.1).reduce(f64::max)?;
+
+        let zoom = (0..=MAX_ZOOM)
+            .rev()
+            .find(|zoom| {
+                let scale = Self::scale_at(*zoom);
+                (max_x - min_x) * scale <= MAP_WIDTH - 2.0 * MAP_PADDING
+                    && (max_y - min_y) * scale <= MAP_HEIGHT - 2.0 * MAP_PADDING
+            })
+            .unwrap_or(0);
+        let scale = Self::scale_at(zoom);
+        Some(Viewport {
+            zoom,
+            left: (min_x + max_x) / 2.0 * scale - MAP_WIDTH / 2.0,
+            top: (min_y + max_y) / 2.0 * scale - MAP_HEIGHT / 2.0,
+        })
+    }
+
+    /// Width of the whole world at `zoom`, in pixels.
+    fn scale_at(zoom: i32) -> f64 {
+        TILE_SIZE * 2f64.powi(zoom)
+    }
+
+    fn scale(&self) -> f64 {
+        Self::scale_at(self.zoom)
+    }
+
+    /// Where a position is drawn in the view box.
+    fn project(&self, latitude: f64, longitude: f64) -> (f64, f64) {
+        let (x, y) = world_point(latitude, longitude);
+        (x * self.scale() - self.left, y * self.scale() - self.top)
+    }
+
+    /// The tiles under the view, as (x, y, URL) with `x` and `y` in the view box.
+    fn tiles(&self, template: &str) -> Vec<(f64, f64, String)> {
+        let count = 2i64.pow(self.zoom as u32);
+        let columns = (self.left / TILE_SIZE).floor() as i64
+            ..=((self.left + MAP_WIDTH) / TILE_SIZE).floor() as i64;
+        let rows = ((self.top / TILE_SIZE).floor() as i64).max(0)
+            ..=(((self.top + MAP_HEIGHT) / TILE_SIZE).floor() as i64).min(count - 1);
+        rows.flat_map(|row| {
+            columns.clone().map(move |column| {
+                let url = template
+                    .replace("{z}", &self.zoom.to_string())
+                    .replace("{x}", &column.rem_euclid(count).to_string())
+                    .replace("{y}", &row.to_string());
+                (
+                    column as f64 * TILE_SIZE - self.left,
+                    row as f64 * TILE_SIZE - self.top,
+                    url,
+                )
+            })
+        })
+        .collect()
+    }
+
+    /// Longitudes and latitudes worth a grid line, about five of each across the view.
+    fn grid(&self) -> (Vec<f64>, Vec<f64>) {
+        let west = self.left / self.scale() * 360.0 - 180.0;
+        let east = (self.left + MAP_WIDTH) / self.scale() * 360.0 - 180.0;
+        let north = world_latitude(self.top / self.scale());
+        let south = world_latitude((self.top + MAP_HEIGHT) / self.scale());
+        let longitudes = multiples(west, east, nice_step((east - west) / 5.0));
+        let latitudes = multiples(south, north, nice_step((north - south) / 4.0));
+        (longitudes, latitudes)
+    }
+}
+
+/// One device's track, as the `points` of an SVG polyline.
+#[derive(Clone, Debug, PartialEq)]
+struct Track {
+    colour: &'static str,
+    points: String,
+}
+
+/// A fix on the map. `index` is its position in the rows the map was given.
+#[derive(Clone, Debug, PartialEq)]
+struct Marker {
+    index: usize,
+    x: f64,
+    y: f64,
+    colour: String,
+    /// The device's newest fix.
+    latest: bool,
+    title: String,
+}
+
+/// Everything drawn for a set of rows, worked out once per change of rows.
+#[derive(Clone, Debug, PartialEq)]
+struct MapLayout {
+    viewport: Viewport,
+    tracks: Vec<Track>,
+    /// Oldest first, so newer fixes are drawn over older ones and the latest on top.
+    markers: Vec<Marker>,
+}
+
+impl MapLayout {
+    fn new(rows: &[StoredData]) -> Option<Self> {
+        let positions: Vec<(f64, f64)> = rows
+            .iter()
+            .map(|row| (row.latitude_deg, row.longitude_deg))
+            .collect();
+        let viewport = Viewport::fit(&positions)?;
+
+        // Rows come in whatever order the table shows; tracks and colours go by time
+        let mut order: Vec<usize> = (0..rows.len()).collect();
+        order.sort_by_key(|i| (rows[*i].timestamp, *i));
+        let oldest = rows[order[0]].timestamp;
+        let span = (rows[order[order.len() - 1]].timestamp - oldest).num_seconds();
+
+        let mut devices: Vec<&str> = rows.iter().map(|row| row.id.as_str()).collect();
+        devices.sort_unstable();
+        devices.dedup();
+
+        let tracks = devices
+            .iter()
+            .enumerate()
+            .map(|(n, device_id)| Track {
+                colour: TRACK_COLOURS[n % TRACK_COLOURS.len()],
+                points: order
+                    .iter()
+                    .filter(|i| rows[**i].id == *device_id)
+                    .map(|i| {
+                        let (x, y) = viewport.project(positions[*i].0, positions[*i].1);
+                        format!("{:.1},{:.1}", x, y)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            })
+            .collect();
+
+        let mut markers: Vec<Marker> = order
+            .iter()
+            .map(|i| {
+                let row = &rows[*i];
+                let (x, y) = viewport.project(positions[*i].0, positions[*i].1);
+                let fraction = if span > 0 {
+                    (row.timestamp - oldest).num_seconds() as f64 / span as f64
+                } else {
+                    1.0
+                };
+                Marker {
+                    index: *i,
+                    x,
+                    y,
+                    colour: time_colour(fraction),
+                    latest: false,
+                    title: format!(
+                        "{} at {}",
+                        row.id,
+                        row.timestamp
+                            .with_timezone(&Local)
+                            .format("%Y-%m-%d %H:%M:%S")
+                    ),
+                }
+            })
+            .collect();
+        // The last marker of each device is its newest fix; move those to the end
+        for device_id in &devices {
+            if let Some(marker) = markers
+                .iter_mut()
+                .rev()
+                .find(|marker| rows[marker.index].id == *device_id)
+            {
+                marker.latest = true;
+            }
+        }
+        markers.sort_by_key(|marker| marker.latest);
+
+        Some(MapLayout {
+            viewport,
+            tracks,
+            markers,
+        })
+    }
+}
+
+/// Map of the tracks in `rows`, one line per device, with a marker per fix coloured from
+/// blue (oldest) to red (newest) and each device's latest fix drawn larger.
+///
+/// Clicking a marker puts its row index in `selected`, which is meant to be the table's
+/// selection; the selected fix is outlined. `cursor` (latitude, longitude) is where
+/// playback has got to, drawn as an amber ring on top of everything.
+#[component]
+pub fn TrackMap(
+    #[prop(into)] rows: Signal<Vec<StoredData>>,
+    selected: RwSignal<Option<usize>>,
+    #[prop(into)] settings: Signal<MapSettings>,
+    #[prop(optional, into)] cursor: MaybeProp<(f64, f64)>,
+) -> impl IntoView {
+    let layout = Memo::new(move |_| rows.with(|rows| MapLayout::new(rows)));
+
+    let background = move || {
+        let layout = layout.get()?;
+        let view = match settings.with(|s| s.tile_url.clone()) {
+            Some(template) => layout
+                .viewport
+                .tiles(&template)
+                .into_iter()
+                .map(|(x, y, url)| {
+                    view! { <image href=url x=x y=y width=TILE_SIZE height=TILE_SIZE/> }
+                })
+                .collect_view()
+                .into_any(),
+            None => {
+                let (longitudes, latitudes) = layout.viewport.grid();
+                let meridians = longitudes
+                    .into_iter()
+                    .map(|longitude| {
+                        let (x, _) = layout.viewport.project(0.0, longitude);
+                        view! {
+                            <line x1=x y1=0 x2=x y2=MAP_HEIGHT stroke="#cbd5e1" stroke-width="1"/>
+                            <text x=x + 3.0 y=MAP_HEIGHT - 4.0 font-size="10" fill="#64748b">
+                                {format!("{:.4}°", longitude)}
+                            </text>
+                        }
+                    })
+                    .collect_view();
+                let parallels = latitudes
+                    .into_iter()
+                    .map(|latitude| {
+                        let (_, y) = layout.viewport.project(latitude, 0.0);
+                        view! {
+                            <line x1=0 y1=y x2=MAP_WIDTH y2=y stroke="#cbd5e1" stroke-width="1"/>
+                            <text x=4 y=y - 3.0 font-size="10" fill="#64748b">
+                                {format!("{:.4}°", latitude)}
+                            </text>
+                        }
+                    })
+                    .collect_view();
+                view! { {meridians} {parallels} }.into_any()
+            }
+        };
+        Some(view)
+    };
+
+    let tracks = move || {
+        layout.get().map(|layout| {
+            layout
+                .tracks
+                .into_iter()
+                .map(|track| {
+                    view! {
+                        <polyline
+                            points=track.points
+                            fill="none"
+                            stroke=track.colour
+                            stroke-width="3"
+                            stroke-linejoin="round"
+                            stroke-opacity="0.7"
+                        />
+                    }
+                })
+                .collect_view()
+        })
+    };
+
+    let markers = move || {
+        layout.get().map(|layout| {
+            layout
+                .markers
+                .into_iter()
+                .map(|marker| {
+                    let index = marker.index;
+                    let radius = if marker.latest { 9.0 } else { 5.0 };
+                    let is_selected = move || selected.get() == Some(index);
+                    view! {
+                        <circle
+                            cx=marker.x
+                            cy=marker.y
+                            r=move || if is_selected() { radius + 3.0 } else { radius }
+                            fill=marker.colour
+                            stroke=move || if is_selected() { "#111827" } else { "white" }
+                            stroke-width=if marker.latest { "3" } else { "1.5" }
+                            class="cursor-pointer"
+                            on:click=move |_| selected.set(Some(index))
+                        >
+                            <title>{marker.title}</title>
+                        </circle>
+                    }
+                })
+                .collect_view()
+        })
+    };
+
+    let playback_marker = move || {
+        let viewport = layout.with(|layout| layout.as_ref().map(|layout| layout.viewport))?;
+        let (latitude, longitude) = cursor.get()?;
+        let (x, y) = viewport.project(latitude, longitude);
+        Some(view! {
+            <circle cx=x cy=y r="12" fill="#f59e0b" fill-opacity="0.35" stroke="#b45309" stroke-width="2"/>
+            <circle cx=x cy=y r="4" fill="#b45309"/>
+        })
+    };
+
+    view! {
+        <div class="rounded-xl overflow-hidden shadow-lg ring-1 ring-gray-200 bg-slate-50">
+            <svg
+                viewBox=format!("0 0 {} {}", MAP_WIDTH, MAP_HEIGHT)
+                class="w-full h-auto block"
+                role="img"
+                aria-label="Map of the tracks"
+            >
+                {background}
+                {tracks}
+                {markers}
+                {playback_marker}
+            </svg>
+            {move || settings.with(|s| s.attribution.clone()).map(|attribution| view! {
+                <p class="px-2 py-1 text-right text-xs text-gray-500">{attribution}</p>
+            })}
+        </div>
+    }
+    .into_any()
+}
@@ -0,0 +1,312 @@
+use crate::gps_data::StoredData;
+use chrono::{DateTime, Local, Utc};
+use leptos::prelude::*;
+use std::time::Duration;
+use wasm_bindgen::JsCast;
+
+// --- Track Playback ---
+//
+// Replays the rows on screen in the order they were recorded: a clock runs from the first
+// fix to the last at the chosen speed, the map's playback marker glides between fixes,
+// and the newest fix the clock has passed is selected in the table and scrolled to.
+
+/// How often a playing clock moves on.
+const TICK: Duration = Duration::from_millis(100);
+
+/// Playback speeds offered, in seconds of track per second, with their labels.
+const SPEEDS: [(f64, &str); 4] = [
+    (60.0, "1 min/s"),
+    (600.0, "10 min/s"),
+    (3600.0, "1 h/s"),
+    (6.0 * 3600.0, "6 h/s"),
+];
+const DEFAULT_SPEED: f64 = 600.0;
+
+/// The rows on screen in recording order, with their times.
+#[derive(Clone, Debug, PartialEq)]
+struct Timeline {
+    start: DateTime<Utc>,
+    /// Row indexes, oldest fix first.
+    order: Vec<usize>,
+    /// Seconds from `start` to each fix of `order`.
+    offsets: Vec<f64>,
+    /// Position of each fix of `order`, as (latitude, longitude).
+    positions: Vec<(f64, f64)>,
+}
+
+impl Timeline {
+    fn new(rows: &[StoredData]) -> Option<Self> {
+        let mut order: Vec<usize> = (0..rows.len()).collect();
+        order.sort_by_key(|i| (rows[*i].timestamp, *i));
+        let start = rows.get(*order.first()?)?.timestamp;
+        Some(Timeline {
+            start,
+            offsets: order
+                .iter()
+                .map(|i| (rows[*i].timestamp - start).num_milliseconds() as f64 / 1000.0)
+                .collect(),
+            positions: order
+                .iter()
+                .map(|i| (rows[*i].latitude_deg, rows[*i].longitude_deg))
+                .collect(),
+            order,
+        })
+    }
+
+    /// Seconds from the first fix to the last.
+    fn span(&self) -> f64 {
+        self.offsets.last().copied().unwrap_or(0.0)
+    }
+
+    /// Place in `order` of the newest fix at or before `clock`.
+    fn step_at(&self, clock: f64) -> usize {
+        self.offsets
+            .partition_point(|offset| *offset <= clock)
+            .saturating_sub(1)
+    }
+
+    /// Row index of the newest fix at or before `clock`.
+    fn row_at(&self, clock: f64) -> usize {
+        self.order[self.step_at(clock)]
+    }
+
+    /// Where the device was at `clock`, going in a straight line from fix to fix.
+    fn position_at(&self, clock: f64) -> (f64, f64) {
+        let step = self.step_at(clock);
+        let Some(next) = self.offsets.get(step + 1) else {
+            return self.positions[step];
+        };
+        let gap = next - self.offsets[step];
+        let fraction = if gap > 0.0 {
+            ((clock - self.offsets[step]) / gap).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let (from, to) = (self.positions[step], self.positions[step + 1]);
+        (
+            from.0 + (to.0 - from.0) * fraction,
+            from.1 + (to.1 - from.1) * fraction,
+        )
+    }
+}
+
+/// Scrolls the page to table row `index`, which must be drawn with `StoredDataRowRenderer`.
+///
+/// The table only draws the rows near the visible ones, so when `index` isn't drawn yet
+/// the page is scrolled to where it will be, judging by a row that is.
+fn scroll_to_row(index: usize) {
+    let document = document();
+    if let Ok(Some(row)) = document.query_selector(&format!("tr[data-row=\"{}\"]", index)) {
+        let options = web_sys::ScrollIntoViewOptions::new();
+        options.set_block(web_sys::ScrollLogicalPosition::Nearest);
+        row.scroll_into_view_with_scroll_into_view_options(&options);
+        return;
+    }
+    let Ok(Some(drawn)) = document.query_selector("tr[data-row]") else {
+        return;
+    };
+    let Some(drawn_index) = drawn
+        .get_attribute("data-row")
+        .and_then(|value| value.parse::<f64>().ok())
+    else {
+        return;
+    };
+    let rect = drawn.get_bounding_client_rect();
+    let window = window();
+    let viewport_height = window
+        .inner_height()
+        .ok()
+        .and_then(|height| height.as_f64())
+        .unwrap_or(0.0);
+    let target = rect.top() + (index as f64 - drawn_index) * rect.height();
+    window.scroll_by_with_x_and_y(0.0, target - viewport_height / 2.0);
+}
+
+/// Formats seconds of track as `1 h 05 min`, `12 min` or `40 s`.
+fn format_offset(secs: f64) -> String {
+    let secs = secs.max(0.0) as i64;
+    match secs {
+        s if s < 60 => format!("{} s", s),
+        s if s < 3600 => format!("{} min", s / 60),
+        s => format!("{} h {:02} min", s / 3600, s % 3600 / 60),
+    }
+}
+
+/// Slider, play/pause button and speed picker replaying `rows` in recording order.
+///
+/// While it runs, the newest fix passed is put in `selected` (the table's selection) and
+/// scrolled to, and the interpolated position is put in `cursor` for the map to draw.
+/// Any change to `rows` stops playback and starts it over.
+#[component]
+pub fn Playback(
+    #[prop(into)] rows: Signal<Vec<StoredData>>,
+    selected: RwSignal<Option<usize>>,
+    cursor: RwSignal<Option<(f64, f64)>>,
+) -> impl IntoView {
+    let timeline = Memo::new(move |_| rows.with(|rows| Timeline::new(rows)));
+    // Seconds since the first fix; `None` until playback is started or the slider moved
+    let clock = RwSignal::new(None::<f64>);
+    let playing = RwSignal::new(false);
+    let speed = RwSignal::new(DEFAULT_SPEED);
+
+    Effect::new(move |_| {
+        timeline.track();
+        playing.set(false);
+        clock.set(None);
+        cursor.set(None);
+    });
+    on_cleanup(move || cursor.set(None));
+
+    // Follow the clock on the map and in the table
+    Effect::new(move |_| {
+        let Some(clock) = clock.get() else {
+            return;
+        };
+        let Some(timeline) = timeline.get() else {
+            return;
+        };
+        let row = timeline.row_at(clock);
+        cursor.set(Some(timeline.position_at(clock)));
+        if selected.get_untracked() != Some(row) {
+            selected.set(Some(row));
+            scroll_to_row(row);
+        }
+    });
+
+    // Run the clock while playing; the interval is dropped when playback pauses
+    Effect::new(move |_| {
+        if !playing.get() {
+            return;
+        }
+        let handle = set_interval_with_handle(
+            move || {
+                let span = timeline.with_untracked(|t| t.as_ref().map_or(0.0, Timeline::span));
+                let next = clock.get_untracked().unwrap_or(0.0)
+                    + speed.get_untracked() * TICK.as_secs_f64();
+                if next >= span {
+                    clock.set(Some(span));
+                    playing.set(false);
+                } else {
+                    clock.set(Some(next));
+                }
+            },
+            TICK,
+        );
+        if let Ok(handle) = handle {
+            on_cleanup(move || handle.clear());
+        }
+    });
+
+    let on_play_click = move |_| {
+        if playing.get_untracked() {
+            playing.set(false);
+            return;
+        }
+        // Start over once the end is reached
+        let span = timeline.with_untracked(|t| t.as_ref().map_or(0.0, Timeline::span));
+        if clock.get_untracked().is_none_or(|clock| clock >= span) {
+            clock.set(Some(0.0));
+        }
+        playing.set(true);
+    };
+
+    let span = move || timeline.with(|t| t.as_ref().map_or(0.0, Timeline::span));
+    let current_time = move || {
+        timeline.get().map(|timeline| {
+            let offset = clock.get().unwrap_or(0.0);
+            let at = timeline.start + chrono::Duration::milliseconds((offset * 1000.0) as i64);
+            format!(
+                "{} (+{})",
+                at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+                format_offset(offset)
+            )
+        })
+    };
+
+    view! {
+        <div class="flex flex-wrap items-center gap-3 mt-3 text-sm text-gray-700">
+            <button
+                on:click=on_play_click
+                class="flex items-center justify-center w-10 h-10 bg-teal-600 hover:bg-teal-700 text-white rounded-full shadow transition duration-300 focus:outline-none focus:ring-4 focus:ring-teal-300"
+                title=move || if playing.get() { "Pause" } else { "Play" }
+            >
+                <i class=move || if playing.get() { "fas fa-pause" } else { "fas fa-play" }></i>
+            </button>
+            <input
+                type="range"
+                min="0"
+                max=move || span().ceil().to_string()
+                step="any"
+                prop:value=move || clock.get().unwrap_or(0.0).to_string()
+                on:input=move |ev| {
+                    let value = ev
+                        .target()
+                        .and_then(|target| target.dyn_into::<web_sys::HtmlInputElement>().ok())
+                        .map(|input| input.value_as_number());
+                    if let Some(value) = value.filter(|value| value.is_finite()) {
+                        clock.set(Some(value));
+                    }
+                }
+                class="flex-1 min-w-[12rem] accent-teal-600"
+            />
+            <select
+                on:change=move |ev| {
+                    if let Ok(value) = event_target_value(&ev).parse::<f64>() {
+                        speed.set(value);
+                    }
+                }
+                class="border border-amber-200 rounded-lg px-2 py-1 focus:outline-none focus:ring-2 focus:ring-teal-300"
+            >
+                {SPEEDS
+                    .into_iter()
+                    .map(|(value, label)| view! {
+                        <option value=value.to_string() selected=value == DEFAULT_SPEED>{label}</option>
+                    })
+                    .collect_view()}
+            </select>
+            <span class="w-full text-xs text-gray-500">{current_time}</span>
+        </div>
+    }
+    .into_any()
+}
+
+// --- Tests ---
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::storage::tests::point;
+
+    /// A fix `minutes` after `at(0)` at `latitude`.
+    fn fix(minutes: i64, latitude: f64) -> StoredData {
+        StoredData {
+            latitude_deg: latitude,
+            ..point("A", minutes)
+        }
+    }
+
+    #[test]
+    fn rows_in_any_order_replay_by_time() {
+        // Newest first, as the table may show them
+        let rows = [fix(10, 3.0), fix(5, 2.0), fix(0, 1.0)];
+        let timeline = Timeline::new(&rows).unwrap();
+        assert_eq!(timeline.order, [2, 1, 0]);
+        assert_eq!(timeline.span(), 600.0);
+
+        assert_eq!(timeline.row_at(0.0), 2);
+        assert_eq!(timeline.row_at(299.0), 2);
+        assert_eq!(timeline.row_at(300.0), 1);
+        assert_eq!(timeline.row_at(10_000.0), 0);
+        assert_eq!(timeline.position_at(150.0), (1.5, 100.5));
+        assert_eq!(timeline.position_at(10_000.0), (3.0, 100.5));
+    }
+
+    #[test]
+    fn a_range_with_one_fix_or_none_still_plays() {
+        assert_eq!(Timeline::new(&[]), None);
+        let timeline = Timeline::new(&[fix(0, 1.0)]).unwrap();
+        assert_eq!(timeline.span(), 0.0);
+        assert_eq!(timeline.row_at(60.0), 0);
+        assert_eq!(timeline.position_at(60.0), (1.0, 100.5));
+    }
+}
@@ -0,0 +1,248 @@
+use crate::device::LatestFix;
+use crate::gps_data::StoredData;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[cfg(feature = "ssr")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "ssr")]
+use sha2::Sha256;
+#[cfg(feature = "ssr")]
+use subtle::ConstantTimeEq;
+
+// --- Share Links ---
+//
+// When a pet goes missing, an owner can hand neighbours a link to the pet's latest
+// position and recent track that works without an account. The link carries
+//
+//   <share id>.<expiry, unix seconds>.<hex HMAC-SHA256(server secret, "<share id>.<expiry>")>
+//
+// so altered or made-up links are refused before the store is touched, and every link
+// stops working at its expiry. Revoking the share ends it sooner.
+
+/// Hours of track shown, and hours a link stays valid, when the request doesn't say.
+pub const DEFAULT_SHARE_HOURS: u32 = 24;
+/// Longest track, and longest validity, a share link can have (one week).
+pub const MAX_SHARE_HOURS: u32 = 7 * 24;
+
+#[cfg(feature = "ssr")]
+type HmacSha256 = Hmac<Sha256>;
+
+/// A public link to one device's whereabouts.
+///
+/// `token` is only returned once, in the response that creates the share.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Share {
+    pub share_id: i64,
+    pub device_id: String,
+    /// How many hours of track the link shows, counting back from the time it is opened.
+    pub track_hours: u32,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl Share {
+    /// Whether the link still works at `now`.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && self.expires_at > now
+    }
+}
+
+fn default_share_hours() -> u32 {
+    DEFAULT_SHARE_HOURS
+}
+
+/// Body of `POST /api/devices/{id}/shares`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct NewShare {
+    /// Hours of track to show.
+    #[serde(default = "default_share_hours")]
+    pub track_hours: u32,
+    /// Hours until the link stops working.
+    #[serde(default = "default_share_hours")]
+    pub valid_hours: u32,
+}
+
+impl Default for NewShare {
+    fn default() -> Self {
+        NewShare {
+            track_hours: DEFAULT_SHARE_HOURS,
+            valid_hours: DEFAULT_SHARE_HOURS,
+        }
+    }
+}
+
+impl NewShare {
+    /// Checks both durations are between 1 hour and `MAX_SHARE_HOURS`.
+    pub fn validate(&self) -> Result<(), ShareError> {
+        for hours in [self.track_hours, self.valid_hours] {
+            if !(1..=MAX_SHARE_HOURS).contains(&hours) {
+                return Err(ShareError::InvalidHours(hours));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a share link shows, as returned by `GET /api/shared/{token}`.
+///
+/// The device in `fix` is stripped down to what the page needs: its names, photo and schedule.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SharedLocation {
+    pub fix: LatestFix,
+    /// Points from the last `track_hours` hours, oldest first.
+    pub track: Vec<StoredData>,
+    pub track_hours: u32,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The token for the link of share `share_id`, signed with the server's `secret`.
+#[cfg(feature = "ssr")]
+pub fn sign_share(secret: &[u8], share_id: i64, expires_at: DateTime<Utc>) -> String {
+    let message = format!("{}.{}", share_id, expires_at.timestamp());
+    format!("{}.{}", message, hex::encode(share_mac(secret, &message)))
+}
+
+/// Checks a link's token was signed with `secret` and hasn't expired by `now`,
+/// returning the id of its share.
+#[cfg(feature = "ssr")]
+pub fn verify_share_token(
+    secret: &[u8],
+    token: &str,
+    now: DateTime<Utc>,
+) -> Result<i64, ShareError> {
+    let (message, signature) = token.rsplit_once('.').ok_or(ShareError::NotFound)?;
+    let signature = hex::decode(signature).map_err(|_| ShareError::NotFound)?;
+    if !bool::from(share_mac(secret, message).ct_eq(&signature)) {
+        return Err(ShareError::NotFound);
+    }
+    let (share_id, expires) = message.split_once('.').ok_or(ShareError::NotFound)?;
+    let share_id = share_id.parse().map_err(|_| ShareError::NotFound)?;
+    let expires: i64 = expires.parse().map_err(|_| ShareError::NotFound)?;
+    if expires <= now.timestamp() {
+        return Err(ShareError::Expired);
+    }
+    Ok(share_id)
+}
+
+#[cfg(feature = "ssr")]
+fn share_mac(secret: &[u8], message: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// --- Errors ---
+
+/// Why a share request was refused.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShareError {
+    /// A duration outside 1..=`MAX_SHARE_HOURS`.
+    InvalidHours(u32),
+    /// The link is malformed, wasn't signed by this server, was revoked or its device is gone.
+    NotFound,
+    /// The link is past its expiry.
+    Expired,
+}
+
+impl ShareError {
+    /// Stable, machine-readable identifier returned in error responses.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ShareError::InvalidHours(_) => "invalid_share_hours",
+            ShareError::NotFound => "share_not_found",
+            ShareError::Expired => "share_expired",
+        }
+    }
+}
+
+impl fmt::Display for ShareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShareError::InvalidHours(hours) => write!(
+                f,
+                "Invalid duration {} h: use 1 to {} hours",
+                hours, MAX_SHARE_HOURS
+            ),
+            ShareError::NotFound => write!(f, "This link is invalid or was revoked"),
+            ShareError::Expired => write!(f, "This link has expired"),
+        }
+    }
+}
+
+impl std::error::Error for ShareError {}
+
+// --- Tests ---
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"server secret";
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_761_955_200 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn a_signed_link_works_until_it_expires() {
+        let token = sign_share(SECRET, 42, at(3600));
+        assert_eq!(verify_share_token(SECRET, &token, at(0)), Ok(42));
+        assert_eq!(verify_share_token(SECRET, &token, at(3599)), Ok(42));
+        assert_eq!(
+            verify_share_token(SECRET, &token, at(3600)),
+            Err(ShareError::Expired)
+        );
+    }
+
+    #[test]
+    fn altered_links_are_refused() {
+        let token = sign_share(SECRET, 42, at(3600));
+        let (message, signature) = token.rsplit_once('.').unwrap();
+
+        let other_share = token.replacen("42.", "43.", 1);
+        let extended = format!("42.{}.{}", at(7200).timestamp(), signature);
+        let mut flipped = signature.to_string();
+        let last = if flipped.ends_with('0') { "1" } else { "0" };
+        flipped.replace_range(flipped.len() - 1.., last);
+        let flipped = format!("{}.{}", message, flipped);
+
+        for forged in [other_share, extended, flipped] {
+            assert_eq!(
+                verify_share_token(SECRET, &forged, at(0)),
+                Err(ShareError::NotFound),
+                "{forged}"
+            );
+        }
+        assert_eq!(
+            verify_share_token(b"another secret", &token, at(0)),
+            Err(ShareError::NotFound)
+        );
+    }
+
+    #[test]
+    fn malformed_tokens_are_not_found() {
+        let valid_mac =
+            |message: &str| format!("{}.{}", message, hex::encode(share_mac(SECRET, message)));
+        for token in [
+            String::new(),
+            "42".to_string(),
+            "42.1761958800".to_string(),
+            "42.1761958800.not-hex".to_string(),
+            "42.1761958800.abc".to_string(),
+            valid_mac("42"),
+            valid_mac("x.1761958800"),
+            valid_mac("42.soon"),
+        ] {
+            assert_eq!(
+                verify_share_token(SECRET, &token, at(0)),
+                Err(ShareError::NotFound),
+                "{token:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,3521 @@
+use crate::account::User;
+use crate::alert::{Alert, AlertKind, AlertQuery, AlertState};
+use crate::device::{Device, DeviceKey, QuarantinedPost, ReportingSchedule};
+use crate::email::EmailPrefs;
+use crate::geofence::{Geofence, GeofenceEvent, GeofenceEventQuery};
+use crate::gps_data::{
+    CoordinateEncoding, DataPage, DataQuery, IncomingData, SortOrder, StoredData,
+};
+use crate::household::{Household, Invitation, Member, Membership, Role};
+use crate::share::Share;
+use crate::webhook::{Delivery, DeliveryQuery, DeliveryState, Webhook};
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, named_params};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+
+// --- Errors ---
+
+/// Everything that can go wrong while talking to a storage backend.
+#[derive(Debug)]
+pub enum StorageError {
+    /// A lock guarding the backend was poisoned by a panicking writer.
+    Lock,
+    /// The SQLite backend reported an error.
+    Sqlite(rusqlite::Error),
+    /// A pagination cursor passed by the client isn't one we handed out.
+    InvalidCursor(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Lock => write!(f, "failed to lock data store"),
+            StorageError::Sqlite(e) => write!(f, "sqlite error: {}", e),
+            StorageError::InvalidCursor(c) => write!(f, "invalid cursor {:?}", c),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Sqlite(e)
+    }
+}
+
+// --- Storage Trait ---
+
+/// A backend that persists the GPS points accepted by the API.
+///
+/// Handlers only ever see `dyn DataStore`, so the server can swap the
+/// SQLite file for the in-memory store without touching request code.
+pub trait DataStore: Send + Sync {
+    /// Appends a single point to the store.
+    ///
+    /// `lsn` is the write-ahead log sequence number the point was logged under;
+    /// it is recorded atomically with the point so startup replay can skip it.
+    fn insert(&self, lsn: u64, data: &StoredData) -> Result<(), StorageError> {
+        self.insert_batch(&[(lsn, data)])
+    }
+
+    /// Appends several points, each with its write-ahead log sequence number,
+    /// all at once: if any of them can't be stored, none are.
+    fn insert_batch(&self, records: &[(u64, &StoredData)]) -> Result<(), StorageError>;
+
+    /// The highest write-ahead log sequence number already applied (0 if none).
+    fn applied_lsn(&self) -> Result<u64, StorageError>;
+
+    /// Returns one page of the points matching `query` sent by devices of the households
+    /// `household_ids`, ordered by device timestamp.
+    fn query(&self, query: &DataQuery, household_ids: &[i64]) -> Result<DataPage, StorageError>;
+
+    /// Points logged after `after_lsn` by devices of the households `household_ids`
+    /// (only `device_id`'s, if given), in log order, at most `limit` of them.
+    fn since(
+        &self,
+        after_lsn: u64,
+        household_ids: &[i64],
+        device_id: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(u64, StoredData)>, StorageError>;
+
+    /// The first point from `data`'s device received since `since` with the same
+    /// sequence number or idempotency key as `data`, and the same fix: device timestamp
+    /// and raw position.
+    fn find_duplicate(
+        &self,
+        data: &StoredData,
+        since: DateTime<Utc>,
+    ) -> Result<Option<StoredData>, StorageError>;
+
+    /// The point with the highest sequence number `device_id` sent since `since`.
+    fn highest_sequence(
+        &self,
+        device_id: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Option<StoredData>, StorageError>;
+
+    /// The point with the newest device timestamp for each device of the households
+    /// `household_ids`, ordered by device id.
+    fn latest(&self, household_ids: &[i64]) -> Result<Vec<StoredData>, StorageError>;
+
+    /// The point with the newest device timestamp from `device_id`, if it sent any.
+    fn device_latest(&self, device_id: &str) -> Result<Option<StoredData>, StorageError>;
+
+    /// Every device of the households `household_ids`, ordered by id.
+    fn devices(&self, household_ids: &[i64]) -> Result<Vec<Device>, StorageError>;
+
+    /// Every registered device, with or without a household, ordered by id.
+    fn all_devices(&self) -> Result<Vec<Device>, StorageError>;
+
+    /// The registered device with this id, if there is one.
+    fn device(&self, id: &str) -> Result<Option<Device>, StorageError>;
+
+    /// Registers a new device in `device.household_id`. Returns `false` if the id is already taken.
+    fn create_device(&self, device: &Device) -> Result<bool, StorageError>;
+
+    /// Moves every device without a household into `household_id`, returning how many there were.
+    fn claim_devices(&self, household_id: i64) -> Result<usize, StorageError>;
+
+    /// Replaces a registered device. Returns `false` if there is none with its id.
+    fn update_device(&self, device: &Device) -> Result<bool, StorageError>;
+
+    /// Removes a device, its points, keys, shares, alerts and geofences, and remembers it was
+    /// deleted at `now`: points under its id received before then are never stored again, so a
+    /// device registering the id later doesn't inherit its track. Returns `false` if it wasn't
+    /// registered.
+    fn delete_device(&self, id: &str, now: DateTime<Utc>) -> Result<bool, StorageError>;
+
+    /// Stores a new key for `device_id` and returns it, secret included.
+    fn create_key(
+        &self,
+        device_id: &str,
+        secret: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<DeviceKey, StorageError>;
+
+    /// Every key of `device_id`, secrets included, oldest first.
+    fn keys(&self, device_id: &str) -> Result<Vec<DeviceKey>, StorageError>;
+
+    /// Revokes a key. Returns `false` if `device_id` has no key with that id.
+    fn delete_key(&self, device_id: &str, key_id: i64) -> Result<bool, StorageError>;
+
+    /// Stores a new share link and returns it with its id.
+    fn create_share(&self, share: &Share) -> Result<Share, StorageError>;
+
+    /// Every share link of `device_id`, revoked and expired ones included, oldest first.
+    fn shares(&self, device_id: &str) -> Result<Vec<Share>, StorageError>;
+
+    /// The share link with this id, whatever its state.
+    fn share(&self, share_id: i64) -> Result<Option<Share>, StorageError>;
+
+    /// Revokes a share link at `now`. Returns `false` if `device_id` has no unrevoked share with that id.
+    fn revoke_share(
+        &self,
+        device_id: &str,
+        share_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<bool, StorageError>;
+
+    /// Stores a firing alert and returns it with its id, unless its device already has
+    /// an unresolved alert of the same kind.
+    fn create_alert(&self, alert: &Alert) -> Result<Option<Alert>, StorageError>;
+
+    /// Resolves `device_id`'s unresolved alerts of `kind` at `now`, returning them as resolved.
+    fn resolve_alerts(
+        &self,
+        device_id: &str,
+        kind: AlertKind,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Alert>, StorageError>;
+
+    /// The alerts matching `query` about devices of the households `household_ids`:
+    /// unresolved ones first, then newest first.
+    fn alerts(&self, query: &AlertQuery, household_ids: &[i64])
+    -> Result<Vec<Alert>, StorageError>;
+
+    /// The alert with this id, whatever its state.
+    fn alert(&self, alert_id: i64) -> Result<Option<Alert>, StorageError>;
+
+    /// Marks a firing alert as seen by `user_id`. Returns `false` if it isn't firing.
+    fn acknowledge_alert(
+        &self,
+        alert_id: i64,
+        user_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<bool, StorageError>;
+
+    /// Stores a new geofence and returns it with its id.
+    fn create_geofence(&self, geofence: &Geofence) -> Result<Geofence, StorageError>;
+
+    /// Every geofence of `device_id`, oldest first.
+    fn geofences(&self, device_id: &str) -> Result<Vec<Geofence>, StorageError>;
+
+    /// The geofence with this id.
+    fn geofence(&self, geofence_id: i64) -> Result<Option<Geofence>, StorageError>;
+
+    /// Replaces a geofence of its device. Returns `false` if the device has none with its id.
+    fn update_geofence(&self, geofence: &Geofence) -> Result<bool, StorageError>;
+
+    /// Removes a geofence and its events. Returns `false` if `device_id` has none with that id.
+    fn delete_geofence(&self, device_id: &str, geofence_id: i64) -> Result<bool, StorageError>;
+
+    /// Records which side of a geofence the device was on at its fix from `checked_at`,
+    /// storing `event` along with it. Returns the event with its id, if there was one.
+    /// Does nothing if the geofence was already checked against a newer fix.
+    fn record_geofence_check(
+        &self,
+        geofence_id: i64,
+        inside: bool,
+        checked_at: DateTime<Utc>,
+        event: Option<&GeofenceEvent>,
+    ) -> Result<Option<GeofenceEvent>, StorageError>;
+
+    /// The geofence events matching `query` of devices of the households `household_ids`,
+    /// newest fix first.
+    fn geofence_events(
+        &self,
+        query: &GeofenceEventQuery,
+        household_ids: &[i64],
+    ) -> Result<Vec<GeofenceEvent>, StorageError>;
+
+    /// Stores a new webhook and returns it with its id.
+    fn create_webhook(&self, webhook: &Webhook) -> Result<Webhook, StorageError>;
+
+    /// Every webhook of `household_id`, with its secret, oldest first.
+    fn webhooks(&self, household_id: i64) -> Result<Vec<Webhook>, StorageError>;
+
+    /// The webhook with this id, with its secret.
+    fn webhook(&self, webhook_id: i64) -> Result<Option<Webhook>, StorageError>;
+
+    /// Removes a webhook and its deliveries. Returns `false` if `household_id` has none
+    /// with that id.
+    fn delete_webhook(&self, household_id: i64, webhook_id: i64) -> Result<bool, StorageError>;
+
+    /// Puts a delivery in the outbox and returns it with its id.
+    fn create_delivery(&self, delivery: &Delivery) -> Result<Delivery, StorageError>;
+
+    /// Up to `limit` pending deliveries whose next attempt is due at `now`, longest waiting first.
+    fn due_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<Delivery>, StorageError>;
+
+    /// Records a delivery's state, attempts and error. Returns `false` if it no longer exists.
+    fn update_delivery(&self, delivery: &Delivery) -> Result<bool, StorageError>;
+
+    /// The deliveries of `household_id` matching `query`, newest first.
+    fn deliveries(
+        &self,
+        household_id: i64,
+        query: &DeliveryQuery,
+    ) -> Result<Vec<Delivery>, StorageError>;
+
+    /// The delivery with this id.
+    fn delivery(&self, delivery_id: i64) -> Result<Option<Delivery>, StorageError>;
+
+    /// Sets aside a post from an unregistered device.
+    fn quarantine(&self, post: &QuarantinedPost) -> Result<(), StorageError>;
+
+    /// Every quarantined post, oldest first.
+    fn quarantined(&self) -> Result<Vec<QuarantinedPost>, StorageError>;
+
+    /// Creates an account. Returns `None` if the username is taken (ignoring case).
+    fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<Option<User>, StorageError>;
+
+    /// How many accounts there are.
+    fn user_count(&self) -> Result<usize, StorageError>;
+
+    /// The account with this username (ignoring case) and its password hash.
+    fn user_credentials(&self, username: &str) -> Result<Option<(User, String)>, StorageError>;
+
+    /// What `user_id` wants emailed to them, if they ever said.
+    fn email_prefs(&self, user_id: i64) -> Result<Option<EmailPrefs>, StorageError>;
+
+    /// Replaces `user_id`'s email settings.
+    fn set_email_prefs(&self, user_id: i64, prefs: &EmailPrefs) -> Result<(), StorageError>;
+
+    /// Starts a session for `user_id`, identified by the hash of its token.
+    /// Sessions that have already expired are cleared out on the way.
+    fn create_session(
+        &self,
+        token_hash: &str,
+        user_id: i64,
+        created_at: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), StorageError>;
+
+    /// The user signed in with this session, unless it has expired by `now`.
+    fn session_user(
+        &self,
+        token_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<User>, StorageError>;
+
+    /// Ends a session.
+    fn delete_session(&self, token_hash: &str) -> Result<(), StorageError>;
+
+    /// Creates a household with `owner_id` as its first owner.
+    fn create_household(
+        &self,
+        name: &str,
+        owner_id: i64,
+        created_at: DateTime<Utc>,
+    ) -> Result<Household, StorageError>;
+
+    /// Every household `user_id` belongs to, oldest first, with their role in it.
+    fn memberships(&self, user_id: i64) -> Result<Vec<Membership>, StorageError>;
+
+    /// Everyone in `household_id`, in the order they joined.
+    fn members(&self, household_id: i64) -> Result<Vec<Member>, StorageError>;
+
+    /// Changes a member's role. Returns `false` if they aren't in the household.
+    fn set_role(&self, household_id: i64, user_id: i64, role: Role) -> Result<bool, StorageError>;
+
+    /// Takes someone out of a household. Returns `false` if they weren't in it.
+    fn remove_member(&self, household_id: i64, user_id: i64) -> Result<bool, StorageError>;
+
+    /// Stores an invitation, identified by the hash of its token.
+    fn create_invitation(
+        &self,
+        token_hash: &str,
+        invitation: &Invitation,
+        created_by: i64,
+    ) -> Result<(), StorageError>;
+
+    /// The invitation with this token hash, unless it has expired by `now` or was used.
+    fn invitation(
+        &self,
+        token_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Invitation>, StorageError>;
+
+    /// Uses up an invitation, adding `user_id` to its household with its role.
+    /// Returns `None` if the invitation has expired by `now` or was already used.
+    fn accept_invitation(
+        &self,
+        token_hash: &str,
+        user_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Membership>, StorageError>;
+}
+
+// --- Pagination ---
+
+/// Where the previous page stopped: the sort key of its last row.
+///
+/// Points are ordered by `(timestamp, row_id)`, so the cursor stays valid
+/// while new points keep arriving.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PageCursor {
+    timestamp: DateTime<Utc>,
+    row_id: i64,
+}
+
+impl PageCursor {
+    /// Opaque token handed to the client as `next`.
+    fn encode(&self) -> String {
+        format!(
+            "{}_{}",
+            self.timestamp.timestamp_nanos_opt().unwrap_or_default(),
+            self.row_id
+        )
+    }
+
+    fn decode(token: &str) -> Result<Self, StorageError> {
+        let invalid = || StorageError::InvalidCursor(token.to_string());
+        let (nanos, row_id) = token.split_once('_').ok_or_else(invalid)?;
+        Ok(Self {
+            timestamp: DateTime::from_timestamp_nanos(nanos.parse().map_err(|_| invalid())?),
+            row_id: row_id.parse().map_err(|_| invalid())?,
+        })
+    }
+
+    /// Whether a row with this sort key comes after the cursor in `order`.
+    fn precedes(&self, order: SortOrder, timestamp: DateTime<Utc>, row_id: i64) -> bool {
+        let key = (timestamp, row_id);
+        let cursor = (self.timestamp, self.row_id);
+        match order {
+            SortOrder::Asc => key > cursor,
+            SortOrder::Desc => key < cursor,
+        }
+    }
+}
+
+/// Cuts `rows` (fetched with one extra row) down to a page and derives its `next` cursor.
+fn into_page(mut rows: Vec<(i64, StoredData)>, page_size: usize) -> DataPage {
+    let next = if rows.len() > page_size {
+        rows.truncate(page_size);
+        rows.last().map(|(row_id, data)| {
+            PageCursor {
+                timestamp: data.timestamp,
+                row_id: *row_id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    DataPage {
+        data: rows.into_iter().map(|(_, data)| data).collect(),
+        next,
+    }
+}
+
+// --- In-Memory Backend ---
+
+/// Keeps everything in a `Vec`; nothing survives a restart.
+/// Handy for tests and throwaway local runs.
+#[derive(Default)]
+pub struct MemoryStore {
+    data_points: RwLock<Vec<StoredData>>,
+    /// Log sequence number of each point in `data_points`, by position.
+    point_lsns: RwLock<Vec<u64>>,
+    applied_lsn: AtomicU64,
+    devices: RwLock<BTreeMap<String, Device>>,
+    keys: RwLock<Vec<DeviceKey>>,
+    shares: RwLock<Vec<Share>>,
+    alerts: RwLock<Vec<Alert>>,
+    geofences: RwLock<Vec<Geofence>>,
+    geofence_events: RwLock<Vec<GeofenceEvent>>,
+    webhooks: RwLock<Vec<Webhook>>,
+    deliveries: RwLock<Vec<Delivery>>,
+    quarantined: RwLock<Vec<QuarantinedPost>>,
+    users: RwLock<Vec<(User, String)>>,
+    /// Session user and expiry, by token hash.
+    sessions: RwLock<BTreeMap<String, (i64, DateTime<Utc>)>>,
+    /// Email settings, by user id.
+    email_prefs: RwLock<BTreeMap<i64, EmailPrefs>>,
+    households: RwLock<Vec<Household>>,
+    /// Members, by household id.
+    members: RwLock<Vec<(i64, Member)>>,
+    /// Invitations and who accepted them, by token hash.
+    invitations: RwLock<BTreeMap<String, (Invitation, Option<i64>)>>,
+    /// When each deleted device was deleted, by id.
+    deleted_devices: RwLock<BTreeMap<String, DateTime<Utc>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ids of the devices in the households `household_ids`.
+    fn household_devices(&self, household_ids: &[i64]) -> Result<Vec<String>, StorageError> {
+        let devices = self.devices.read().map_err(|_| StorageError::Lock)?;
+        Ok(devices
+            .values()
+            .filter(|device| {
+                device
+                    .household_id
+                    .is_some_and(|id| household_ids.contains(&id))
+            })
+            .map(|device| device.id.clone())
+            .collect())
+    }
+
+    /// Drops every point `device_id` sent, along with their log sequence numbers.
+    fn delete_points(&self, device_id: &str) -> Result<(), StorageError> {
+        let mut data_points = self.data_points.write().map_err(|_| StorageError::Lock)?;
+        let mut point_lsns = self.point_lsns.write().map_err(|_| StorageError::Lock)?;
+        let (points, lsns) = data_points
+            .drain(..)
+            .zip(point_lsns.drain(..))
+            .filter(|(data, _)| data.id != device_id)
+            .unzip();
+        *data_points = points;
+        *point_lsns = lsns;
+        Ok(())
+    }
+}
+
+impl DataStore for MemoryStore {
+    fn insert_batch(&self, records: &[(u64, &StoredData)]) -> Result<(), StorageError> {
+        let mut data_points = self.data_points.write().map_err(|_| StorageError::Lock)?;
+        let mut point_lsns = self.point_lsns.write().map_err(|_| StorageError::Lock)?;
+        let deleted_devices = self
+            .deleted_devices
+            .read()
+            .map_err(|_| StorageError::Lock)?;
+        for (lsn, data) in records {
+            let from_deleted_device = deleted_devices
+                .get(&data.id)
+                .is_some_and(|deleted_at| data.received_at <= *deleted_at);
+            if !from_deleted_device {
+                data_points.push((*data).clone());
+                point_lsns.push(*lsn);
+            }
+            self.applied_lsn.fetch_max(*lsn, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    fn applied_lsn(&self) -> Result<u64, StorageError> {
+        Ok(self.applied_lsn.load(Ordering::SeqCst))
+    }
+
+    fn query(&self, query: &DataQuery, household_ids: &[i64]) -> Result<DataPage, StorageError> {
+        let cursor = query
+            .cursor
+            .as_deref()
+            .map(PageCursor::decode)
+            .transpose()?;
+        let device_ids = self.household_devices(household_ids)?;
+        let data_points = self.data_points.read().map_err(|_| StorageError::Lock)?;
+
+        // The position in the Vec stands in for SQLite's row_id
+        let mut rows: Vec<(i64, StoredData)> = data_points
+            .iter()
+            .enumerate()
+            .map(|(index, data)| (index as i64 + 1, data))
+            .filter(|(_, data)| device_ids.contains(&data.id))
+            .filter(|(_, data)| query.device_id.as_ref().is_none_or(|id| &data.id == id))
+            .filter(|(_, data)| query.from.is_none_or(|from| data.timestamp >= from))
+            .filter(|(_, data)| query.to.is_none_or(|to| data.timestamp < to))
+            .filter(|(row_id, data)| {
+                cursor.is_none_or(|c| c.precedes(query.order, data.timestamp, *row_id))
+            })
+            .map(|(row_id, data)| (row_id, data.clone()))
+            .collect();
+
+        rows.sort_by_key(|(row_id, data)| (data.timestamp, *row_id));
+        if query.order == SortOrder::Desc {
+            rows.reverse();
+        }
+        rows.truncate(query.page_size() + 1);
+        Ok(into_page(rows, query.page_size()))
+    }
+
+    fn since(
+        &self,
+        after_lsn: u64,
+        household_ids: &[i64],
+        device_id: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(u64, StoredData)>, StorageError> {
+        let device_ids = self.household_devices(household_ids)?;
+        let data_points = self.data_points.read().map_err(|_| StorageError::Lock)?;
+        let point_lsns = self.point_lsns.read().map_err(|_| StorageError::Lock)?;
+        // Points are pushed in log order, so no sorting is needed
+        Ok(point_lsns
+            .iter()
+            .zip(data_points.iter())
+            .filter(|(lsn, data)| {
+                **lsn > after_lsn
+                    && device_ids.contains(&data.id)
+                    && device_id.is_none_or(|id| data.id == id)
+            })
+            .take(limit)
+            .map(|(lsn, data)| (*lsn, data.clone()))
+            .collect())
+    }
+
+    fn find_duplicate(
+        &self,
+        data: &StoredData,
+        since: DateTime<Utc>,
+    ) -> Result<Option<StoredData>, StorageError> {
+        let data_points = self.data_points.read().map_err(|_| StorageError::Lock)?;
+        Ok(data_points
+            .iter()
+            .filter(|stored| stored.id == data.id && stored.received_at >= since)
+            .filter(|stored| {
+                (data.sequence.is_some() && stored.sequence == data.sequence)
+                    || (data.idempotency_key.is_some()
+                        && stored.idempotency_key == data.idempotency_key)
+            })
+            .find(|stored| {
+                stored.timestamp == data.timestamp
+                    && stored.longitude == data.longitude
+                    && stored.latitude == data.latitude
+            })
+            .cloned())
+    }
+
+    fn highest_sequence(
+        &self,
+        device_id: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Option<StoredData>, StorageError> {
+        let data_points = self.data_points.read().map_err(|_| StorageError::Lock)?;
+        // Later insertions win ties, like the row_id tie-break in SQLite
+        Ok(data_points
+            .iter()
+            .filter(|stored| stored.id == device_id && stored.received_at >= since)
+            .filter(|stored| stored.sequence.is_some())
+            .max_by_key(|stored| stored.sequence)
+            .cloned())
+    }
+
+    fn latest(&self, household_ids: &[i64]) -> Result<Vec<StoredData>, StorageError> {
+        let device_ids = self.household_devices(household_ids)?;
+        let data_points = self.data_points.read().map_err(|_| StorageError::Lock)?;
+        // Later insertions win ties, like the row_id tie-break in SQLite
+        let mut latest = BTreeMap::<&str, &StoredData>::new();
+        for data in data_points
+            .iter()
+            .filter(|data| device_ids.contains(&data.id))
+        {
+            match latest.get(data.id.as_str()) {
+                Some(current) if current.timestamp > data.timestamp => {}
+                _ => {
+                    latest.insert(&data.id, data);
+                }
+            }
+        }
+        Ok(latest.into_values().cloned().collect())
+    }
+
+    fn device_latest(&self, device_id: &str) -> Result<Option<StoredData>, StorageError> {
+        let data_points = self.data_points.read().map_err(|_| StorageError::Lock)?;
+        // Later insertions win ties, like the row_id tie-break in SQLite
+        Ok(data_points
+            .iter()
+            .filter(|data| data.id == device_id)
+            .max_by_key(|data| data.timestamp)
+            .cloned())
+    }
+
+    fn devices(&self, household_ids: &[i64]) -> Result<Vec<Device>, StorageError> {
+        let devices = self.devices.read().map_err(|_| StorageError::Lock)?;
+        Ok(devices
+            .values()
+            .filter(|device| {
+                device
+                    .household_id
+                    .is_some_and(|id| household_ids.contains(&id))
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn all_devices(&self) -> Result<Vec<Device>, StorageError> {
+        let devices = self.devices.read().map_err(|_| StorageError::Lock)?;
+        Ok(devices.values().cloned().collect())
+    }
+
+    fn device(&self, id: &str) -> Result<Option<Device>, StorageError> {
+        let devices = self.devices.read().map_err(|_| StorageError::Lock)?;
+        Ok(devices.get(id).cloned())
+    }
+
+    fn create_device(&self, device: &Device) -> Result<bool, StorageError> {
+        let mut devices = self.devices.write().map_err(|_| StorageError::Lock)?;
+        if devices.contains_key(&device.id) {
+            return Ok(false);
+        }
+        devices.insert(device.id.clone(), device.clone());
+        Ok(true)
+    }
+
+    fn claim_devices(&self, household_id: i64) -> Result<usize, StorageError> {
+        let mut devices = self.devices.write().map_err(|_| StorageError::Lock)?;
+        let mut claimed = 0;
+        for device in devices.values_mut() {
+            if device.household_id.is_none() {
+                device.household_id = Some(household_id);
+                claimed += 1;
+            }
+        }
+        Ok(claimed)
+    }
+
+    fn update_device(&self, device: &Device) -> Result<bool, StorageError> {
+        let mut devices = self.devices.write().map_err(|_| StorageError::Lock)?;
+        match devices.get_mut(&device.id) {
+            Some(existing) => {
+                *existing = device.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn delete_device(&self, id: &str, now: DateTime<Utc>) -> Result<bool, StorageError> {
+        let mut devices = self.devices.write().map_err(|_| StorageError::Lock)?;
+        let mut keys = self.keys.write().map_err(|_| StorageError::Lock)?;
+        let mut shares = self.shares.write().map_err(|_| StorageError::Lock)?;
+        let mut alerts = self.alerts.write().map_err(|_| StorageError::Lock)?;
+        let mut geofences = self.geofences.write().map_err(|_| StorageError::Lock)?;
+        let mut events = self
+            .geofence_events
+            .write()
+            .map_err(|_| StorageError::Lock)?;
+        keys.retain(|key| key.device_id != id);
+        shares.retain(|share| share.device_id != id);
+        alerts.retain(|alert| alert.device_id != id);
+        geofences.retain(|geofence| geofence.device_id != id);
+        events.retain(|event| event.device_id != id);
+        self.delete_points(id)?;
+        if devices.remove(id).is_none() {
+            return Ok(false);
+        }
+        self.deleted_devices
+            .write()
+            .map_err(|_| StorageError::Lock)?
+            .insert(id.to_string(), now);
+        Ok(true)
+    }
+
+    fn create_key(
+        &self,
+        device_id: &str,
+        secret: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<DeviceKey, StorageError> {
+        let mut keys = self.keys.write().map_err(|_| StorageError::Lock)?;
+        let key = DeviceKey {
+            key_id: keys.last().map_or(1, |k| k.key_id + 1),
+            device_id: device_id.to_string(),
+            created_at,
+            secret: Some(secret.to_string()),
+        };
+        keys.push(key.clone());
+        Ok(key)
+    }
+
+    fn keys(&self, device_id: &str) -> Result<Vec<DeviceKey>, StorageError> {
+        let keys = self.keys.read().map_err(|_| StorageError::Lock)?;
+        Ok(keys
+            .iter()
+            .filter(|key| key.device_id == device_id)
+            .cloned()
+            .collect())
+    }
+
+    fn delete_key(&self, device_id: &str, key_id: i64) -> Result<bool, StorageError> {
+        let mut keys = self.keys.write().map_err(|_| StorageError::Lock)?;
+        let before = keys.len();
+        keys.retain(|key| !(key.device_id == device_id && key.key_id == key_id));
+        Ok(keys.len() < before)
+    }
+
+    fn create_share(&self, share: &Share) -> Result<Share, StorageError> {
+        let mut shares = self.shares.write().map_err(|_| StorageError::Lock)?;
+        let share = Share {
+            share_id: shares.last().map_or(1, |s| s.share_id + 1),
+            token: None,
+            ..share.clone()
+        };
+        shares.push(share.clone());
+        Ok(share)
+    }
+
+    fn shares(&self, device_id: &str) -> Result<Vec<Share>, StorageError> {
+        let shares = self.shares.read().map_err(|_| StorageError::Lock)?;
+        Ok(shares
+            .iter()
+            .filter(|share| share.device_id == device_id)
+            .cloned()
+            .collect())
+    }
+
+    fn share(&self, share_id: i64) -> Result<Option<Share>, StorageError> {
+        let shares = self.shares.read().map_err(|_| StorageError::Lock)?;
+        Ok(shares
+            .iter()
+            .find(|share| share.share_id == share_id)
+            .cloned())
+    }
+
+    fn revoke_share(
+        &self,
+        device_id: &str,
+        share_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<bool, StorageError> {
+        let mut shares = self.shares.write().map_err(|_| StorageError::Lock)?;
+        match shares.iter_mut().find(|share| {
+            share.share_id == share_id && share.device_id == device_id && share.revoked_at.is_none()
+        }) {
+            Some(share) => {
+                share.revoked_at = Some(now);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn create_alert(&self, alert: &Alert) -> Result<Option<Alert>, StorageError> {
+        let mut alerts = self.alerts.write().map_err(|_| StorageError::Lock)?;
+        if alerts.iter().any(|open| {
+            open.device_id == alert.device_id && open.kind == alert.kind && open.is_open()
+        }) {
+            return Ok(None);
+        }
+        let alert = Alert {
+            alert_id: alerts.last().map_or(1, |a| a.alert_id + 1),
+            ..alert.clone()
+        };
+        alerts.push(alert.clone());
+        Ok(Some(alert))
+    }
+
+    fn resolve_alerts(
+        &self,
+        device_id: &str,
+        kind: AlertKind,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Alert>, StorageError> {
+        let mut alerts = self.alerts.write().map_err(|_| StorageError::Lock)?;
+        Ok(alerts
+            .iter_mut()
+            .filter(|alert| alert.device_id == device_id && alert.kind == kind && alert.is_open())
+            .map(|alert| {
+                alert.state = AlertState::Resolved;
+                alert.resolved_at = Some(now);
+                alert.clone()
+            })
+            .collect())
+    }
+
+    fn alerts(
+        &self,
+        query: &AlertQuery,
+        household_ids: &[i64],
+    ) -> Result<Vec<Alert>, StorageError> {
+        let device_ids = self.household_devices(household_ids)?;
+        let alerts = self.alerts.read().map_err(|_| StorageError::Lock)?;
+        let mut found: Vec<Alert> = alerts
+            .iter()
+            .filter(|alert| device_ids.contains(&alert.device_id))
+            .filter(|alert| {
+                query
+                    .device_id
+                    .as_ref()
+                    .is_none_or(|id| &alert.device_id == id)
+            })
+            .filter(|alert| query.state.is_none_or(|state| alert.state == state))
+            .cloned()
+            .collect();
+        found.sort_by_key(|alert| {
+            (
+                !alert.is_open(),
+                std::cmp::Reverse((alert.fired_at, alert.alert_id)),
+            )
+        });
+        found.truncate(query.limit());
+        Ok(found)
+    }
+
+    fn alert(&self, alert_id: i64) -> Result<Option<Alert>, StorageError> {
+        let alerts = self.alerts.read().map_err(|_| StorageError::Lock)?;
+        Ok(alerts
+            .iter()
+            .find(|alert| alert.alert_id == alert_id)
+            .cloned())
+    }
+
+    fn acknowledge_alert(
+        &self,
+        alert_id: i64,
+        user_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<bool, StorageError> {
+        let mut alerts = self.alerts.write().map_err(|_| StorageError::Lock)?;
+        match alerts
+            .iter_mut()
+            .find(|alert| alert.alert_id == alert_id && alert.state == AlertState::Firing)
+        {
+            Some(alert) => {
+                alert.state = AlertState::Acknowledged;
+                alert.acknowledged_at = Some(now);
+                alert.acknowledged_by = Some(user_id);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn create_geofence(&self, geofence: &Geofence) -> Result<Geofence, StorageError> {
+        let mut geofences = self.geofences.write().map_err(|_| StorageError::Lock)?;
+        let geofence = Geofence {
+            geofence_id: geofences.last().map_or(1, |g| g.geofence_id + 1),
+            ..geofence.clone()
+        };
+        geofences.push(geofence.clone());
+        Ok(geofence)
+    }
+
+    fn geofences(&self, device_id: &str) -> Result<Vec<Geofence>, StorageError> {
+        let geofences = self.geofences.read().map_err(|_| StorageError::Lock)?;
+        Ok(geofences
+            .iter()
+            .filter(|geofence| geofence.device_id == device_id)
+            .cloned()
+            .collect())
+    }
+
+    fn geofence(&self, geofence_id: i64) -> Result<Option<Geofence>, StorageError> {
+        let geofences = self.geofences.read().map_err(|_| StorageError::Lock)?;
+        Ok(geofences
+            .iter()
+            .find(|geofence| geofence.geofence_id == geofence_id)
+            .cloned())
+    }
+
+    fn update_geofence(&self, geofence: &Geofence) -> Result<bool, StorageError> {
+        let mut geofences = self.geofences.write().map_err(|_| StorageError::Lock)?;
+        match geofences.iter_mut().find(|stored| {
+            stored.geofence_id == geofence.geofence_id && stored.device_id == geofence.device_id
+        }) {
+            Some(stored) => {
+                *stored = Geofence {
+                    created_at: stored.created_at,
+                    ..geofence.clone()
+                };
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn delete_geofence(&self, device_id: &str, geofence_id: i64) -> Result<bool, StorageError> {
+        let mut geofences = self.geofences.write().map_err(|_| StorageError::Lock)?;
+        let mut events = self
+            .geofence_events
+            .write()
+            .map_err(|_| StorageError::Lock)?;
+        let before = geofences.len();
+        geofences.retain(|g| !(g.device_id == device_id && g.geofence_id == geofence_id));
+        if geofences.len() == before {
+            return Ok(false);
+        }
+        events.retain(|event| event.geofence_id != geofence_id);
+        Ok(true)
+    }
+
+    fn record_geofence_check(
+        &self,
+        geofence_id: i64,
+        inside: bool,
+        checked_at: DateTime<Utc>,
+        event: Option<&GeofenceEvent>,
+    ) -> Result<Option<GeofenceEvent>, StorageError> {
+        let mut geofences = self.geofences.write().map_err(|_| StorageError::Lock)?;
+        let mut events = self
+            .geofence_events
+            .write()
+            .map_err(|_| StorageError::Lock)?;
+        let Some(geofence) = geofences.iter_mut().find(|geofence| {
+            geofence.geofence_id == geofence_id
+                && geofence.checked_at.is_none_or(|at| at <= checked_at)
+        }) else {
+            return Ok(None);
+        };
+        geofence.inside = Some(inside);
+        geofence.checked_at = Some(checked_at);
+        Ok(event.map(|event| {
+            let event = GeofenceEvent {
+                event_id: events.last().map_or(1, |e| e.event_id + 1),
+                ..event.clone()
+            };
+            events.push(event.clone());
+            event
+        }))
+    }
+
+    fn geofence_events(
+        &self,
+        query: &GeofenceEventQuery,
+        household_ids: &[i64],
+    ) -> Result<Vec<GeofenceEvent>, StorageError> {
+        let device_ids = self.household_devices(household_ids)?;
+        let events = self
+            .geofence_events
+            .read()
+            .map_err(|_| StorageError::Lock)?;
+        let mut found: Vec<GeofenceEvent> = events
+            .iter()
+            .filter(|event| device_ids.contains(&event.device_id))
+            .filter(|event| {
+                query
+                    .device_id
+                    .as_ref()
+                    .is_none_or(|id| &event.device_id == id)
+            })
+            .filter(|event| query.geofence_id.is_none_or(|id| event.geofence_id == id))
+            .cloned()
+            .collect();
+        found.sort_by_key(|event| std::cmp::Reverse((event.fix.timestamp, event.event_id)));
+        found.truncate(query.limit());
+        Ok(found)
+    }
+
+    fn create_webhook(&self, webhook: &Webhook) -> Result<Webhook, StorageError> {
+        let mut webhooks = self.webhooks.write().map_err(|_| StorageError::Lock)?;
+        let webhook = Webhook {
+            webhook_id: webhooks.last().map_or(1, |w| w.webhook_id + 1),
+            ..webhook.clone()
+        };
+        webhooks.push(webhook.clone());
+        Ok(webhook)
+    }
+
+    fn webhooks(&self, household_id: i64) -> Result<Vec<Webhook>, StorageError> {
+        let webhooks = self.webhooks.read().map_err(|_| StorageError::Lock)?;
+        Ok(webhooks
+            .iter()
+            .filter(|webhook| webhook.household_id == household_id)
+            .cloned()
+            .collect())
+    }
+
+    fn webhook(&self, webhook_id: i64) -> Result<Option<Webhook>, StorageError> {
+        let webhooks = self.webhooks.read().map_err(|_| StorageError::Lock)?;
+        Ok(webhooks
+            .iter()
+            .find(|webhook| webhook.webhook_id == webhook_id)
+            .cloned())
+    }
+
+    fn delete_webhook(&self, household_id: i64, webhook_id: i64) -> Result<bool, StorageError> {
+        let mut webhooks = self.webhooks.write().map_err(|_| StorageError::Lock)?;
+        let mut deliveries = self.deliveries.write().map_err(|_| StorageError::Lock)?;
+        let before = webhooks.len();
+        webhooks.retain(|w| !(w.household_id == household_id && w.webhook_id == webhook_id));
+        if webhooks.len() == before {
+            return Ok(false);
+        }
+        deliveries.retain(|delivery| delivery.webhook_id != webhook_id);
+        Ok(true)
+    }
+
+    fn create_delivery(&self, delivery: &Delivery) -> Result<Delivery, StorageError> {
+        let mut deliveries = self.deliveries.write().map_err(|_| StorageError::Lock)?;
+        let delivery = Delivery {
+            delivery_id: deliveries.last().map_or(1, |d| d.delivery_id + 1),
+            ..delivery.clone()
+        };
+        deliveries.push(delivery.clone());
+        Ok(delivery)
+    }
+
+    fn due_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<Delivery>, StorageError> {
+        let deliveries = self.deliveries.read().map_err(|_| StorageError::Lock)?;
+        let mut due: Vec<Delivery> = deliveries
+            .iter()
+            .filter(|d| d.state == DeliveryState::Pending && d.next_attempt_at <= now)
+            .cloned()
+            .collect();
+        due.sort_by_key(|delivery| (delivery.next_attempt_at, delivery.delivery_id));
+        due.truncate(limit);
+        Ok(due)
+    }
+
+    fn update_delivery(&self, delivery: &Delivery) -> Result<bool, StorageError> {
+        let mut deliveries = self.deliveries.write().map_err(|_| StorageError::Lock)?;
+        match deliveries
+            .iter_mut()
+            .find(|stored| stored.delivery_id == delivery.delivery_id)
+        {
+            Some(stored) => {
+                *stored = delivery.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn deliveries(
+        &self,
+        household_id: i64,
+        query: &DeliveryQuery,
+    ) -> Result<Vec<Delivery>, StorageError> {
+        let deliveries = self.deliveries.read().map_err(|_| StorageError::Lock)?;
+        let mut found: Vec<Delivery> = deliveries
+            .iter()
+            .filter(|delivery| delivery.household_id == household_id)
+            .filter(|delivery| query.state.is_none_or(|state| delivery.state == state))
+            .cloned()
+            .collect();
+        found
+            .sort_by_key(|delivery| std::cmp::Reverse((delivery.created_at, delivery.delivery_id)));
+        found.truncate(query.limit());
+        Ok(found)
+    }
+
+    fn delivery(&self, delivery_id: i64) -> Result<Option<Delivery>, StorageError> {
+        let deliveries = self.deliveries.read().map_err(|_| StorageError::Lock)?;
+        Ok(deliveries
+            .iter()
+            .find(|delivery| delivery.delivery_id == delivery_id)
+            .cloned())
+    }
+
+    fn quarantine(&self, post: &QuarantinedPost) -> Result<(), StorageError> {
+        let mut quarantined = self.quarantined.write().map_err(|_| StorageError::Lock)?;
+        quarantined.push(post.clone());
+        Ok(())
+    }
+
+    fn quarantined(&self) -> Result<Vec<QuarantinedPost>, StorageError> {
+        let quarantined = self.quarantined.read().map_err(|_| StorageError::Lock)?;
+        Ok(quarantined.clone())
+    }
+
+    fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<Option<User>, StorageError> {
+        let mut users = self.users.write().map_err(|_| StorageError::Lock)?;
+        if users
+            .iter()
+            .any(|(user, _)| user.username.eq_ignore_ascii_case(username))
+        {
+            return Ok(None);
+        }
+        let user = User {
+            user_id: users.last().map_or(1, |(u, _)| u.user_id + 1),
+            username: username.to_string(),
+            created_at,
+        };
+        users.push((user.clone(), password_hash.to_string()));
+        Ok(Some(user))
+    }
+
+    fn user_count(&self) -> Result<usize, StorageError> {
+        let users = self.users.read().map_err(|_| StorageError::Lock)?;
+        Ok(users.len())
+    }
+
+    fn user_credentials(&self, username: &str) -> Result<Option<(User, String)>, StorageError> {
+        let users = self.users.read().map_err(|_| StorageError::Lock)?;
+        Ok(users
+            .iter()
+            .find(|(user, _)| user.username.eq_ignore_ascii_case(username))
+            .cloned())
+    }
+
+    fn email_prefs(&self, user_id: i64) -> Result<Option<EmailPrefs>, StorageError> {
+        let email_prefs = self.email_prefs.read().map_err(|_| StorageError::Lock)?;
+        Ok(email_prefs.get(&user_id).cloned())
+    }
+
+    fn set_email_prefs(&self, user_id: i64, prefs: &EmailPrefs) -> Result<(), StorageError> {
+        let mut email_prefs = self.email_prefs.write().map_err(|_| StorageError::Lock)?;
+        email_prefs.insert(user_id, prefs.clone());
+        Ok(())
+    }
+
+    fn create_session(
+        &self,
+        token_hash: &str,
+        user_id: i64,
+        created_at: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), StorageError> {
+        let mut sessions = self.sessions.write().map_err(|_| StorageError::Lock)?;
+        sessions.retain(|_, (_, expires_at)| *expires_at > created_at);
+        sessions.insert(token_hash.to_string(), (user_id, expires_at));
+        Ok(())
+    }
+
+    fn session_user(
+        &self,
+        token_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<User>, StorageError> {
+        let sessions = self.sessions.read().map_err(|_| StorageError::Lock)?;
+        let users = self.users.read().map_err(|_| StorageError::Lock)?;
+        Ok(sessions
+            .get(token_hash)
+            .filter(|(_, expires_at)| *expires_at > now)
+            .and_then(|(user_id, _)| users.iter().find(|(user, _)| user.user_id == *user_id))
+            .map(|(user, _)| user.clone()))
+    }
+
+    fn delete_session(&self, token_hash: &str) -> Result<(), StorageError> {
+        let mut sessions = self.sessions.write().map_err(|_| StorageError::Lock)?;
+        sessions.remove(token_hash);
+        Ok(())
+    }
+
+    fn create_household(
+        &self,
+        name: &str,
+        owner_id: i64,
+        created_at: DateTime<Utc>,
+    ) -> Result<Household, StorageError> {
+        let users = self.users.read().map_err(|_| StorageError::Lock)?;
+        let mut households = self.households.write().map_err(|_| StorageError::Lock)?;
+        let mut members = self.members.write().map_err(|_| StorageError::Lock)?;
+        let household = Household {
+            household_id: households.last().map_or(1, |h| h.household_id + 1),
+            name: name.to_string(),
+            created_at,
+        };
+        let username = users
+            .iter()
+            .find(|(user, _)| user.user_id == owner_id)
+            .map(|(user, _)| user.username.clone())
+            .unwrap_or_default();
+        households.push(household.clone());
+        members.push((
+            household.household_id,
+            Member {
+                user_id: owner_id,
+                username,
+                role: Role::Owner,
+                joined_at: created_at,
+            },
+        ));
+        Ok(household)
+    }
+
+    fn memberships(&self, user_id: i64) -> Result<Vec<Membership>, StorageError> {
+        let households = self.households.read().map_err(|_| StorageError::Lock)?;
+        let members = self.members.read().map_err(|_| StorageError::Lock)?;
+        Ok(households
+            .iter()
+            .filter_map(|household| {
+                members
+                    .iter()
+                    .find(|(id, member)| *id == household.household_id && member.user_id == user_id)
+                    .map(|(_, member)| Membership {
+                        household: household.clone(),
+                        role: member.role,
+                    })
+            })
+            .collect())
+    }
+
+    fn members(&self, household_id: i64) -> Result<Vec<Member>, StorageError> {
+        let members = self.members.read().map_err(|_| StorageError::Lock)?;
+        Ok(members
+            .iter()
+            .filter(|(id, _)| *id == household_id)
+            .map(|(_, member)| member.clone())
+            .collect())
+    }
+
+    fn set_role(&self, household_id: i64, user_id: i64, role: Role) -> Result<bool, StorageError> {
+        let mut members = self.members.write().map_err(|_| StorageError::Lock)?;
+        match members
+            .iter_mut()
+            .find(|(id, member)| *id == household_id && member.user_id == user_id)
+        {
+            Some((_, member)) => {
+                member.role = role;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn remove_member(&self, household_id: i64, user_id: i64) -> Result<bool, StorageError> {
+        let mut members = self.members.write().map_err(|_| StorageError::Lock)?;
+        let before = members.len();
+        members.retain(|(id, member)| !(*id == household_id && member.user_id == user_id));
+        Ok(members.len() < before)
+    }
+
+    fn create_invitation(
+        &self,
+        token_hash: &str,
+        invitation: &Invitation,
+        _created_by: i64,
+    ) -> Result<(), StorageError> {
+        let mut invitations = self.invitations.write().map_err(|_| StorageError::Lock)?;
+        let invitation = Invitation {
+            token: None,
+            ..invitation.clone()
+        };
+        invitations.insert(token_hash.to_string(), (invitation, None));
+        Ok(())
+    }
+
+    fn invitation(
+        &self,
+        token_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Invitation>, StorageError> {
+        let invitations = self.invitations.read().map_err(|_| StorageError::Lock)?;
+        Ok(invitations
+            .get(token_hash)
+            .filter(|(invitation, accepted_by)| {
+                accepted_by.is_none() && invitation.expires_at > now
+            })
+            .map(|(invitation, _)| invitation.clone()))
+    }
+
+    fn accept_invitation(
+        &self,
+        token_hash: &str,
+        user_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Membership>, StorageError> {
+        let users = self.users.read().map_err(|_| StorageError::Lock)?;
+        let households = self.households.read().map_err(|_| StorageError::Lock)?;
+        let mut members = self.members.write().map_err(|_| StorageError::Lock)?;
+        let mut invitations = self.invitations.write().map_err(|_| StorageError::Lock)?;
+        let Some((invitation, accepted_by)) =
+            invitations
+                .get_mut(token_hash)
+                .filter(|(invitation, accepted_by)| {
+                    accepted_by.is_none() && invitation.expires_at > now
+                })
+        else {
+            return Ok(None);
+        };
+        let Some(household) = households
+            .iter()
+            .find(|h| h.household_id == invitation.household_id)
+        else {
+            return Ok(None);
+        };
+
+        *accepted_by = Some(user_id);
+        let username = users
+            .iter()
+            .find(|(user, _)| user.user_id == user_id)
+            .map(|(user, _)| user.username.clone())
+            .unwrap_or_default();
+        members.push((
+            household.household_id,
+            Member {
+                user_id,
+                username,
+                role: invitation.role,
+                joined_at: now,
+            },
+        ));
+        Ok(Some(Membership {
+            household: household.clone(),
+            role: invitation.role,
+        }))
+    }
+}
+
+// --- SQLite Backend ---
+
+/// Schema migrations, applied in order on startup.
+/// The index of each entry + 1 is stored in `PRAGMA user_version` once it has run,
+/// so new migrations must only ever be appended to the end of this list.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE data_points (
+        row_id    INTEGER PRIMARY KEY AUTOINCREMENT,
+        id        TEXT    NOT NULL,
+        longitude INTEGER NOT NULL,
+        latitude  INTEGER NOT NULL,
+        battery   INTEGER NOT NULL,
+        timestamp TEXT    NOT NULL
+    );
+    CREATE INDEX idx_data_points_id ON data_points (id);",
+    // 2: write-ahead log sequence number of each point (NULL for points stored before the log existed)
+    "ALTER TABLE data_points ADD COLUMN lsn INTEGER;
+    CREATE INDEX idx_data_points_lsn ON data_points (lsn);",
+    // 3: decoded WGS84 coordinates; rows stored before this assume the default world-wide encoding
+    "ALTER TABLE data_points ADD COLUMN longitude_deg REAL NOT NULL DEFAULT 0;
+    ALTER TABLE data_points ADD COLUMN latitude_deg REAL NOT NULL DEFAULT 0;
+    UPDATE data_points SET
+        longitude_deg = -180.0 + longitude * 360.0 / 65535.0,
+        latitude_deg  = -90.0 + latitude * 180.0 / 65535.0;",
+    // 4: versioned payloads and their optional fields
+    "ALTER TABLE data_points ADD COLUMN payload_version INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE data_points ADD COLUMN altitude INTEGER;
+    ALTER TABLE data_points ADD COLUMN hdop REAL;
+    ALTER TABLE data_points ADD COLUMN satellites INTEGER;
+    ALTER TABLE data_points ADD COLUMN speed REAL;
+    ALTER TABLE data_points ADD COLUMN sequence INTEGER;",
+    // 5: timestamps become UTC instants; the legacy free-form "date time" strings were ICT (+07:00)
+    "UPDATE data_points SET timestamp = COALESCE(
+        strftime('%Y-%m-%d %H:%M:%S+00:00', timestamp, '-7 hours'),
+        '1970-01-01 00:00:00+00:00'
+    );
+    CREATE INDEX idx_data_points_timestamp ON data_points (timestamp);",
+    // 6: server receipt time and device clock skew; unknown for older rows, so assume no skew
+    "ALTER TABLE data_points ADD COLUMN received_at TEXT NOT NULL DEFAULT '';
+    ALTER TABLE data_points ADD COLUMN clock_skew_secs INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE data_points ADD COLUMN clock_skew_flagged INTEGER NOT NULL DEFAULT 0;
+    UPDATE data_points SET received_at = timestamp;",
+    // 7: device registry, and posts from unregistered devices held for review
+    "CREATE TABLE devices (
+        id            TEXT    PRIMARY KEY,
+        display_name  TEXT    NOT NULL,
+        pet_name      TEXT,
+        photo_url     TEXT,
+        start_hour    INTEGER NOT NULL,
+        end_hour      INTEGER NOT NULL,
+        interval_secs INTEGER NOT NULL,
+        timezone      TEXT,
+        min_longitude REAL,
+        min_latitude  REAL,
+        max_longitude REAL,
+        max_latitude  REAL
+    );
+    CREATE TABLE quarantined_posts (
+        row_id      INTEGER PRIMARY KEY AUTOINCREMENT,
+        id          TEXT NOT NULL,
+        payload     TEXT NOT NULL,
+        date        TEXT NOT NULL,
+        time        TEXT NOT NULL,
+        tz          TEXT,
+        received_at TEXT NOT NULL
+    );",
+    // 8: per-device secrets for authenticating posts; several per device while keys are rotated
+    "CREATE TABLE device_keys (
+        key_id     INTEGER PRIMARY KEY AUTOINCREMENT,
+        device_id  TEXT NOT NULL,
+        secret     TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE INDEX idx_device_keys_device_id ON device_keys (device_id);",
+    // 9: retry detection by sequence number or idempotency key, per device
+    "ALTER TABLE data_points ADD COLUMN idempotency_key TEXT;
+    CREATE INDEX idx_data_points_id_sequence ON data_points (id, sequence);
+    CREATE INDEX idx_data_points_id_idempotency_key ON data_points (id, idempotency_key);
+    ALTER TABLE quarantined_posts ADD COLUMN sequence INTEGER;
+    ALTER TABLE quarantined_posts ADD COLUMN idempotency_key TEXT;",
+    // 10: user accounts and their sessions; devices registered before this have no owner
+    "CREATE TABLE users (
+        user_id       INTEGER PRIMARY KEY AUTOINCREMENT,
+        username      TEXT NOT NULL UNIQUE COLLATE NOCASE,
+        password_hash TEXT NOT NULL,
+        created_at    TEXT NOT NULL
+    );
+    CREATE TABLE sessions (
+        token_hash TEXT    PRIMARY KEY,
+        user_id    INTEGER NOT NULL,
+        created_at TEXT    NOT NULL,
+        expires_at TEXT    NOT NULL
+    );
+    CREATE INDEX idx_sessions_expires_at ON sessions (expires_at);
+    ALTER TABLE devices ADD COLUMN owner_id INTEGER;
+    CREATE INDEX idx_devices_owner_id ON devices (owner_id);",
+    // 11: households own devices instead of users; each existing user gets one of their own
+    // (with the same id) holding the devices they registered
+    "CREATE TABLE households (
+        household_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name         TEXT NOT NULL,
+        created_at   TEXT NOT NULL
+    );
+    CREATE TABLE memberships (
+        household_id INTEGER NOT NULL,
+        user_id      INTEGER NOT NULL,
+        role         TEXT    NOT NULL,
+        joined_at    TEXT    NOT NULL,
+        PRIMARY KEY (household_id, user_id)
+    );
+    CREATE INDEX idx_memberships_user_id ON memberships (user_id);
+    CREATE TABLE invitations (
+        token_hash   TEXT    PRIMARY KEY,
+        household_id INTEGER NOT NULL,
+        role         TEXT    NOT NULL,
+        created_by   INTEGER NOT NULL,
+        created_at   TEXT    NOT NULL,
+        expires_at   TEXT    NOT NULL,
+        accepted_by  INTEGER
+    );
+    INSERT INTO households (household_id, name, created_at)
+        SELECT user_id, username || '''s household', created_at FROM users;
+    INSERT INTO memberships (household_id, user_id, role, joined_at)
+        SELECT user_id, user_id, 'owner', created_at FROM users;
+    ALTER TABLE devices ADD COLUMN household_id INTEGER;
+    UPDATE devices SET household_id = owner_id;
+    DROP INDEX idx_devices_owner_id;
+    ALTER TABLE devices DROP COLUMN owner_id;
+    CREATE INDEX idx_devices_household_id ON devices (household_id);",
+    // 12: public share links; the token is signed, so only its id and limits are kept
+    "CREATE TABLE shares (
+        share_id    INTEGER PRIMARY KEY AUTOINCREMENT,
+        device_id   TEXT    NOT NULL,
+        track_hours INTEGER NOT NULL,
+        created_at  TEXT    NOT NULL,
+        expires_at  TEXT    NOT NULL,
+        revoked_at  TEXT
+    );
+    CREATE INDEX idx_shares_device_id ON shares (device_id);",
+    // 13: alerts raised about devices, kept once resolved
+    "CREATE TABLE alerts (
+        alert_id        INTEGER PRIMARY KEY AUTOINCREMENT,
+        device_id       TEXT    NOT NULL,
+        kind            TEXT    NOT NULL,
+        state           TEXT    NOT NULL,
+        message         TEXT    NOT NULL,
+        fired_at        TEXT    NOT NULL,
+        acknowledged_at TEXT,
+        acknowledged_by INTEGER,
+        resolved_at     TEXT
+    );
+    CREATE INDEX idx_alerts_device_id ON alerts (device_id, kind);",
+    // 14: geofences, with the side of each the device was last on, and their crossings;
+    // shapes and fixes are kept as JSON
+    "CREATE TABLE geofences (
+        geofence_id  INTEGER PRIMARY KEY AUTOINCREMENT,
+        device_id    TEXT    NOT NULL,
+        name         TEXT    NOT NULL,
+        shape        TEXT    NOT NULL,
+        hysteresis_m REAL    NOT NULL,
+        created_at   TEXT    NOT NULL,
+        inside       INTEGER,
+        checked_at   TEXT
+    );
+    CREATE INDEX idx_geofences_device_id ON geofences (device_id);
+    CREATE TABLE geofence_events (
+        event_id      INTEGER PRIMARY KEY AUTOINCREMENT,
+        geofence_id   INTEGER NOT NULL,
+        geofence_name TEXT    NOT NULL,
+        device_id     TEXT    NOT NULL,
+        transition    TEXT    NOT NULL,
+        fix           TEXT    NOT NULL,
+        fix_timestamp TEXT    NOT NULL,
+        recorded_at   TEXT    NOT NULL
+    );
+    CREATE INDEX idx_geofence_events_device_id ON geofence_events (device_id, fix_timestamp);
+    CREATE INDEX idx_geofence_events_geofence_id ON geofence_events (geofence_id);",
+    // 15: webhooks and their outbox; delivered and dead deliveries are kept
+    "CREATE TABLE webhooks (
+        webhook_id   INTEGER PRIMARY KEY AUTOINCREMENT,
+        household_id INTEGER NOT NULL,
+        url          TEXT    NOT NULL,
+        events       TEXT    NOT NULL,
+        secret       TEXT    NOT NULL,
+        created_at   TEXT    NOT NULL
+    );
+    CREATE INDEX idx_webhooks_household_id ON webhooks (household_id);
+    CREATE TABLE webhook_deliveries (
+        delivery_id     INTEGER PRIMARY KEY AUTOINCREMENT,
+        webhook_id      INTEGER NOT NULL,
+        household_id    INTEGER NOT NULL,
+        url             TEXT    NOT NULL,
+        event           TEXT    NOT NULL,
+        data            TEXT    NOT NULL,
+        state           TEXT    NOT NULL,
+        attempts        INTEGER NOT NULL,
+        next_attempt_at TEXT    NOT NULL,
+        last_error      TEXT,
+        created_at      TEXT    NOT NULL,
+        delivered_at    TEXT
+    );
+    CREATE INDEX idx_webhook_deliveries_due ON webhook_deliveries (state, next_attempt_at);
+    CREATE INDEX idx_webhook_deliveries_household_id
+        ON webhook_deliveries (household_id, created_at);
+    CREATE INDEX idx_webhook_deliveries_webhook_id ON webhook_deliveries (webhook_id);",
+    // 16: what each user wants emailed to them
+    "CREATE TABLE email_prefs (
+        user_id INTEGER PRIMARY KEY,
+        email   TEXT,
+        events  TEXT NOT NULL
+    );",
+    // 17: highest write-ahead log sequence number applied, kept apart from data_points so
+    // deleting a device's points can't make the log hand out its numbers again
+    "CREATE TABLE wal_applied (
+        lsn INTEGER NOT NULL
+    );
+    INSERT INTO wal_applied (lsn) SELECT COALESCE(MAX(lsn), 0) FROM data_points;",
+    // 18: when each deleted device was deleted, so points it sent before then that are still
+    // on their way aren't stored under its id for whoever registers it next
+    "CREATE TABLE deleted_devices (
+        id         TEXT PRIMARY KEY,
+        deleted_at TEXT NOT NULL
+    );",
+];
+
+/// Embedded SQLite database stored in a single file.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database file at `path` and brings its schema up to date.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        let mut conn = Connection::open(path)?;
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+/// Runs every migration newer than the database's `user_version` in a single transaction.
+fn migrate(conn: &mut Connection) -> Result<(), StorageError> {
+    let current: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if current >= MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    let tx = conn.transaction()?;
+    for (version, sql) in MIGRATIONS.iter().enumerate().skip(current) {
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", version + 1)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Columns making up a `StoredData`, in the order `read_data` expects them.
+const DATA_COLUMNS: &str =
+    "id, longitude, latitude, longitude_deg, latitude_deg, battery, timestamp,
+     received_at, clock_skew_secs, clock_skew_flagged,
+     payload_version, altitude, hdop, satellites, speed, sequence, idempotency_key";
+
+/// Builds a `StoredData` from a row selected with `DATA_COLUMNS`.
+fn read_data(row: &rusqlite::Row) -> rusqlite::Result<StoredData> {
+    Ok(StoredData {
+        id: row.get("id")?,
+        longitude: row.get("longitude")?,
+        latitude: row.get("latitude")?,
+        longitude_deg: row.get("longitude_deg")?,
+        latitude_deg: row.get("latitude_deg")?,
+        battery: row.get("battery")?,
+        timestamp: row.get("timestamp")?,
+        received_at: row.get("received_at")?,
+        clock_skew_secs: row.get("clock_skew_secs")?,
+        clock_skew_flagged: row.get("clock_skew_flagged")?,
+        payload_version: row.get("payload_version")?,
+        altitude: row.get("altitude")?,
+        hdop: row.get("hdop")?,
+        satellites: row.get("satellites")?,
+        speed: row.get("speed")?,
+        sequence: row.get("sequence")?,
+        idempotency_key: row.get("idempotency_key")?,
+    })
+}
+
+/// Columns making up a `Device`, in the order `read_device` expects them.
+const DEVICE_COLUMNS: &str = "id, display_name, pet_name, photo_url,
+     start_hour, end_hour, interval_secs, timezone,
+     min_longitude, min_latitude, max_longitude, max_latitude, household_id";
+
+/// Builds a `Device` from a row selected with `DEVICE_COLUMNS`.
+fn read_device(row: &rusqlite::Row) -> rusqlite::Result<Device> {
+    let bbox: [Option<f64>; 4] = [
+        row.get("min_longitude")?,
+        row.get("min_latitude")?,
+        row.get("max_longitude")?,
+        row.get("max_latitude")?,
+    ];
+    let encoding = match bbox {
+        [
+            Some(min_longitude),
+            Some(min_latitude),
+            Some(max_longitude),
+            Some(max_latitude),
+        ] => Some(CoordinateEncoding {
+            min_longitude,
+            min_latitude,
+            max_longitude,
+            max_latitude,
+        }),
+        _ => None,
+    };
+
+    Ok(Device {
+        id: row.get("id")?,
+        display_name: row.get("display_name")?,
+        pet_name: row.get("pet_name")?,
+        photo_url: row.get("photo_url")?,
+        schedule: ReportingSchedule {
+            start_hour: row.get("start_hour")?,
+            end_hour: row.get("end_hour")?,
+            interval_secs: row.get("interval_secs")?,
+        },
+        timezone: row.get("timezone")?,
+        encoding,
+        household_id: row.get("household_id")?,
+    })
+}
+
+/// Runs `sql` (an INSERT or UPDATE naming every `DEVICE_COLUMNS` column) for `device`,
+/// returning the number of rows changed.
+fn write_device(conn: &Connection, sql: &str, device: &Device) -> Result<usize, StorageError> {
+    let encoding = device.encoding.as_ref();
+    Ok(conn.execute(
+        sql,
+        named_params! {
+            ":id": device.id,
+            ":display_name": device.display_name,
+            ":pet_name": device.pet_name,
+            ":photo_url": device.photo_url,
+            ":start_hour": device.schedule.start_hour,
+            ":end_hour": device.schedule.end_hour,
+            ":interval_secs": device.schedule.interval_secs,
+            ":timezone": device.timezone,
+            ":min_longitude": encoding.map(|e| e.min_longitude),
+            ":min_latitude": encoding.map(|e| e.min_latitude),
+            ":max_longitude": encoding.map(|e| e.max_longitude),
+            ":max_latitude": encoding.map(|e| e.max_latitude),
+            ":household_id": device.household_id,
+        },
+    )?)
+}
+
+/// Builds a `User` from a row with its `user_id`, `username` and `created_at` columns.
+fn read_user(row: &rusqlite::Row) -> rusqlite::Result<User> {
+    Ok(User {
+        user_id: row.get("user_id")?,
+        username: row.get("username")?,
+        created_at: row.get("created_at")?,
+    })
+}
+
+/// Columns of `shares`, in the order `read_share` expects them.
+const SHARE_COLUMNS: &str = "share_id, device_id, track_hours, created_at, expires_at, revoked_at";
+
+/// Builds a `Share` from a row selected with `SHARE_COLUMNS`.
+fn read_share(row: &rusqlite::Row) -> rusqlite::Result<Share> {
+    Ok(Share {
+        share_id: row.get("share_id")?,
+        device_id: row.get("device_id")?,
+        track_hours: row.get("track_hours")?,
+        created_at: row.get("created_at")?,
+        expires_at: row.get("expires_at")?,
+        revoked_at: row.get("revoked_at")?,
+        token: None,
+    })
+}
+
+/// Columns of `alerts`, in the order `read_alert` expects them.
+const ALERT_COLUMNS: &str = "alert_id, device_id, kind, state, message,
+     fired_at, acknowledged_at, acknowledged_by, resolved_at";
+
+/// Builds an `Alert` from a row selected with `ALERT_COLUMNS`.
+fn read_alert(row: &rusqlite::Row) -> rusqlite::Result<Alert> {
+    let parse_error = |e: String| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+    };
+    let kind: String = row.get("kind")?;
+    let state: String = row.get("state")?;
+    Ok(Alert {
+        alert_id: row.get("alert_id")?,
+        device_id: row.get("device_id")?,
+        kind: kind.parse().map_err(parse_error)?,
+        state: state.parse().map_err(parse_error)?,
+        message: row.get("message")?,
+        fired_at: row.get("fired_at")?,
+        acknowledged_at: row.get("acknowledged_at")?,
+        acknowledged_by: row.get("acknowledged_by")?,
+        resolved_at: row.get("resolved_at")?,
+    })
+}
+
+/// Columns of `geofences`, in the order `read_geofence` expects them.
+const GEOFENCE_COLUMNS: &str =
+    "geofence_id, device_id, name, shape, hysteresis_m, created_at, inside, checked_at";
+
+/// Turns a JSON column that failed to parse into a rusqlite error.
+fn json_column_error(e: serde_json::Error) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+}
+
+/// Builds a `Geofence` from a row selected with `GEOFENCE_COLUMNS`.
+fn read_geofence(row: &rusqlite::Row) -> rusqlite::Result<Geofence> {
+    let shape: String = row.get("shape")?;
+    Ok(Geofence {
+        geofence_id: row.get("geofence_id")?,
+        device_id: row.get("device_id")?,
+        name: row.get("name")?,
+        shape: serde_json::from_str(&shape).map_err(json_column_error)?,
+        hysteresis_m: row.get("hysteresis_m")?,
+        created_at: row.get("created_at")?,
+        inside: row.get("inside")?,
+        checked_at: row.get("checked_at")?,
+    })
+}
+
+/// Columns of `geofence_events`, in the order `read_geofence_event` expects them.
+const GEOFENCE_EVENT_COLUMNS: &str =
+    "event_id, geofence_id, geofence_name, device_id, transition, fix, recorded_at";
+
+/// Builds a `GeofenceEvent` from a row selected with `GEOFENCE_EVENT_COLUMNS`.
+fn read_geofence_event(row: &rusqlite::Row) -> rusqlite::Result<GeofenceEvent> {
+    let transition: String = row.get("transition")?;
+    let fix: String = row.get("fix")?;
+    Ok(GeofenceEvent {
+        event_id: row.get("event_id")?,
+        geofence_id: row.get("geofence_id")?,
+        geofence_name: row.get("geofence_name")?,
+        device_id: row.get("device_id")?,
+        transition: transition.parse().map_err(|e: String| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+        })?,
+        fix: serde_json::from_str(&fix).map_err(json_column_error)?,
+        recorded_at: row.get("recorded_at")?,
+    })
+}
+
+/// Columns of `webhooks`, in the order `read_webhook` expects them.
+const WEBHOOK_COLUMNS: &str = "webhook_id, household_id, url, events, secret, created_at";
+
+/// Builds a `Webhook` from a row selected with `WEBHOOK_COLUMNS`.
+fn read_webhook(row: &rusqlite::Row) -> rusqlite::Result<Webhook> {
+    let events: String = row.get("events")?;
+    Ok(Webhook {
+        webhook_id: row.get("webhook_id")?,
+        household_id: row.get("household_id")?,
+        url: row.get("url")?,
+        events: serde_json::from_str(&events).map_err(json_column_error)?,
+        created_at: row.get("created_at")?,
+        secret: row.get("secret")?,
+    })
+}
+
+/// Columns of `webhook_deliveries`, in the order `read_delivery` expects them.
+const DELIVERY_COLUMNS: &str = "delivery_id, webhook_id, household_id, url, event, data, state,
+     attempts, next_attempt_at, last_error, created_at, delivered_at";
+
+/// Builds a `Delivery` from a row selected with `DELIVERY_COLUMNS`.
+fn read_delivery(row: &rusqlite::Row) -> rusqlite::Result<Delivery> {
+    let parse_error = |e: String| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+    };
+    let event: String = row.get("event")?;
+    let data: String = row.get("data")?;
+    let state: String = row.get("state")?;
+    Ok(Delivery {
+        delivery_id: row.get("delivery_id")?,
+        webhook_id: row.get("webhook_id")?,
+        household_id: row.get("household_id")?,
+        url: row.get("url")?,
+        event: event.parse().map_err(parse_error)?,
+        data: serde_json::from_str(&data).map_err(json_column_error)?,
+        state: state.parse().map_err(parse_error)?,
+        attempts: row.get("attempts")?,
+        next_attempt_at: row.get("next_attempt_at")?,
+        last_error: row.get("last_error")?,
+        created_at: row.get("created_at")?,
+        delivered_at: row.get("delivered_at")?,
+    })
+}
+
+/// Builds a `Household` from a row with its `household_id`, `name` and `created_at` columns.
+fn read_household(row: &rusqlite::Row) -> rusqlite::Result<Household> {
+    Ok(Household {
+        household_id: row.get("household_id")?,
+        name: row.get("name")?,
+        created_at: row.get("created_at")?,
+    })
+}
+
+/// Reads the `role` column of a membership or invitation row.
+fn read_role(row: &rusqlite::Row) -> rusqlite::Result<Role> {
+    let role: String = row.get("role")?;
+    role.parse().map_err(|e: String| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+    })
+}
+
+/// The unused, unexpired invitation with this token hash.
+fn find_invitation(
+    conn: &Connection,
+    token_hash: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<Invitation>, StorageError> {
+    let mut stmt = conn.prepare(
+        "SELECT household_id, role, created_at, expires_at FROM invitations
+         WHERE token_hash = :token_hash AND accepted_by IS NULL AND expires_at > :now",
+    )?;
+    let mut invitations = stmt.query_map(
+        named_params! {":token_hash": token_hash, ":now": now},
+        |row| {
+            Ok(Invitation {
+                household_id: row.get("household_id")?,
+                role: read_role(row)?,
+                created_at: row.get("created_at")?,
+                expires_at: row.get("expires_at")?,
+                token: None,
+            })
+        },
+    )?;
+    Ok(invitations.next().transpose()?)
+}
+
+impl DataStore for SqliteStore {
+    fn insert_batch(&self, records: &[(u64, &StoredData)]) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let tx = conn.transaction()?;
+        let mut stmt = tx.prepare(&format!(
+            "INSERT INTO data_points ({DATA_COLUMNS}, lsn)
+             SELECT :id, :longitude, :latitude, :longitude_deg, :latitude_deg, :battery,
+                    :timestamp, :received_at, :clock_skew_secs, :clock_skew_flagged,
+                    :payload_version, :altitude, :hdop, :satellites, :speed,
+                    :sequence, :idempotency_key, :lsn
+             WHERE NOT EXISTS (
+                 SELECT 1 FROM deleted_devices WHERE id = :id AND deleted_at >= :received_at
+             )"
+        ))?;
+        for (lsn, data) in records {
+            stmt.execute(named_params! {
+                ":id": data.id,
+                ":longitude": data.longitude,
+                ":latitude": data.latitude,
+                ":longitude_deg": data.longitude_deg,
+                ":latitude_deg": data.latitude_deg,
+                ":battery": data.battery,
+                ":timestamp": data.timestamp,
+                ":received_at": data.received_at,
+                ":clock_skew_secs": data.clock_skew_secs,
+                ":clock_skew_flagged": data.clock_skew_flagged,
+                ":payload_version": data.payload_version,
+                ":altitude": data.altitude,
+                ":hdop": data.hdop,
+                ":satellites": data.satellites,
+                ":speed": data.speed,
+                ":sequence": data.sequence,
+                ":idempotency_key": data.idempotency_key,
+                ":lsn": lsn,
+            })?;
+        }
+        drop(stmt);
+        if let Some(lsn) = records.iter().map(|(lsn, _)| *lsn).max() {
+            tx.execute(
+                "UPDATE wal_applied SET lsn = MAX(lsn, :lsn)",
+                named_params! {":lsn": lsn},
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn applied_lsn(&self) -> Result<u64, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        Ok(conn.query_row("SELECT lsn FROM wal_applied", [], |row| row.get(0))?)
+    }
+
+    fn query(&self, query: &DataQuery, household_ids: &[i64]) -> Result<DataPage, StorageError> {
+        let cursor = query
+            .cursor
+            .as_deref()
+            .map(PageCursor::decode)
+            .transpose()?;
+        let (direction, after) = match query.order {
+            SortOrder::Asc => ("ASC", ">"),
+            SortOrder::Desc => ("DESC", "<"),
+        };
+
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT row_id, {DATA_COLUMNS} FROM data_points
+             WHERE id IN (SELECT id FROM devices
+                          WHERE household_id IN (SELECT value FROM json_each(:household_ids)))
+               AND (:device_id IS NULL OR id = :device_id)
+               AND (:from IS NULL OR timestamp >= :from)
+               AND (:to IS NULL OR timestamp < :to)
+               AND (:cursor_ts IS NULL
+                    OR timestamp {after} :cursor_ts
+                    OR (timestamp = :cursor_ts AND row_id {after} :cursor_row))
+             ORDER BY timestamp {direction}, row_id {direction}
+             LIMIT :limit"
+        ))?;
+        let rows = stmt.query_map(
+            named_params! {
+                ":household_ids": serde_json::to_string(household_ids).unwrap_or_default(),
+                ":device_id": query.device_id,
+                ":from": query.from,
+                ":to": query.to,
+                ":cursor_ts": cursor.map(|c| c.timestamp),
+                ":cursor_row": cursor.map(|c| c.row_id),
+                ":limit": query.page_size() as i64 + 1,
+            },
+            |row| Ok((row.get("row_id")?, read_data(row)?)),
+        )?;
+        let rows = rows.collect::<Result<Vec<_>, _>>()?;
+        Ok(into_page(rows, query.page_size()))
+    }
+
+    fn since(
+        &self,
+        after_lsn: u64,
+        household_ids: &[i64],
+        device_id: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(u64, StoredData)>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT lsn, {DATA_COLUMNS} FROM data_points
+             WHERE lsn > :after_lsn
+               AND id IN (SELECT id FROM devices
+                          WHERE household_id IN (SELECT value FROM json_each(:household_ids)))
+               AND (:device_id IS NULL OR id = :device_id)
+             ORDER BY lsn
+             LIMIT :limit"
+        ))?;
+        let rows = stmt.query_map(
+            named_params! {
+                ":after_lsn": after_lsn,
+                ":household_ids": serde_json::to_string(household_ids).unwrap_or_default(),
+                ":device_id": device_id,
+                ":limit": limit as i64,
+            },
+            |row| Ok((row.get("lsn")?, read_data(row)?)),
+        )?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn find_duplicate(
+        &self,
+        data: &StoredData,
+        since: DateTime<Utc>,
+    ) -> Result<Option<StoredData>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {DATA_COLUMNS} FROM data_points
+             WHERE id = :id AND received_at >= :since
+               AND ((:sequence IS NOT NULL AND sequence = :sequence)
+                    OR (:idempotency_key IS NOT NULL AND idempotency_key = :idempotency_key))
+               AND timestamp = :timestamp AND longitude = :longitude AND latitude = :latitude
+             ORDER BY row_id LIMIT 1"
+        ))?;
+        let mut rows = stmt.query_map(
+            named_params! {
+                ":id": data.id,
+                ":since": since,
+                ":sequence": data.sequence,
+                ":idempotency_key": data.idempotency_key,
+                ":timestamp": data.timestamp,
+                ":longitude": data.longitude,
+                ":latitude": data.latitude,
+            },
+            read_data,
+        )?;
+        Ok(rows.next().transpose()?)
+    }
+
+    fn highest_sequence(
+        &self,
+        device_id: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Option<StoredData>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {DATA_COLUMNS} FROM data_points
+             WHERE id = :id AND received_at >= :since AND sequence IS NOT NULL
+             ORDER BY sequence DESC, row_id DESC LIMIT 1"
+        ))?;
+        let mut rows =
+            stmt.query_map(named_params! {":id": device_id, ":since": since}, read_data)?;
+        Ok(rows.next().transpose()?)
+    }
+
+    fn latest(&self, household_ids: &[i64]) -> Result<Vec<StoredData>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        // One indexed lookup per device rather than a scan of every point
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {DATA_COLUMNS} FROM data_points WHERE row_id IN (
+                SELECT (SELECT row_id FROM data_points p
+                        WHERE p.id = ids.id
+                        ORDER BY timestamp DESC, row_id DESC LIMIT 1)
+                FROM (SELECT id FROM devices
+                      WHERE household_id IN (SELECT value FROM json_each(:household_ids))) ids
+             )
+             ORDER BY id"
+        ))?;
+        let rows = stmt.query_map(
+            named_params! {
+                ":household_ids": serde_json::to_string(household_ids).unwrap_or_default(),
+            },
+            read_data,
+        )?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn device_latest(&self, device_id: &str) -> Result<Option<StoredData>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {DATA_COLUMNS} FROM data_points WHERE id = :id
+             ORDER BY timestamp DESC, row_id DESC LIMIT 1"
+        ))?;
+        let mut rows = stmt.query_map(named_params! {":id": device_id}, read_data)?;
+        Ok(rows.next().transpose()?)
+    }
+
+    fn devices(&self, household_ids: &[i64]) -> Result<Vec<Device>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {DEVICE_COLUMNS} FROM devices
+             WHERE household_id IN (SELECT value FROM json_each(:household_ids))
+             ORDER BY id"
+        ))?;
+        let devices = stmt.query_map(
+            named_params! {
+                ":household_ids": serde_json::to_string(household_ids).unwrap_or_default(),
+            },
+            read_device,
+        )?;
+        Ok(devices.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn all_devices(&self) -> Result<Vec<Device>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt =
+            conn.prepare(&format!("SELECT {DEVICE_COLUMNS} FROM devices ORDER BY id"))?;
+        let devices = stmt.query_map([], read_device)?;
+        Ok(devices.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn device(&self, id: &str) -> Result<Option<Device>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {DEVICE_COLUMNS} FROM devices WHERE id = :id"
+        ))?;
+        let mut devices = stmt.query_map(named_params! {":id": id}, read_device)?;
+        Ok(devices.next().transpose()?)
+    }
+
+    fn create_device(&self, device: &Device) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let inserted = write_device(
+            &conn,
+            &format!(
+                "INSERT OR IGNORE INTO devices ({DEVICE_COLUMNS})
+                 VALUES (:id, :display_name, :pet_name, :photo_url,
+                         :start_hour, :end_hour, :interval_secs, :timezone,
+                         :min_longitude, :min_latitude, :max_longitude, :max_latitude,
+                         :household_id)"
+            ),
+            device,
+        )?;
+        Ok(inserted > 0)
+    }
+
+    fn claim_devices(&self, household_id: i64) -> Result<usize, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        Ok(conn.execute(
+            "UPDATE devices SET household_id = :household_id WHERE household_id IS NULL",
+            named_params! {":household_id": household_id},
+        )?)
+    }
+
+    fn update_device(&self, device: &Device) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let updated = write_device(
+            &conn,
+            "UPDATE devices SET
+                display_name = :display_name, pet_name = :pet_name, photo_url = :photo_url,
+                start_hour = :start_hour, end_hour = :end_hour, interval_secs = :interval_secs,
+                timezone = :timezone,
+                min_longitude = :min_longitude, min_latitude = :min_latitude,
+                max_longitude = :max_longitude, max_latitude = :max_latitude,
+                household_id = :household_id
+             WHERE id = :id",
+            device,
+        )?;
+        Ok(updated > 0)
+    }
+
+    fn delete_device(&self, id: &str, now: DateTime<Utc>) -> Result<bool, StorageError> {
+        let mut conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM device_keys WHERE device_id = :id",
+            named_params! {":id": id},
+        )?;
+        tx.execute(
+            "DELETE FROM shares WHERE device_id = :id",
+            named_params! {":id": id},
+        )?;
+        tx.execute(
+            "DELETE FROM alerts WHERE device_id = :id",
+            named_params! {":id": id},
+        )?;
+        tx.execute(
+            "DELETE FROM geofences WHERE device_id = :id",
+            named_params! {":id": id},
+        )?;
+        tx.execute(
+            "DELETE FROM geofence_events WHERE device_id = :id",
+            named_params! {":id": id},
+        )?;
+        tx.execute(
+            "DELETE FROM data_points WHERE id = :id",
+            named_params! {":id": id},
+        )?;
+        let deleted = tx.execute(
+            "DELETE FROM devices WHERE id = :id",
+            named_params! {":id": id},
+        )?;
+        if deleted > 0 {
+            tx.execute(
+                "INSERT OR REPLACE INTO deleted_devices (id, deleted_at) VALUES (:id, :now)",
+                named_params! {":id": id, ":now": now},
+            )?;
+        }
+        tx.commit()?;
+        Ok(deleted > 0)
+    }
+
+    fn create_key(
+        &self,
+        device_id: &str,
+        secret: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<DeviceKey, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        conn.execute(
+            "INSERT INTO device_keys (device_id, secret, created_at)
+             VALUES (:device_id, :secret, :created_at)",
+            named_params! {
+                ":device_id": device_id,
+                ":secret": secret,
+                ":created_at": created_at,
+            },
+        )?;
+        Ok(DeviceKey {
+            key_id: conn.last_insert_rowid(),
+            device_id: device_id.to_string(),
+            created_at,
+            secret: Some(secret.to_string()),
+        })
+    }
+
+    fn keys(&self, device_id: &str) -> Result<Vec<DeviceKey>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(
+            "SELECT key_id, device_id, secret, created_at FROM device_keys
+             WHERE device_id = :device_id ORDER BY key_id",
+        )?;
+        let keys = stmt.query_map(named_params! {":device_id": device_id}, |row| {
+            Ok(DeviceKey {
+                key_id: row.get("key_id")?,
+                device_id: row.get("device_id")?,
+                created_at: row.get("created_at")?,
+                secret: row.get("secret")?,
+            })
+        })?;
+        Ok(keys.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn delete_key(&self, device_id: &str, key_id: i64) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let deleted = conn.execute(
+            "DELETE FROM device_keys WHERE device_id = :device_id AND key_id = :key_id",
+            named_params! {":device_id": device_id, ":key_id": key_id},
+        )?;
+        Ok(deleted > 0)
+    }
+
+    fn create_share(&self, share: &Share) -> Result<Share, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        conn.execute(
+            "INSERT INTO shares (device_id, track_hours, created_at, expires_at, revoked_at)
+             VALUES (:device_id, :track_hours, :created_at, :expires_at, :revoked_at)",
+            named_params! {
+                ":device_id": share.device_id,
+                ":track_hours": share.track_hours,
+                ":created_at": share.created_at,
+                ":expires_at": share.expires_at,
+                ":revoked_at": share.revoked_at,
+            },
+        )?;
+        Ok(Share {
+            share_id: conn.last_insert_rowid(),
+            token: None,
+            ..share.clone()
+        })
+    }
+
+    fn shares(&self, device_id: &str) -> Result<Vec<Share>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {SHARE_COLUMNS} FROM shares WHERE device_id = :device_id ORDER BY share_id"
+        ))?;
+        let shares = stmt.query_map(named_params! {":device_id": device_id}, read_share)?;
+        Ok(shares.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn share(&self, share_id: i64) -> Result<Option<Share>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {SHARE_COLUMNS} FROM shares WHERE share_id = :share_id"
+        ))?;
+        let mut shares = stmt.query_map(named_params! {":share_id": share_id}, read_share)?;
+        Ok(shares.next().transpose()?)
+    }
+
+    fn revoke_share(
+        &self,
+        device_id: &str,
+        share_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let revoked = conn.execute(
+            "UPDATE shares SET revoked_at = :now
+             WHERE device_id = :device_id AND share_id = :share_id AND revoked_at IS NULL",
+            named_params! {":now": now, ":device_id": device_id, ":share_id": share_id},
+        )?;
+        Ok(revoked > 0)
+    }
+
+    fn create_alert(&self, alert: &Alert) -> Result<Option<Alert>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        // The connection lock makes the check and the insert one step
+        let open: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM alerts
+                WHERE device_id = :device_id AND kind = :kind AND state != 'resolved')",
+            named_params! {":device_id": alert.device_id, ":kind": alert.kind.as_str()},
+            |row| row.get(0),
+        )?;
+        if open {
+            return Ok(None);
+        }
+        conn.execute(
+            "INSERT INTO alerts (device_id, kind, state, message, fired_at,
+                                 acknowledged_at, acknowledged_by, resolved_at)
+             VALUES (:device_id, :kind, :state, :message, :fired_at,
+                     :acknowledged_at, :acknowledged_by, :resolved_at)",
+            named_params! {
+                ":device_id": alert.device_id,
+                ":kind": alert.kind.as_str(),
+                ":state": alert.state.as_str(),
+                ":message": alert.message,
+                ":fired_at": alert.fired_at,
+                ":acknowledged_at": alert.acknowledged_at,
+                ":acknowledged_by": alert.acknowledged_by,
+                ":resolved_at": alert.resolved_at,
+            },
+        )?;
+        Ok(Some(Alert {
+            alert_id: conn.last_insert_rowid(),
+            ..alert.clone()
+        }))
+    }
+
+    fn resolve_alerts(
+        &self,
+        device_id: &str,
+        kind: AlertKind,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Alert>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "UPDATE alerts SET state = 'resolved', resolved_at = :now
+             WHERE device_id = :device_id AND kind = :kind AND state != 'resolved'
+             RETURNING {ALERT_COLUMNS}"
+        ))?;
+        let alerts = stmt.query_map(
+            named_params! {":now": now, ":device_id": device_id, ":kind": kind.as_str()},
+            read_alert,
+        )?;
+        Ok(alerts.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn alerts(
+        &self,
+        query: &AlertQuery,
+        household_ids: &[i64],
+    ) -> Result<Vec<Alert>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {ALERT_COLUMNS} FROM alerts
+             WHERE device_id IN (
+                 SELECT id FROM devices
+                 WHERE household_id IN (SELECT value FROM json_each(:household_ids))
+             )
+               AND (:device_id IS NULL OR device_id = :device_id)
+               AND (:state IS NULL OR state = :state)
+             ORDER BY state = 'resolved', fired_at DESC, alert_id DESC
+             LIMIT :limit"
+        ))?;
+        let alerts = stmt.query_map(
+            named_params! {
+                ":household_ids": serde_json::to_string(household_ids).unwrap_or_default(),
+                ":device_id": query.device_id,
+                ":state": query.state.map(AlertState::as_str),
+                ":limit": query.limit() as i64,
+            },
+            read_alert,
+        )?;
+        Ok(alerts.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn alert(&self, alert_id: i64) -> Result<Option<Alert>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {ALERT_COLUMNS} FROM alerts WHERE alert_id = :alert_id"
+        ))?;
+        let mut alerts = stmt.query_map(named_params! {":alert_id": alert_id}, read_alert)?;
+        Ok(alerts.next().transpose()?)
+    }
+
+    fn acknowledge_alert(
+        &self,
+        alert_id: i64,
+        user_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let acknowledged = conn.execute(
+            "UPDATE alerts
+             SET state = 'acknowledged', acknowledged_at = :now, acknowledged_by = :user_id
+             WHERE alert_id = :alert_id AND state = 'firing'",
+            named_params! {":now": now, ":user_id": user_id, ":alert_id": alert_id},
+        )?;
+        Ok(acknowledged > 0)
+    }
+
+    fn create_geofence(&self, geofence: &Geofence) -> Result<Geofence, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        conn.execute(
+            "INSERT INTO geofences
+                (device_id, name, shape, hysteresis_m, created_at, inside, checked_at)
+             VALUES (:device_id, :name, :shape, :hysteresis_m, :created_at, :inside, :checked_at)",
+            named_params! {
+                ":device_id": geofence.device_id,
+                ":name": geofence.name,
+                ":shape": serde_json::to_string(&geofence.shape).unwrap_or_default(),
+                ":hysteresis_m": geofence.hysteresis_m,
+                ":created_at": geofence.created_at,
+                ":inside": geofence.inside,
+                ":checked_at": geofence.checked_at,
+            },
+        )?;
+        Ok(Geofence {
+            geofence_id: conn.last_insert_rowid(),
+            ..geofence.clone()
+        })
+    }
+
+    fn geofences(&self, device_id: &str) -> Result<Vec<Geofence>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {GEOFENCE_COLUMNS} FROM geofences
+             WHERE device_id = :device_id ORDER BY geofence_id"
+        ))?;
+        let geofences = stmt.query_map(named_params! {":device_id": device_id}, read_geofence)?;
+        Ok(geofences.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn geofence(&self, geofence_id: i64) -> Result<Option<Geofence>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {GEOFENCE_COLUMNS} FROM geofences WHERE geofence_id = :geofence_id"
+        ))?;
+        let mut geofences =
+            stmt.query_map(named_params! {":geofence_id": geofence_id}, read_geofence)?;
+        Ok(geofences.next().transpose()?)
+    }
+
+    fn update_geofence(&self, geofence: &Geofence) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let updated = conn.execute(
+            "UPDATE geofences SET
+                name = :name, shape = :shape, hysteresis_m = :hysteresis_m,
+                inside = :inside, checked_at = :checked_at
+             WHERE geofence_id = :geofence_id AND device_id = :device_id",
+            named_params! {
+                ":name": geofence.name,
+                ":shape": serde_json::to_string(&geofence.shape).unwrap_or_default(),
+                ":hysteresis_m": geofence.hysteresis_m,
+                ":inside": geofence.inside,
+                ":checked_at": geofence.checked_at,
+                ":geofence_id": geofence.geofence_id,
+                ":device_id": geofence.device_id,
+            },
+        )?;
+        Ok(updated > 0)
+    }
+
+    fn delete_geofence(&self, device_id: &str, geofence_id: i64) -> Result<bool, StorageError> {
+        let mut conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let tx = conn.transaction()?;
+        let deleted = tx.execute(
+            "DELETE FROM geofences WHERE device_id = :device_id AND geofence_id = :geofence_id",
+            named_params! {":device_id": device_id, ":geofence_id": geofence_id},
+        )?;
+        if deleted > 0 {
+            tx.execute(
+                "DELETE FROM geofence_events WHERE geofence_id = :geofence_id",
+                named_params! {":geofence_id": geofence_id},
+            )?;
+        }
+        tx.commit()?;
+        Ok(deleted > 0)
+    }
+
+    fn record_geofence_check(
+        &self,
+        geofence_id: i64,
+        inside: bool,
+        checked_at: DateTime<Utc>,
+        event: Option<&GeofenceEvent>,
+    ) -> Result<Option<GeofenceEvent>, StorageError> {
+        let mut conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let tx = conn.transaction()?;
+        let updated = tx.execute(
+            "UPDATE geofences SET inside = :inside, checked_at = :checked_at
+             WHERE geofence_id = :geofence_id
+               AND (checked_at IS NULL OR checked_at <= :checked_at)",
+            named_params! {
+                ":inside": inside,
+                ":checked_at": checked_at,
+                ":geofence_id": geofence_id,
+            },
+        )?;
+        let recorded = match event.filter(|_| updated > 0) {
+            Some(event) => {
+                tx.execute(
+                    "INSERT INTO geofence_events (geofence_id, geofence_name, device_id,
+                                                  transition, fix, fix_timestamp, recorded_at)
+                     VALUES (:geofence_id, :geofence_name, :device_id,
+                             :transition, :fix, :fix_timestamp, :recorded_at)",
+                    named_params! {
+                        ":geofence_id": event.geofence_id,
+                        ":geofence_name": event.geofence_name,
+                        ":device_id": event.device_id,
+                        ":transition": event.transition.as_str(),
+                        ":fix": serde_json::to_string(&event.fix).unwrap_or_default(),
+                        ":fix_timestamp": event.fix.timestamp,
+                        ":recorded_at": event.recorded_at,
+                    },
+                )?;
+                Some(GeofenceEvent {
+                    event_id: tx.last_insert_rowid(),
+                    ..event.clone()
+                })
+            }
+            None => None,
+        };
+        tx.commit()?;
+        Ok(recorded)
+    }
+
+    fn geofence_events(
+        &self,
+        query: &GeofenceEventQuery,
+        household_ids: &[i64],
+    ) -> Result<Vec<GeofenceEvent>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {GEOFENCE_EVENT_COLUMNS} FROM geofence_events
+             WHERE device_id IN (
+                 SELECT id FROM devices
+                 WHERE household_id IN (SELECT value FROM json_each(:household_ids))
+             )
+               AND (:device_id IS NULL OR device_id = :device_id)
+               AND (:geofence_id IS NULL OR geofence_id = :geofence_id)
+             ORDER BY fix_timestamp DESC, event_id DESC
+             LIMIT :limit"
+        ))?;
+        let events = stmt.query_map(
+            named_params! {
+                ":household_ids": serde_json::to_string(household_ids).unwrap_or_default(),
+                ":device_id": query.device_id,
+                ":geofence_id": query.geofence_id,
+                ":limit": query.limit() as i64,
+            },
+            read_geofence_event,
+        )?;
+        Ok(events.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn create_webhook(&self, webhook: &Webhook) -> Result<Webhook, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        conn.execute(
+            "INSERT INTO webhooks (household_id, url, events, secret, created_at)
+             VALUES (:household_id, :url, :events, :secret, :created_at)",
+            named_params! {
+                ":household_id": webhook.household_id,
+                ":url": webhook.url,
+                ":events": serde_json::to_string(&webhook.events).unwrap_or_default(),
+                ":secret": webhook.secret.as_deref().unwrap_or_default(),
+                ":created_at": webhook.created_at,
+            },
+        )?;
+        Ok(Webhook {
+            webhook_id: conn.last_insert_rowid(),
+            ..webhook.clone()
+        })
+    }
+
+    fn webhooks(&self, household_id: i64) -> Result<Vec<Webhook>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {WEBHOOK_COLUMNS} FROM webhooks
+             WHERE household_id = :household_id ORDER BY webhook_id"
+        ))?;
+        let webhooks =
+            stmt.query_map(named_params! {":household_id": household_id}, read_webhook)?;
+        Ok(webhooks.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn webhook(&self, webhook_id: i64) -> Result<Option<Webhook>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {WEBHOOK_COLUMNS} FROM webhooks WHERE webhook_id = :webhook_id"
+        ))?;
+        let mut webhooks =
+            stmt.query_map(named_params! {":webhook_id": webhook_id}, read_webhook)?;
+        Ok(webhooks.next().transpose()?)
+    }
+
+    fn delete_webhook(&self, household_id: i64, webhook_id: i64) -> Result<bool, StorageError> {
+        let mut conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let tx = conn.transaction()?;
+        let deleted = tx.execute(
+            "DELETE FROM webhooks WHERE household_id = :household_id AND webhook_id = :webhook_id",
+            named_params! {":household_id": household_id, ":webhook_id": webhook_id},
+        )?;
+        if deleted > 0 {
+            tx.execute(
+                "DELETE FROM webhook_deliveries WHERE webhook_id = :webhook_id",
+                named_params! {":webhook_id": webhook_id},
+            )?;
+        }
+        tx.commit()?;
+        Ok(deleted > 0)
+    }
+
+    fn create_delivery(&self, delivery: &Delivery) -> Result<Delivery, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        conn.execute(
+            "INSERT INTO webhook_deliveries (webhook_id, household_id, url, event, data, state,
+                                             attempts, next_attempt_at, last_error,
+                                             created_at, delivered_at)
+             VALUES (:webhook_id, :household_id, :url, :event, :data, :state,
+                     :attempts, :next_attempt_at, :last_error, :created_at, :delivered_at)",
+            named_params! {
+                ":webhook_id": delivery.webhook_id,
+                ":household_id": delivery.household_id,
+                ":url": delivery.url,
+                ":event": delivery.event.as_str(),
+                ":data": delivery.data.to_string(),
+                ":state": delivery.state.as_str(),
+                ":attempts": delivery.attempts,
+                ":next_attempt_at": delivery.next_attempt_at,
+                ":last_error": delivery.last_error,
+                ":created_at": delivery.created_at,
+                ":delivered_at": delivery.delivered_at,
+            },
+        )?;
+        Ok(Delivery {
+            delivery_id: conn.last_insert_rowid(),
+            ..delivery.clone()
+        })
+    }
+
+    fn due_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<Delivery>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries
+             WHERE state = 'pending' AND next_attempt_at <= :now
+             ORDER BY next_attempt_at, delivery_id
+             LIMIT :limit"
+        ))?;
+        let deliveries = stmt.query_map(
+            named_params! {":now": now, ":limit": limit as i64},
+            read_delivery,
+        )?;
+        Ok(deliveries.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn update_delivery(&self, delivery: &Delivery) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let updated = conn.execute(
+            "UPDATE webhook_deliveries SET
+                state = :state, attempts = :attempts, next_attempt_at = :next_attempt_at,
+                last_error = :last_error, delivered_at = :delivered_at
+             WHERE delivery_id = :delivery_id",
+            named_params! {
+                ":state": delivery.state.as_str(),
+                ":attempts": delivery.attempts,
+                ":next_attempt_at": delivery.next_attempt_at,
+                ":last_error": delivery.last_error,
+                ":delivered_at": delivery.delivered_at,
+                ":delivery_id": delivery.delivery_id,
+            },
+        )?;
+        Ok(updated > 0)
+    }
+
+    fn deliveries(
+        &self,
+        household_id: i64,
+        query: &DeliveryQuery,
+    ) -> Result<Vec<Delivery>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries
+             WHERE household_id = :household_id
+               AND (:state IS NULL OR state = :state)
+             ORDER BY created_at DESC, delivery_id DESC
+             LIMIT :limit"
+        ))?;
+        let deliveries = stmt.query_map(
+            named_params! {
+                ":household_id": household_id,
+                ":state": query.state.map(DeliveryState::as_str),
+                ":limit": query.limit() as i64,
+            },
+            read_delivery,
+        )?;
+        Ok(deliveries.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn delivery(&self, delivery_id: i64) -> Result<Option<Delivery>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries WHERE delivery_id = :delivery_id"
+        ))?;
+        let mut deliveries =
+            stmt.query_map(named_params! {":delivery_id": delivery_id}, read_delivery)?;
+        Ok(deliveries.next().transpose()?)
+    }
+
+    fn quarantine(&self, post: &QuarantinedPost) -> Result<(), StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        conn.execute(
+            "INSERT INTO quarantined_posts
+                (id, payload, date, time, tz, sequence, idempotency_key, received_at)
+             VALUES (:id, :payload, :date, :time, :tz, :sequence, :idempotency_key, :received_at)",
+            named_params! {
+                ":id": post.post.id,
+                ":payload": post.post.payload,
+                ":date": post.post.date,
+                ":time": post.post.time,
+                ":tz": post.post.tz,
+                ":sequence": post.post.sequence,
+                ":idempotency_key": post.post.idempotency_key,
+                ":received_at": post.received_at,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn quarantined(&self) -> Result<Vec<QuarantinedPost>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(
+            "SELECT id, payload, date, time, tz, sequence, idempotency_key, received_at
+             FROM quarantined_posts ORDER BY row_id",
+        )?;
+        let posts = stmt.query_map([], |row| {
+            Ok(QuarantinedPost {
+                post: IncomingData {
+                    id: row.get("id")?,
+                    payload: row.get("payload")?,
+                    date: row.get("date")?,
+                    time: row.get("time")?,
+                    tz: row.get("tz")?,
+                    sequence: row.get("sequence")?,
+                    idempotency_key: row.get("idempotency_key")?,
+                },
+                received_at: row.get("received_at")?,
+            })
+        })?;
+        Ok(posts.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<Option<User>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO users (username, password_hash, created_at)
+             VALUES (:username, :password_hash, :created_at)",
+            named_params! {
+                ":username": username,
+                ":password_hash": password_hash,
+                ":created_at": created_at,
+            },
+        )?;
+        Ok((inserted > 0).then(|| User {
+            user_id: conn.last_insert_rowid(),
+            username: username.to_string(),
+            created_at,
+        }))
+    }
+
+    fn user_count(&self) -> Result<usize, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        Ok(conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?)
+    }
+
+    fn user_credentials(&self, username: &str) -> Result<Option<(User, String)>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(
+            "SELECT user_id, username, created_at, password_hash FROM users
+             WHERE username = :username",
+        )?;
+        let mut users = stmt.query_map(named_params! {":username": username}, |row| {
+            Ok((read_user(row)?, row.get("password_hash")?))
+        })?;
+        Ok(users.next().transpose()?)
+    }
+
+    fn email_prefs(&self, user_id: i64) -> Result<Option<EmailPrefs>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt =
+            conn.prepare("SELECT email, events FROM email_prefs WHERE user_id = :user_id")?;
+        let mut prefs = stmt.query_map(named_params! {":user_id": user_id}, |row| {
+            let events: String = row.get("events")?;
+            Ok(EmailPrefs {
+                email: row.get("email")?,
+                events: serde_json::from_str(&events).map_err(json_column_error)?,
+            })
+        })?;
+        Ok(prefs.next().transpose()?)
+    }
+
+    fn set_email_prefs(&self, user_id: i64, prefs: &EmailPrefs) -> Result<(), StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        conn.execute(
+            "INSERT INTO email_prefs (user_id, email, events) VALUES (:user_id, :email, :events)
+             ON CONFLICT (user_id) DO UPDATE SET email = excluded.email, events = excluded.events",
+            named_params! {
+                ":user_id": user_id,
+                ":email": prefs.email,
+                ":events": serde_json::to_string(&prefs.events).unwrap_or_default(),
+            },
+        )?;
+        Ok(())
+    }
+
+    fn create_session(
+        &self,
+        token_hash: &str,
+        user_id: i64,
+        created_at: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM sessions WHERE expires_at <= :now",
+            named_params! {":now": created_at},
+        )?;
+        tx.execute(
+            "INSERT INTO sessions (token_hash, user_id, created_at, expires_at)
+             VALUES (:token_hash, :user_id, :created_at, :expires_at)",
+            named_params! {
+                ":token_hash": token_hash,
+                ":user_id": user_id,
+                ":created_at": created_at,
+                ":expires_at": expires_at,
+            },
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn session_user(
+        &self,
+        token_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<User>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(
+            "SELECT users.user_id, users.username, users.created_at
+             FROM sessions JOIN users ON users.user_id = sessions.user_id
+             WHERE sessions.token_hash = :token_hash AND sessions.expires_at > :now",
+        )?;
+        let mut users = stmt.query_map(
+            named_params! {":token_hash": token_hash, ":now": now},
+            read_user,
+        )?;
+        Ok(users.next().transpose()?)
+    }
+
+    fn delete_session(&self, token_hash: &str) -> Result<(), StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        conn.execute(
+            "DELETE FROM sessions WHERE token_hash = :token_hash",
+            named_params! {":token_hash": token_hash},
+        )?;
+        Ok(())
+    }
+
+    fn create_household(
+        &self,
+        name: &str,
+        owner_id: i64,
+        created_at: DateTime<Utc>,
+    ) -> Result<Household, StorageError> {
+        let mut conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO households (name, created_at) VALUES (:name, :created_at)",
+            named_params! {":name": name, ":created_at": created_at},
+        )?;
+        let household = Household {
+            household_id: tx.last_insert_rowid(),
+            name: name.to_string(),
+            created_at,
+        };
+        tx.execute(
+            "INSERT INTO memberships (household_id, user_id, role, joined_at)
+             VALUES (:household_id, :user_id, :role, :joined_at)",
+            named_params! {
+                ":household_id": household.household_id,
+                ":user_id": owner_id,
+                ":role": Role::Owner.as_str(),
+                ":joined_at": created_at,
+            },
+        )?;
+        tx.commit()?;
+        Ok(household)
+    }
+
+    fn memberships(&self, user_id: i64) -> Result<Vec<Membership>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(
+            "SELECT households.household_id, households.name, households.created_at,
+                    memberships.role
+             FROM memberships JOIN households USING (household_id)
+             WHERE memberships.user_id = :user_id
+             ORDER BY households.household_id",
+        )?;
+        let memberships = stmt.query_map(named_params! {":user_id": user_id}, |row| {
+            Ok(Membership {
+                household: read_household(row)?,
+                role: read_role(row)?,
+            })
+        })?;
+        Ok(memberships.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn members(&self, household_id: i64) -> Result<Vec<Member>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let mut stmt = conn.prepare(
+            "SELECT users.user_id, users.username, memberships.role, memberships.joined_at
+             FROM memberships JOIN users USING (user_id)
+             WHERE memberships.household_id = :household_id
+             ORDER BY memberships.joined_at, users.user_id",
+        )?;
+        let members = stmt.query_map(named_params! {":household_id": household_id}, |row| {
+            Ok(Member {
+                user_id: row.get("user_id")?,
+                username: row.get("username")?,
+                role: read_role(row)?,
+                joined_at: row.get("joined_at")?,
+            })
+        })?;
+        Ok(members.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn set_role(&self, household_id: i64, user_id: i64, role: Role) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let updated = conn.execute(
+            "UPDATE memberships SET role = :role
+             WHERE household_id = :household_id AND user_id = :user_id",
+            named_params! {
+                ":role": role.as_str(),
+                ":household_id": household_id,
+                ":user_id": user_id,
+            },
+        )?;
+        Ok(updated > 0)
+    }
+
+    fn remove_member(&self, household_id: i64, user_id: i64) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let deleted = conn.execute(
+            "DELETE FROM memberships WHERE household_id = :household_id AND user_id = :user_id",
+            named_params! {":household_id": household_id, ":user_id": user_id},
+        )?;
+        Ok(deleted > 0)
+    }
+
+    fn create_invitation(
+        &self,
+        token_hash: &str,
+        invitation: &Invitation,
+        created_by: i64,
+    ) -> Result<(), StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        conn.execute(
+            "INSERT INTO invitations
+                (token_hash, household_id, role, created_by, created_at, expires_at)
+             VALUES (:token_hash, :household_id, :role, :created_by, :created_at, :expires_at)",
+            named_params! {
+                ":token_hash": token_hash,
+                ":household_id": invitation.household_id,
+                ":role": invitation.role.as_str(),
+                ":created_by": created_by,
+                ":created_at": invitation.created_at,
+                ":expires_at": invitation.expires_at,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn invitation(
+        &self,
+        token_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Invitation>, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        find_invitation(&conn, token_hash, now)
+    }
+
+    fn accept_invitation(
+        &self,
+        token_hash: &str,
+        user_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Membership>, StorageError> {
+        let mut conn = self.conn.lock().map_err(|_| StorageError::Lock)?;
+        let tx = conn.transaction()?;
+        let Some(invitation) = find_invitation(&tx, token_hash, now)? else {
+            return Ok(None);
+        };
+        tx.execute(
+            "UPDATE invitations SET accepted_by = :user_id WHERE token_hash = :token_hash",
+            named_params! {":user_id": user_id, ":token_hash": token_hash},
+        )?;
+        tx.execute(
+            "INSERT INTO memberships (household_id, user_id, role, joined_at)
+             VALUES (:household_id, :user_id, :role, :joined_at)",
+            named_params! {
+                ":household_id": invitation.household_id,
+                ":user_id": user_id,
+                ":role": invitation.role.as_str(),
+                ":joined_at": now,
+            },
+        )?;
+        let household = tx.query_row(
+            "SELECT household_id, name, created_at FROM households
+             WHERE household_id = :household_id",
+            named_params! {":household_id": invitation.household_id},
+            read_household,
+        )?;
+        tx.commit()?;
+        Ok(Some(Membership {
+            household,
+            role: invitation.role,
+        }))
+    }
+}
+
+// --- Tests ---
+
+/// Fixtures shared with the tests of the modules built on the store.
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// An empty store of each backend, named so a failure says which one it was.
+    pub(crate) fn backends() -> Vec<(&'static str, Box<dyn DataStore>)> {
+        vec![
+            ("memory", Box::new(MemoryStore::new())),
+            (
+                "sqlite",
+                Box::new(SqliteStore::open(":memory:").expect("in-memory database")),
+            ),
+        ]
+    }
+
+    /// `minutes` after 2025-11-01 00:00 UTC.
+    pub(crate) fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_761_955_200 + minutes * 60, 0).unwrap()
+    }
+
+    /// A fix from `device_id` taken, and received, `minutes` after `at(0)`.
+    pub(crate) fn point(device_id: &str, minutes: i64) -> StoredData {
+        StoredData {
+            id: device_id.to_string(),
+            longitude: 0x8000,
+            latitude: 0x8000,
+            longitude_deg: 100.5,
+            latitude_deg: 13.75,
+            battery: 80,
+            timestamp: at(minutes),
+            received_at: at(minutes),
+            clock_skew_secs: 0,
+            clock_skew_flagged: false,
+            payload_version: 0,
+            altitude: None,
+            hdop: None,
+            satellites: None,
+            speed: None,
+            sequence: None,
+            idempotency_key: None,
+        }
+    }
+
+    /// A device registered in `household_id`, reporting around the clock.
+    pub(crate) fn device(id: &str, household_id: i64) -> Device {
+        Device {
+            id: id.to_string(),
+            display_name: format!("Tracker {}", id),
+            pet_name: None,
+            photo_url: None,
+            schedule: ReportingSchedule::default(),
+            timezone: Some("+00:00".to_string()),
+            encoding: None,
+            household_id: Some(household_id),
+        }
+    }
+
+    /// Creates `username` and a household they own. Returns the household and user ids.
+    pub(crate) fn household(store: &dyn DataStore, username: &str) -> (i64, i64) {
+        let user = store
+            .create_user(username, "hash", at(0))
+            .unwrap()
+            .expect("username is free");
+        let household = store
+            .create_household(username, user.user_id, at(0))
+            .unwrap();
+        (household.household_id, user.user_id)
+    }
+
+    /// Stores `points` under consecutive log sequence numbers after the last applied one.
+    pub(crate) fn insert(store: &dyn DataStore, points: &[StoredData]) {
+        let first = store.applied_lsn().unwrap() + 1;
+        let records: Vec<(u64, &StoredData)> = points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| (first + i as u64, point))
+            .collect();
+        store.insert_batch(&records).unwrap();
+    }
+
+    #[test]
+    fn points_come_back_in_log_order_after_the_last_applied_lsn() {
+        for (name, store) in backends() {
+            let (ann, _) = household(store.as_ref(), "ann");
+            store.create_device(&device("A", ann)).unwrap();
+            assert_eq!(store.applied_lsn().unwrap(), 0, "{name}");
+            insert(
+                store.as_ref(),
+                &[point("A", 2), point("A", 1), point("A", 3)],
+            );
+            assert_eq!(store.applied_lsn().unwrap(), 3, "{name}");
+
+            let after_first = store.since(1, &[ann], None, 10).unwrap();
+            let lsns: Vec<u64> = after_first.iter().map(|(lsn, _)| *lsn).collect();
+            assert_eq!(lsns, [2, 3], "{name}");
+            assert_eq!(after_first[0].1.timestamp, at(1), "{name}");
+            assert_eq!(store.since(0, &[ann], None, 1).unwrap().len(), 1, "{name}");
+        }
+    }
+
+    #[test]
+    fn the_database_file_keeps_points_across_restarts() {
+        let path = std::env::temp_dir().join(format!("buddy-store-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let ann = {
+            let store = SqliteStore::open(&path).unwrap();
+            let (ann, _) = household(&store, "ann");
+            store.create_device(&device("A", ann)).unwrap();
+            insert(&store, &[point("A", 1), point("A", 2)]);
+            ann
+        };
+
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.applied_lsn().unwrap(), 2);
+        let page = store.query(&DataQuery::default(), &[ann]).unwrap();
+        let timestamps: Vec<_> = page.data.iter().map(|p| p.timestamp).collect();
+        assert_eq!(timestamps, [at(1), at(2)]);
+        drop(store);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn points_are_only_visible_to_their_household() {
+        for (name, store) in backends() {
+            let (ann, _) = household(store.as_ref(), "ann");
+            let (bob, _) = household(store.as_ref(), "bob");
+            assert!(store.create_device(&device("A", ann)).unwrap());
+            assert!(store.create_device(&device("B", bob)).unwrap());
+            insert(
+                store.as_ref(),
+                &[point("A", 1), point("B", 2), point("stray", 3)],
+            );
+
+            let page = store.query(&DataQuery::default(), &[ann]).unwrap();
+            let ids: Vec<&str> = page.data.iter().map(|p| p.id.as_str()).collect();
+            assert_eq!(ids, ["A"], "{name}");
+            let latest = store.latest(&[bob]).unwrap();
+            assert_eq!(latest.len(), 1, "{name}");
+            assert_eq!(latest[0].id, "B", "{name}");
+            let since = store.since(0, &[ann, bob], None, 10).unwrap();
+            let lsns: Vec<u64> = since.iter().map(|(lsn, _)| *lsn).collect();
+            assert_eq!(lsns, [1, 2], "{name}");
+            assert_eq!(store.applied_lsn().unwrap(), 3, "{name}");
+        }
+    }
+
+    #[test]
+    fn a_devices_latest_fix_is_its_newest_by_device_timestamp() {
+        for (name, store) in backends() {
+            assert_eq!(store.device_latest("A").unwrap(), None, "{name}");
+            insert(
+                store.as_ref(),
+                &[point("A", 5), point("B", 9), point("A", 2)],
+            );
+            let latest = store.device_latest("A").unwrap().unwrap();
+            assert_eq!(
+                (latest.id.as_str(), latest.timestamp),
+                ("A", at(5)),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn query_pages_through_points_in_timestamp_order() {
+        for (name, store) in backends() {
+            let (ann, _) = household(store.as_ref(), "ann");
+            store.create_device(&device("A", ann)).unwrap();
+            insert(
+                store.as_ref(),
+                &[
+                    point("A", 3),
+                    point("A", 1),
+                    point("A", 5),
+                    point("A", 2),
+                    point("A", 4),
+                ],
+            );
+
+            for (order, expected) in [
+                (SortOrder::Asc, [1, 2, 3, 4, 5]),
+                (SortOrder::Desc, [5, 4, 3, 2, 1]),
+            ] {
+                let mut query = DataQuery {
+                    limit: Some(2),
+                    order,
+                    ..DataQuery::default()
+                };
+                let mut seen = Vec::new();
+                loop {
+                    let page = store.query(&query, &[ann]).unwrap();
+                    seen.extend(page.data.iter().map(|p| p.timestamp));
+                    match page.next {
+                        Some(next) => query.cursor = Some(next),
+                        None => break,
+                    }
+                }
+                assert_eq!(seen, expected.map(at), "{name} {order:?}");
+            }
+            let bad_cursor = DataQuery {
+                cursor: Some("nonsense".to_string()),
+                ..DataQuery::default()
+            };
+            assert!(
+                matches!(
+                    store.query(&bad_cursor, &[ann]),
+                    Err(StorageError::InvalidCursor(_))
+                ),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn duplicates_are_found_by_sequence_or_key_within_the_window() {
+        for (name, store) in backends() {
+            let mut first = point("A", 10);
+            first.sequence = Some(7);
+            let mut keyed = point("A", 11);
+            keyed.idempotency_key = Some("k1".to_string());
+            insert(store.as_ref(), &[first.clone(), keyed.clone()]);
+
+            let mut retry = first.clone();
+            retry.received_at = at(20);
+            assert_eq!(
+                store.find_duplicate(&retry, at(0)).unwrap(),
+                Some(first.clone()),
+                "{name}"
+            );
+            let mut another_fix = point("A", 20);
+            another_fix.sequence = Some(7);
+            assert_eq!(
+                store.find_duplicate(&another_fix, at(0)).unwrap(),
+                None,
+                "{name}"
+            );
+            assert_eq!(
+                store.find_duplicate(&retry, at(15)).unwrap(),
+                None,
+                "{name}"
+            );
+            retry.id = "B".to_string();
+            assert_eq!(store.find_duplicate(&retry, at(0)).unwrap(), None, "{name}");
+
+            let mut keyed_retry = keyed.clone();
+            keyed_retry.received_at = at(20);
+            assert_eq!(
+                store.find_duplicate(&keyed_retry, at(0)).unwrap(),
+                Some(keyed),
+                "{name}"
+            );
+            assert_eq!(
+                store.highest_sequence("A", at(0)).unwrap(),
+                Some(first),
+                "{name}"
+            );
+            assert_eq!(store.highest_sequence("B", at(0)).unwrap(), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn devices_are_registered_once_and_deleted_with_what_hangs_off_them() {
+        for (name, store) in backends() {
+            let (ann, _) = household(store.as_ref(), "ann");
+            assert!(store.create_device(&device("A", ann)).unwrap(), "{name}");
+            assert!(!store.create_device(&device("A", ann)).unwrap(), "{name}");
+            store.create_key("A", "secret", at(0)).unwrap();
+            store
+                .create_alert(&Alert::firing(
+                    "A",
+                    AlertKind::LowBattery,
+                    "low".to_string(),
+                    at(1),
+                ))
+                .unwrap();
+
+            let mut renamed = device("A", ann);
+            renamed.pet_name = Some("Rex".to_string());
+            assert!(store.update_device(&renamed).unwrap(), "{name}");
+            assert_eq!(store.device("A").unwrap(), Some(renamed), "{name}");
+            assert!(!store.update_device(&device("B", ann)).unwrap(), "{name}");
+
+            assert!(store.delete_device("A", at(10)).unwrap(), "{name}");
+            assert!(!store.delete_device("A", at(10)).unwrap(), "{name}");
+            assert_eq!(store.device("A").unwrap(), None, "{name}");
+            assert!(store.keys("A").unwrap().is_empty(), "{name}");
+            assert!(
+                store
+                    .alerts(&AlertQuery::default(), &[ann])
+                    .unwrap()
+                    .is_empty(),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn points_sent_before_a_device_is_registered_survive_it() {
+        for (name, store) in backends() {
+            let (ann, _) = household(store.as_ref(), "ann");
+            insert(store.as_ref(), &[point("A", 1), point("B", 2)]);
+            store.create_device(&device("A", ann)).unwrap();
+            insert(store.as_ref(), &[point("A", 3)]);
+
+            let page = store.query(&DataQuery::default(), &[ann]).unwrap();
+            let timestamps: Vec<_> = page.data.iter().map(|p| p.timestamp).collect();
+            assert_eq!(timestamps, [at(1), at(3)], "{name}");
+        }
+    }
+
+    #[test]
+    fn a_re_registered_id_does_not_inherit_the_deleted_devices_track() {
+        for (name, store) in backends() {
+            let (ann, _) = household(store.as_ref(), "ann");
+            let (bob, _) = household(store.as_ref(), "bob");
+            store.create_device(&device("A", ann)).unwrap();
+            insert(store.as_ref(), &[point("A", 1)]);
+
+            store.delete_device("A", at(10)).unwrap();
+            // Received before the deletion but only stored after it, and then a new fix
+            insert(store.as_ref(), &[point("A", 5), point("A", 12)]);
+            store.create_device(&device("A", bob)).unwrap();
+
+            let page = store.query(&DataQuery::default(), &[bob]).unwrap();
+            let timestamps: Vec<_> = page.data.iter().map(|p| p.timestamp).collect();
+            assert_eq!(timestamps, [at(12)], "{name}");
+            assert_eq!(store.latest(&[bob]).unwrap()[0].timestamp, at(12), "{name}");
+            assert_eq!(store.applied_lsn().unwrap(), 3, "{name}");
+        }
+    }
+
+    #[test]
+    fn a_device_has_one_open_alert_of_each_kind() {
+        for (name, store) in backends() {
+            let (ann, user_id) = household(store.as_ref(), "ann");
+            store.create_device(&device("A", ann)).unwrap();
+            let low =
+                |minutes| Alert::firing("A", AlertKind::LowBattery, "low".to_string(), at(minutes));
+
+            let fired = store.create_alert(&low(1)).unwrap().expect("first fires");
+            assert_eq!(store.create_alert(&low(2)).unwrap(), None, "{name}");
+            assert!(
+                store
+                    .acknowledge_alert(fired.alert_id, user_id, at(3))
+                    .unwrap(),
+                "{name}"
+            );
+            assert!(
+                !store
+                    .acknowledge_alert(fired.alert_id, user_id, at(3))
+                    .unwrap(),
+                "{name}"
+            );
+
+            let resolved = store
+                .resolve_alerts("A", AlertKind::LowBattery, at(4))
+                .unwrap();
+            assert_eq!(resolved.len(), 1, "{name}");
+            assert_eq!(resolved[0].state, AlertState::Resolved, "{name}");
+            assert!(
+                store
+                    .resolve_alerts("A", AlertKind::Silent, at(4))
+                    .unwrap()
+                    .is_empty(),
+                "{name}"
+            );
+            assert!(store.create_alert(&low(5)).unwrap().is_some(), "{name}");
+        }
+    }
+
+    #[test]
+    fn a_geofence_check_never_goes_back_to_an_older_fix() {
+        use crate::geofence::{GeoPoint, GeofenceShape, Transition};
+
+        for (name, store) in backends() {
+            let (ann, _) = household(store.as_ref(), "ann");
+            store.create_device(&device("A", ann)).unwrap();
+            let geofence = store
+                .create_geofence(&Geofence {
+                    geofence_id: 0,
+                    device_id: "A".to_string(),
+                    name: "Home".to_string(),
+                    shape: GeofenceShape::Circle {
+                        center: GeoPoint {
+                            latitude: 13.75,
+                            longitude: 100.5,
+                        },
+                        radius_m: 50.0,
+                    },
+                    hysteresis_m: 10.0,
+                    created_at: at(0),
+                    inside: Some(true),
+                    checked_at: Some(at(1)),
+                })
+                .unwrap();
+            let exit = |minutes| GeofenceEvent {
+                event_id: 0,
+                geofence_id: geofence.geofence_id,
+                geofence_name: "Home".to_string(),
+                device_id: "A".to_string(),
+                transition: Transition::Exit,
+                fix: point("A", minutes),
+                recorded_at: at(10),
+            };
+
+            let recorded = store
+                .record_geofence_check(geofence.geofence_id, false, at(5), Some(&exit(5)))
+                .unwrap();
+            assert!(recorded.is_some(), "{name}");
+            let late = store
+                .record_geofence_check(geofence.geofence_id, false, at(3), Some(&exit(3)))
+                .unwrap();
+            assert_eq!(late, None, "{name}");
+
+            let stored = store.geofence(geofence.geofence_id).unwrap().unwrap();
+            assert_eq!(stored.checked_at, Some(at(5)), "{name}");
+            let events = store
+                .geofence_events(&GeofenceEventQuery::default(), &[ann])
+                .unwrap();
+            assert_eq!(events.len(), 1, "{name}");
+        }
+    }
+
+    #[test]
+    fn sessions_expire_and_usernames_are_unique_in_any_case() {
+        for (name, store) in backends() {
+            let ann = store.create_user("ann", "hash", at(0)).unwrap().unwrap();
+            store
+                .create_session("s1", ann.user_id, at(0), at(60))
+                .unwrap();
+            assert!(
+                store.session_user("s1", at(59)).unwrap().is_some(),
+                "{name}"
+            );
+            assert!(
+                store.session_user("s1", at(60)).unwrap().is_none(),
+                "{name}"
+            );
+            assert!(
+                store.create_user("ANN", "hash", at(0)).unwrap().is_none(),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn invitations_work_once_and_not_after_they_expire() {
+        for (name, store) in backends() {
+            let (ann, ann_id) = household(store.as_ref(), "ann");
+            let bob = store.create_user("bob", "hash", at(0)).unwrap().unwrap();
+            let invitation = Invitation {
+                household_id: ann,
+                role: Role::Viewer,
+                created_at: at(0),
+                expires_at: at(60),
+                token: None,
+            };
+            store.create_invitation("t1", &invitation, ann_id).unwrap();
+            assert!(store.invitation("t1", at(61)).unwrap().is_none(), "{name}");
+            let joined = store.accept_invitation("t1", bob.user_id, at(1)).unwrap();
+            assert_eq!(joined.map(|m| m.role), Some(Role::Viewer), "{name}");
+            assert!(
+                store
+                    .accept_invitation("t1", bob.user_id, at(2))
+                    .unwrap()
+                    .is_none(),
+                "{name}"
+            );
+            let members = store.members(ann).unwrap();
+            assert_eq!(members.len(), 2, "{name}");
+        }
+    }
+}
@@ -0,0 +1,431 @@
+use crate::gps_data::StoredData;
+use crate::storage::StorageError;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+// --- Record Format ---
+//
+// The log is a flat sequence of records, each laid out as:
+//
+//   [u32 LE body length][u32 LE CRC32 of body][body]
+//   body = [u64 LE sequence number (LSN)][JSON-encoded StoredData]
+//
+// A record is only valid if its header is complete, the full body is present
+// and the checksum matches. Anything after the first invalid record is treated
+// as a torn write and cut off during recovery. A valid record whose JSON can't be
+// read is logged and skipped instead: it was written whole, so what follows is
+// still good.
+//
+// A record is only needed until the store has committed it, so once the log has grown
+// past CHECKPOINT_BYTES it is emptied after the next successful apply, and again after
+// recovery. LSNs carry on from the store's applied LSN, so they never repeat.
+
+/// Size of the `[length][checksum]` header in front of each record.
+const HEADER_LEN: usize = 8;
+
+/// Size of the LSN prefix inside each record body.
+const LSN_LEN: usize = 8;
+
+/// Size the log may grow to before it is emptied.
+const CHECKPOINT_BYTES: u64 = 1 << 20;
+
+// --- Errors ---
+
+/// Everything that can go wrong while appending to or recovering the log.
+#[derive(Debug)]
+pub enum WalError {
+    /// Reading, writing or syncing the log file failed.
+    Io(io::Error),
+    /// A record's body could not be serialized.
+    Encoding(serde_json::Error),
+    /// The log file lock was poisoned by a panicking writer.
+    Lock,
+    /// The record was logged but applying it to the store failed; the record was rolled back.
+    Storage(StorageError),
+}
+
+impl fmt::Display for WalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalError::Io(e) => write!(f, "write-ahead log I/O error: {}", e),
+            WalError::Encoding(e) => write!(f, "write-ahead log encoding error: {}", e),
+            WalError::Lock => write!(f, "failed to lock write-ahead log"),
+            WalError::Storage(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for WalError {}
+
+impl From<io::Error> for WalError {
+    fn from(e: io::Error) -> Self {
+        WalError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for WalError {
+    fn from(e: serde_json::Error) -> Self {
+        WalError::Encoding(e)
+    }
+}
+
+impl From<StorageError> for WalError {
+    fn from(e: StorageError) -> Self {
+        WalError::Storage(e)
+    }
+}
+
+// --- Log ---
+
+/// A record read back from the log during recovery.
+#[derive(Debug)]
+pub struct WalRecord {
+    pub lsn: u64,
+    pub data: StoredData,
+}
+
+struct WalFile {
+    file: File,
+    next_lsn: u64,
+}
+
+/// Append-only, checksummed log of every accepted point, kept until the store has it.
+///
+/// Each point is fsync'd to the log before it is applied to the store,
+/// so a crash between the two is repaired by replaying the log on startup.
+pub struct WriteAheadLog {
+    inner: Mutex<WalFile>,
+}
+
+impl WriteAheadLog {
+    /// Opens (or creates) the log at `path`, truncating a torn final record if there is one.
+    ///
+    /// Returns the log together with every record newer than `applied_lsn`,
+    /// in log order, so the caller can replay them into the store.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        applied_lsn: u64,
+    ) -> Result<(Self, Vec<WalRecord>), WalError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+
+        let (records, last_lsn, valid_len) = decode_records(&buf);
+        if valid_len < buf.len() {
+            leptos::logging::log!(
+                "Write-ahead log: truncating torn tail ({} of {} bytes kept)",
+                valid_len,
+                buf.len()
+            );
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+
+        let next_lsn = last_lsn.max(applied_lsn) + 1;
+        let pending = records
+            .into_iter()
+            .filter(|r| r.lsn > applied_lsn)
+            .collect();
+
+        Ok((
+            Self {
+                inner: Mutex::new(WalFile { file, next_lsn }),
+            },
+            pending,
+        ))
+    }
+
+    /// Durably appends `data` to the log, then hands its LSN to `apply`.
+    ///
+    /// The log stays locked until `apply` returns, so points reach the store in LSN order.
+    /// If `apply` fails the record is cut back off the log and the error is returned.
+    pub fn append<F>(&self, data: &StoredData, apply: F) -> Result<u64, WalError>
+    where
+        F: FnOnce(u64) -> Result<(), StorageError>,
+    {
+        let appended = self.append_unless(data, || Ok(None::<std::convert::Infallible>), apply)?;
+        Ok(appended.unwrap_or_else(|never| match never {}))
+    }
+
+    /// Like `append`, but first runs `check` with the log locked. If it returns a reason
+    /// not to store `data`, nothing is logged and the reason is returned instead of an LSN.
+    ///
+    /// Appends are serialised, so `check` sees every point logged before this one and
+    /// two concurrent posts can't both pass it.
+    pub fn append_unless<T, C, F>(
+        &self,
+        data: &StoredData,
+        check: C,
+        apply: F,
+    ) -> Result<Result<u64, T>, WalError>
+    where
+        C: FnOnce() -> Result<Option<T>, StorageError>,
+        F: FnOnce(u64) -> Result<(), StorageError>,
+    {
+        let mut outcomes = self.append_batch_unless(
+            std::slice::from_ref(data),
+            || Ok(vec![check()?]),
+            |records| apply(records[0].0),
+        )?;
+        Ok(outcomes.remove(0))
+    }
+
+    /// Batch version of `append_unless`: `check` returns one verdict per item of `data`,
+    /// the accepted items are logged with a single write and fsync, and `apply` receives
+    /// them all together with their LSNs.
+    ///
+    /// Either every accepted item is logged and applied, or (if writing or `apply` fails)
+    /// none of them are. Returns each item's LSN or the reason it was turned away, in order.
+    pub fn append_batch_unless<T, C, F>(
+        &self,
+        data: &[StoredData],
+        check: C,
+        apply: F,
+    ) -> Result<Vec<Result<u64, T>>, WalError>
+    where
+        C: FnOnce() -> Result<Vec<Option<T>>, StorageError>,
+        F: FnOnce(&[(u64, &StoredData)]) -> Result<(), StorageError>,
+    {
+        let mut inner = self.inner.lock().map_err(|_| WalError::Lock)?;
+        let verdicts = check()?;
+
+        let mut next_lsn = inner.next_lsn;
+        let mut accepted = Vec::new();
+        let outcomes: Vec<Result<u64, T>> = data
+            .iter()
+            .zip(verdicts)
+            .map(|(item, verdict)| match verdict {
+                Some(reason) => Err(reason),
+                None => {
+                    accepted.push((next_lsn, item));
+                    next_lsn += 1;
+                    Ok(next_lsn - 1)
+                }
+            })
+            .collect();
+        if accepted.is_empty() {
+            return Ok(outcomes);
+        }
+
+        let mut records = Vec::new();
+        for (lsn, item) in &accepted {
+            records.extend(encode_record(*lsn, item)?);
+        }
+
+        let start = inner.file.seek(SeekFrom::End(0))?;
+        if let Err(e) = inner
+            .file
+            .write_all(&records)
+            .and_then(|_| inner.file.sync_data())
+        {
+            // Don't leave half-written records behind for the next append to follow
+            let _ = inner.file.set_len(start);
+            return Err(e.into());
+        }
+
+        if let Err(e) = apply(&accepted) {
+            inner.file.set_len(start)?;
+            inner.file.sync_data()?;
+            return Err(e.into());
+        }
+
+        inner.next_lsn = next_lsn;
+        // Everything logged so far is in the store now; the points are safe either way
+        if start + records.len() as u64 >= CHECKPOINT_BYTES
+            && let Err(e) = inner.truncate()
+        {
+            leptos::logging::log!("Write-ahead log: failed to checkpoint: {}", e);
+        }
+        Ok(outcomes)
+    }
+
+    /// Empties the log. Only call this once every record in it has been applied to the store,
+    /// e.g. after replaying what `open` returned.
+    pub fn checkpoint(&self) -> Result<(), WalError> {
+        let mut inner = self.inner.lock().map_err(|_| WalError::Lock)?;
+        inner.truncate()?;
+        Ok(())
+    }
+}
+
+impl WalFile {
+    fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()
+    }
+}
+
+fn encode_record(lsn: u64, data: &StoredData) -> Result<Vec<u8>, WalError> {
+    let json = serde_json::to_vec(data)?;
+    let mut body = Vec::with_capacity(LSN_LEN + json.len());
+    body.extend_from_slice(&lsn.to_le_bytes());
+    body.extend_from_slice(&json);
+
+    let mut record = Vec::with_capacity(HEADER_LEN + body.len());
+    record.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+    record.extend_from_slice(&body);
+    Ok(record)
+}
+
+/// Decodes records from the start of `buf` until the data runs out or stops checking out.
+/// Returns the decoded records, the last LSN logged and the byte length of the valid prefix.
+fn decode_records(buf: &[u8]) -> (Vec<WalRecord>, u64, usize) {
+    let mut records = Vec::new();
+    let mut last_lsn = 0;
+    let mut offset = 0;
+
+    while buf.len() - offset >= HEADER_LEN {
+        let header = &buf[offset..offset + HEADER_LEN];
+        let body_len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(header[4..8].try_into().unwrap());
+
+        let body_start = offset + HEADER_LEN;
+        let Some(body) = buf.get(body_start..body_start + body_len) else {
+            break; // torn write: body incomplete
+        };
+        if body_len < LSN_LEN || crc32fast::hash(body) != checksum {
+            break; // torn write: garbage or partially flushed body
+        }
+
+        let lsn = u64::from_le_bytes(body[..LSN_LEN].try_into().unwrap());
+        match serde_json::from_slice(&body[LSN_LEN..]) {
+            Ok(data) => records.push(WalRecord { lsn, data }),
+            Err(e) => {
+                leptos::logging::log!("Write-ahead log: skipping unreadable record {}: {}", lsn, e)
+            }
+        }
+        // Counted either way, so its LSN isn't handed out again
+        last_lsn = lsn;
+        offset = body_start + body_len;
+    }
+
+    (records, last_lsn, offset)
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::point;
+
+    /// A fresh path in the temp directory, removed again when dropped.
+    struct TempLog(std::path::PathBuf);
+
+    impl TempLog {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("buddy-wal-{}-{}.wal", name, std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            TempLog(path)
+        }
+
+        fn len(&self) -> u64 {
+            std::fs::metadata(&self.0).unwrap().len()
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn unapplied_records_are_replayed_and_a_torn_tail_is_cut_off() {
+        let path = TempLog::new("replay");
+        {
+            let (wal, pending) = WriteAheadLog::open(&path.0, 0).unwrap();
+            assert!(pending.is_empty());
+            for minutes in 0..3 {
+                wal.append(&point("A", minutes), |_| Ok(())).unwrap();
+            }
+        }
+        let intact = path.len();
+        let mut file = OpenOptions::new().append(true).open(&path.0).unwrap();
+        file.write_all(&[42, 0, 0, 0, 1, 2]).unwrap();
+        drop(file);
+
+        let (wal, pending) = WriteAheadLog::open(&path.0, 1).unwrap();
+        let lsns: Vec<u64> = pending.iter().map(|record| record.lsn).collect();
+        assert_eq!(lsns, [2, 3]);
+        assert_eq!(pending[0].data, point("A", 1));
+        assert_eq!(path.len(), intact);
+        assert_eq!(wal.append(&point("A", 3), |_| Ok(())).unwrap(), 4);
+    }
+
+    #[test]
+    fn an_unreadable_record_is_skipped_and_the_rest_replayed() {
+        let path = TempLog::new("unreadable");
+        {
+            let (wal, _) = WriteAheadLog::open(&path.0, 0).unwrap();
+            for minutes in 0..2 {
+                wal.append(&point("A", minutes), |_| Ok(())).unwrap();
+            }
+        }
+        // A whole record with a sound checksum, but not a StoredData
+        let mut body = 3u64.to_le_bytes().to_vec();
+        body.extend_from_slice(b"{\"id\": 42}");
+        let mut file = OpenOptions::new().append(true).open(&path.0).unwrap();
+        file.write_all(&(body.len() as u32).to_le_bytes()).unwrap();
+        file.write_all(&crc32fast::hash(&body).to_le_bytes())
+            .unwrap();
+        file.write_all(&body).unwrap();
+        file.write_all(&encode_record(4, &point("A", 4)).unwrap())
+            .unwrap();
+        drop(file);
+        let written = path.len();
+
+        let (wal, pending) = WriteAheadLog::open(&path.0, 1).unwrap();
+        let lsns: Vec<u64> = pending.iter().map(|record| record.lsn).collect();
+        assert_eq!(lsns, [2, 4]);
+        assert_eq!(pending[1].data, point("A", 4));
+        assert_eq!(path.len(), written);
+        assert_eq!(wal.append(&point("A", 5), |_| Ok(())).unwrap(), 5);
+    }
+
+    #[test]
+    fn a_failed_apply_leaves_nothing_behind() {
+        let path = TempLog::new("rollback");
+        let (wal, _) = WriteAheadLog::open(&path.0, 0).unwrap();
+        let failed = wal.append(&point("A", 0), |_| Err(StorageError::Lock));
+        assert!(matches!(failed, Err(WalError::Storage(StorageError::Lock))));
+        assert_eq!(path.len(), 0);
+        assert_eq!(wal.append(&point("A", 0), |_| Ok(())).unwrap(), 1);
+    }
+
+    #[test]
+    fn the_log_is_emptied_once_it_is_big_and_applied() {
+        let path = TempLog::new("checkpoint");
+        let (wal, _) = WriteAheadLog::open(&path.0, 0).unwrap();
+        wal.append(&point("A", 0), |_| Ok(())).unwrap();
+        assert!(path.len() > 0);
+
+        let record_len = path.len();
+        let batch: Vec<StoredData> = (0..CHECKPOINT_BYTES / record_len + 1)
+            .map(|minutes| point("A", minutes as i64))
+            .collect();
+        let outcomes = wal
+            .append_batch_unless(&batch, || Ok(vec![None::<()>; batch.len()]), |_| Ok(()))
+            .unwrap();
+        assert_eq!(path.len(), 0);
+
+        // Numbering carries on from the store's applied LSN after a restart
+        let last = *outcomes.last().unwrap().as_ref().unwrap();
+        drop(wal);
+        let (wal, pending) = WriteAheadLog::open(&path.0, last).unwrap();
+        assert!(pending.is_empty());
+        assert_eq!(wal.append(&point("A", 0), |_| Ok(())).unwrap(), last + 1);
+        wal.checkpoint().unwrap();
+        assert_eq!(path.len(), 0);
+    }
+}
@@ -0,0 +1,873 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+
+#[cfg(feature = "ssr")]
+use crate::storage::{DataStore, StorageError};
+
+// --- Webhooks ---
+//
+// A household can have alerts and geofence crossings posted to its own systems, such as
+// a home-automation hub. Each webhook subscribes a URL to some event types. An event is
+// first written to the outbox, one delivery per subscribed webhook, and a background
+// worker posts it from there:
+//
+//   POST <url>
+//   Content-Type: application/json
+//   X-Buddy-Event: <event type>
+//   X-Buddy-Delivery: <delivery id>
+//   X-Buddy-Timestamp: <unix seconds>
+//   X-Buddy-Signature: <hex HMAC-SHA256(webhook secret, "POST <path>\n<timestamp>.<raw body>")>
+//
+// signed the same way devices sign their posts. Any 2xx answer counts as delivered.
+// Anything else is tried again after BUDDY_WEBHOOK_RETRY_SECS, twice as long after each
+// further failure (up to MAX_RETRY_DELAY_SECS). After BUDDY_WEBHOOK_MAX_ATTEMPTS the
+// delivery is dead and waits in the household's dead letters until someone retries it.
+//
+// Webhooks can't point at loopback, private-network or link-local addresses (such as a
+// cloud metadata service) unless BUDDY_WEBHOOK_ALLOW_PRIVATE is on: literal addresses are
+// refused when the webhook is saved, and names are checked again every time they resolve.
+//
+// Due deliveries are posted all at once, and each is put off for as long as an attempt can
+// take before it is sent, so the worker can start its next run while a slow webhook is
+// still being waited on without sending anything twice.
+
+/// Header naming the event type a delivery carries.
+pub const EVENT_HEADER: &str = "X-Buddy-Event";
+/// Header carrying the delivery id, the same on every attempt, so receivers can drop repeats.
+pub const DELIVERY_HEADER: &str = "X-Buddy-Delivery";
+
+/// Longest webhook URL accepted.
+pub const MAX_URL_LEN: usize = 2048;
+/// Longest wait between two attempts at a delivery.
+pub const MAX_RETRY_DELAY_SECS: i64 = 3600;
+/// How often the worker looks for deliveries that are due.
+#[cfg(feature = "ssr")]
+pub const OUTBOX_POLL: std::time::Duration = std::time::Duration::from_secs(1);
+/// Most deliveries posted per run of the worker.
+#[cfg(feature = "ssr")]
+const DELIVERY_BATCH: usize = 20;
+/// Most characters of a failure kept with a delivery.
+#[cfg(feature = "ssr")]
+const MAX_ERROR_LEN: usize = 500;
+
+/// Deliveries listed when the request doesn't say how many.
+pub const DEFAULT_DELIVERY_LIMIT: usize = 50;
+/// Most deliveries listed by one request.
+pub const MAX_DELIVERY_LIMIT: usize = 500;
+
+/// What happened, as named in a delivery's `type` and `X-Buddy-Event`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventType {
+    #[serde(rename = "alert.fired")]
+    AlertFired,
+    #[serde(rename = "alert.resolved")]
+    AlertResolved,
+    #[serde(rename = "geofence.enter")]
+    GeofenceEnter,
+    #[serde(rename = "geofence.exit")]
+    GeofenceExit,
+}
+
+impl EventType {
+    /// Every event type, in the order they are offered on the dashboard.
+    pub const ALL: [EventType; 4] = [
+        EventType::AlertFired,
+        EventType::AlertResolved,
+        EventType::GeofenceEnter,
+        EventType::GeofenceExit,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventType::AlertFired => "alert.fired",
+            EventType::AlertResolved => "alert.resolved",
+            EventType::GeofenceEnter => "geofence.enter",
+            EventType::GeofenceExit => "geofence.exit",
+        }
+    }
+
+    /// Short description for the dashboard.
+    pub fn title(self) -> &'static str {
+        match self {
+            EventType::AlertFired => "An alert fires",
+            EventType::AlertResolved => "An alert resolves",
+            EventType::GeofenceEnter => "A pet enters a geofence",
+            EventType::GeofenceExit => "A pet leaves a geofence",
+        }
+    }
+}
+
+impl std::str::FromStr for EventType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alert.fired" => Ok(EventType::AlertFired),
+            "alert.resolved" => Ok(EventType::AlertResolved),
+            "geofence.enter" => Ok(EventType::GeofenceEnter),
+            "geofence.exit" => Ok(EventType::GeofenceExit),
+            _ => Err(format!(
+                "Unknown event type {:?} (expected alert.fired, alert.resolved, geofence.enter or geofence.exit)",
+                s
+            )),
+        }
+    }
+}
+
+/// A URL a household's events are posted to.
+///
+/// `secret` signs every delivery; it is only returned once, in the response that creates
+/// the webhook.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Webhook {
+    pub webhook_id: i64,
+    pub household_id: i64,
+    pub url: String,
+    /// The event types posted to `url`.
+    pub events: Vec<EventType>,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+/// Body of `POST /api/households/{id}/webhooks`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NewWebhook {
+    pub url: String,
+    pub events: Vec<EventType>,
+}
+
+impl NewWebhook {
+    /// Checks the URL is http(s), names no private address unless `allow_private`, and the
+    /// webhook subscribes to something.
+    pub fn validate(&self, allow_private: bool) -> Result<(), WebhookError> {
+        let url = self.url.trim();
+        let rest = url
+            .strip_prefix("https://")
+            .or_else(|| url.strip_prefix("http://"))
+            .unwrap_or_default();
+        if rest.is_empty()
+            || rest.starts_with('/')
+            || url.len() > MAX_URL_LEN
+            || url.chars().any(char::is_whitespace)
+        {
+            return Err(WebhookError::InvalidUrl(self.url.clone()));
+        }
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        let host_port = authority.rsplit('@').next().unwrap_or_default();
+        let host = match host_port.strip_prefix('[') {
+            Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+            None => host_port.split(':').next().unwrap_or_default(),
+        };
+        // Other spellings of an IPv4 address, like 2130706433 or 0x7f.1, would get past the
+        // check below, so only dotted quads are taken
+        let last_label = host
+            .trim_end_matches('.')
+            .rsplit('.')
+            .next()
+            .unwrap_or_default();
+        let numeric = !host.contains(':')
+            && (last_label.bytes().all(|b| b.is_ascii_digit())
+                || last_label.to_ascii_lowercase().starts_with("0x"));
+        if host.is_empty() || (numeric && host.parse::<std::net::Ipv4Addr>().is_err()) {
+            return Err(WebhookError::InvalidUrl(self.url.clone()));
+        }
+        if !allow_private && !is_public_host(host) {
+            return Err(WebhookError::PrivateAddress(self.url.clone()));
+        }
+        if self.events.is_empty() {
+            return Err(WebhookError::NoEvents);
+        }
+        Ok(())
+    }
+}
+
+/// Whether a URL's host may be posted to without BUDDY_WEBHOOK_ALLOW_PRIVATE. Names other
+/// than `localhost` pass here; what they resolve to is checked when they are posted to.
+pub fn is_public_host(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    if host == "localhost" || host.ends_with(".localhost") {
+        return false;
+    }
+    match host.parse::<IpAddr>() {
+        Ok(ip) => is_public(ip),
+        Err(_) => true,
+    }
+}
+
+/// Whether `ip` is out on the internet, rather than loopback, on a private or shared network,
+/// link-local (which is where cloud metadata services live) or otherwise special.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                // 100.64.0.0/10, carrier-grade NAT
+                || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // fc00::/7, unique local
+                    || first & 0xfe00 == 0xfc00
+                    // fe80::/10, link-local
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Resolves the hosts deliveries are posted to, leaving out addresses that aren't public.
+#[cfg(feature = "ssr")]
+struct PublicResolver;
+
+#[cfg(feature = "ssr")]
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<std::net::SocketAddr> = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", host).into());
+            }
+            let addrs: reqwest::dns::Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Where a delivery is in the outbox.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryState {
+    /// Waiting for its next attempt.
+    Pending,
+    /// The webhook answered with a 2xx.
+    Delivered,
+    /// Out of attempts; only tried again when someone asks.
+    Dead,
+}
+
+impl DeliveryState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryState::Pending => "pending",
+            DeliveryState::Delivered => "delivered",
+            DeliveryState::Dead => "dead",
+        }
+    }
+}
+
+impl std::str::FromStr for DeliveryState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(DeliveryState::Pending),
+            "delivered" => Ok(DeliveryState::Delivered),
+            "dead" => Ok(DeliveryState::Dead),
+            _ => Err(format!(
+                "Unknown delivery state {:?} (expected pending, delivered or dead)",
+                s
+            )),
+        }
+    }
+}
+
+/// One event on its way to one webhook, as listed by `GET /api/households/{id}/deliveries`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Delivery {
+    pub delivery_id: i64,
+    pub webhook_id: i64,
+    pub household_id: i64,
+    /// Where it is posted, as the webhook's URL was when the event happened.
+    pub url: String,
+    pub event: EventType,
+    /// The `data` of the posted body.
+    pub data: serde_json::Value,
+    pub state: DeliveryState,
+    /// Attempts made so far.
+    pub attempts: u32,
+    /// When a pending delivery is tried next.
+    pub next_attempt_at: DateTime<Utc>,
+    /// Why the latest attempt failed, e.g. `HTTP 503 Service Unavailable`.
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+impl Delivery {
+    /// The body posted to the webhook.
+    pub fn body(&self) -> serde_json::Value {
+        serde_json::json!({
+            "delivery_id": self.delivery_id,
+            "type": self.event,
+            "created_at": self.created_at,
+            "data": self.data,
+        })
+    }
+}
+
+/// Query parameters of `GET /api/households/{id}/deliveries`. Every filter is optional.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct DeliveryQuery {
+    /// Only deliveries in this state; `dead` lists the dead letters.
+    pub state: Option<DeliveryState>,
+    /// How many to list, clamped to `MAX_DELIVERY_LIMIT`.
+    pub limit: Option<usize>,
+}
+
+impl DeliveryQuery {
+    /// The effective number of deliveries to list.
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_DELIVERY_LIMIT)
+            .clamp(1, MAX_DELIVERY_LIMIT)
+    }
+}
+
+/// How hard the worker tries.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeliverySettings {
+    /// Attempts made before a delivery is dead.
+    pub max_attempts: u32,
+    /// Wait after the first failed attempt, in seconds; it doubles with every further one.
+    pub retry_secs: i64,
+    /// How long to wait for a webhook to answer, in seconds.
+    pub timeout_secs: u64,
+    /// Whether webhooks may point at loopback, private-network and link-local addresses.
+    pub allow_private: bool,
+}
+
+impl DeliverySettings {
+    /// The client deliveries are posted with. Redirects aren't followed: a webhook answers
+    /// where it was registered, or fails.
+    #[cfg(feature = "ssr")]
+    pub fn client(&self) -> reqwest::Result<reqwest::Client> {
+        let builder = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(self.timeout_secs))
+            .redirect(reqwest::redirect::Policy::none());
+        if self.allow_private {
+            builder.build()
+        } else {
+            builder
+                .dns_resolver(std::sync::Arc::new(PublicResolver))
+                .build()
+        }
+    }
+
+    /// How long a delivery being posted is kept from the next run of the worker: the
+    /// timeout, plus a little to record the outcome in.
+    #[cfg(feature = "ssr")]
+    fn claim(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.timeout_secs.min(MAX_RETRY_DELAY_SECS as u64) as i64 + 5)
+    }
+
+    /// How long to wait after `attempts` failed attempts.
+    pub fn retry_delay(&self, attempts: u32) -> chrono::Duration {
+        let doublings = attempts.saturating_sub(1).min(30);
+        chrono::Duration::seconds(
+            self.retry_secs
+                .saturating_mul(1 << doublings)
+                .min(MAX_RETRY_DELAY_SECS),
+        )
+    }
+}
+
+// --- Outbox ---
+
+/// Queues `subject` (an alert or geofence event of `device_id`) as an `event` for every
+/// webhook of the device's household that subscribes to it. Returns the queued deliveries.
+///
+/// The posted `data` holds the device's id and name next to `subject`, under `key`.
+#[cfg(feature = "ssr")]
+pub fn enqueue<T: Serialize>(
+    store: &dyn DataStore,
+    device_id: &str,
+    event: EventType,
+    key: &str,
+    subject: &T,
+    now: DateTime<Utc>,
+) -> Result<Vec<Delivery>, StorageError> {
+    let Some(device) = store.device(device_id)? else {
+        return Ok(Vec::new());
+    };
+    let Some(household_id) = device.household_id else {
+        return Ok(Vec::new());
+    };
+    let mut data = serde_json::json!({
+        "device": {"id": device.id, "display_name": device.display_name},
+    });
+    data[key] = serde_json::to_value(subject).unwrap_or_default();
+
+    let mut queued = Vec::new();
+    for webhook in store.webhooks(household_id)? {
+        if !webhook.events.contains(&event) {
+            continue;
+        }
+        queued.push(store.create_delivery(&Delivery {
+            delivery_id: 0,
+            webhook_id: webhook.webhook_id,
+            household_id,
+            url: webhook.url,
+            event,
+            data: data.clone(),
+            state: DeliveryState::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            created_at: now,
+            delivered_at: None,
+        })?);
+    }
+    Ok(queued)
+}
+
+/// Posts every delivery due at `now`, all at once, and records how each went.
+/// Returns the deliveries that went dead.
+#[cfg(feature = "ssr")]
+pub async fn deliver_due(
+    store: &dyn DataStore,
+    client: &reqwest::Client,
+    settings: DeliverySettings,
+    now: DateTime<Utc>,
+) -> Result<Vec<Delivery>, StorageError> {
+    let mut claimed = Vec::new();
+    for mut delivery in store.due_deliveries(now, DELIVERY_BATCH)? {
+        // Deleting a webhook takes its deliveries with it, so this is just a race
+        let Some(webhook) = store.webhook(delivery.webhook_id)? else {
+            continue;
+        };
+        delivery.next_attempt_at = now + settings.claim();
+        store.update_delivery(&delivery)?;
+        claimed.push(attempt(store, client, settings, webhook, delivery));
+    }
+
+    let mut dead = Vec::new();
+    for attempted in futures_util::future::join_all(claimed).await {
+        dead.extend(attempted?);
+    }
+    Ok(dead)
+}
+
+/// Posts a claimed delivery and records how it went. Returns it if it went dead.
+#[cfg(feature = "ssr")]
+async fn attempt(
+    store: &dyn DataStore,
+    client: &reqwest::Client,
+    settings: DeliverySettings,
+    webhook: Webhook,
+    mut delivery: Delivery,
+) -> Result<Option<Delivery>, StorageError> {
+    let outcome = post(client, &webhook, &delivery, settings.allow_private).await;
+    let finished_at = Utc::now();
+    delivery.attempts += 1;
+    match outcome {
+        Ok(()) => {
+            delivery.state = DeliveryState::Delivered;
+            delivery.delivered_at = Some(finished_at);
+            delivery.last_error = None;
+        }
+        Err(error) => {
+            delivery.last_error = Some(error.chars().take(MAX_ERROR_LEN).collect());
+            if delivery.attempts >= settings.max_attempts {
+                delivery.state = DeliveryState::Dead;
+            } else {
+                delivery.next_attempt_at = finished_at + settings.retry_delay(delivery.attempts);
+            }
+        }
+    }
+    store.update_delivery(&delivery)?;
+    Ok((delivery.state == DeliveryState::Dead).then_some(delivery))
+}
+
+/// Makes one attempt at posting `delivery` to `webhook`.
+#[cfg(feature = "ssr")]
+async fn post(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    delivery: &Delivery,
+    allow_private: bool,
+) -> Result<(), String> {
+    use crate::device_auth::{SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+    let url = reqwest::Url::parse(&delivery.url).map_err(|e| e.to_string())?;
+    // Addresses written out in the URL never reach the resolver
+    let host = url.host_str().unwrap_or_default();
+    if !allow_private && !is_public_host(host) {
+        return Err(format!("{} is not a public address", host));
+    }
+    let body = serde_json::to_vec(&delivery.body()).map_err(|e| e.to_string())?;
+    let timestamp = Utc::now().timestamp().to_string();
+    let signature = crate::device_auth::sign(
+        webhook.secret.as_deref().unwrap_or_default(),
+        "POST",
+        url.path(),
+        &timestamp,
+        &body,
+    );
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, delivery.event.as_str())
+        .header(DELIVERY_HEADER, delivery.delivery_id.to_string())
+        .header(TIMESTAMP_HEADER, timestamp)
+        .header(SIGNATURE_HEADER, signature)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("HTTP {}", response.status()))
+    }
+}
+
+// --- Errors ---
+
+/// Why a webhook request was refused.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WebhookError {
+    /// The URL isn't an http(s) URL, or is too long.
+    InvalidUrl(String),
+    /// The URL points at a loopback, private-network or link-local address.
+    PrivateAddress(String),
+    /// The webhook doesn't subscribe to any event type.
+    NoEvents,
+    /// No webhook with this id in the household.
+    NotFound(i64),
+    /// No delivery with this id in the household.
+    DeliveryNotFound(i64),
+    /// Only dead deliveries can be retried.
+    NotDead(DeliveryState),
+}
+
+impl WebhookError {
+    /// Stable, machine-readable identifier returned in error responses.
+    pub fn code(&self) -> &'static str {
+        match self {
+            WebhookError::InvalidUrl(_) => "invalid_webhook_url",
+            WebhookError::PrivateAddress(_) => "private_webhook_address",
+            WebhookError::NoEvents => "no_webhook_events",
+            WebhookError::NotFound(_) => "webhook_not_found",
+            WebhookError::DeliveryNotFound(_) => "delivery_not_found",
+            WebhookError::NotDead(_) => "delivery_not_dead",
+        }
+    }
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookError::InvalidUrl(url) => write!(
+                f,
+                "Webhook URL {:?} must be an http:// or https:// URL of at most {} characters",
+                url, MAX_URL_LEN
+            ),
+            WebhookError::PrivateAddress(url) => write!(
+                f,
+                "Webhook URL {:?} points at a private address, which BUDDY_WEBHOOK_ALLOW_PRIVATE has to allow",
+                url
+            ),
+            WebhookError::NoEvents => write!(f, "A webhook needs at least one event type"),
+            WebhookError::NotFound(id) => write!(f, "Webhook {} does not exist", id),
+            WebhookError::DeliveryNotFound(id) => write!(f, "Delivery {} does not exist", id),
+            WebhookError::NotDead(state) => write!(
+                f,
+                "Only dead deliveries can be retried; this one is {}",
+                state.as_str()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WebhookError {}
+
+// --- Tests ---
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::device_auth::{SIGNATURE_HEADER, TIMESTAMP_HEADER};
+    use crate::storage::tests::{at, backends, device, household};
+    use std::collections::BTreeMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::sync::{Arc, Mutex};
+
+    const SETTINGS: DeliverySettings = DeliverySettings {
+        max_attempts: 3,
+        retry_secs: 30,
+        timeout_secs: 5,
+        allow_private: true,
+    };
+
+    /// A request a `Receiver` got: its request line, headers (by lowercase name) and body.
+    type Request = (String, BTreeMap<String, String>, Vec<u8>);
+
+    /// A webhook on a local port that answers with `statuses` in turn, the last one from
+    /// then on, and keeps what it was sent.
+    struct Receiver {
+        url: String,
+        requests: Arc<Mutex<Vec<Request>>>,
+    }
+
+    impl Receiver {
+        fn start(statuses: &[u16]) -> Self {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/hooks/buddy", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let received = requests.clone();
+            let statuses = statuses.to_vec();
+            std::thread::spawn(move || {
+                for (i, stream) in listener.incoming().enumerate() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(&mut stream);
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let mut headers = BTreeMap::new();
+                    loop {
+                        let mut header = String::new();
+                        reader.read_line(&mut header).unwrap();
+                        let Some((name, value)) = header.trim_end().split_once(": ") else {
+                            break;
+                        };
+                        headers.insert(name.to_lowercase(), value.to_string());
+                    }
+                    let len = headers
+                        .get("content-length")
+                        .map_or(0, |len| len.parse().unwrap());
+                    let mut body = vec![0; len];
+                    reader.read_exact(&mut body).unwrap();
+                    received
+                        .lock()
+                        .unwrap()
+                        .push((line.trim_end().to_string(), headers, body));
+                    let status = statuses[i.min(statuses.len() - 1)];
+                    write!(
+                        stream,
+                        "HTTP/1.1 {status} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    )
+                    .unwrap();
+                }
+            });
+            Self { url, requests }
+        }
+
+        fn requests(&self) -> Vec<Request> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    /// A household with device `A` and a webhook to `url` for fired alerts, and one alert
+    /// queued for it. Returns the queued delivery.
+    fn queue_alert(store: &dyn DataStore, url: &str) -> Delivery {
+        let (household_id, _) = household(store, "ann");
+        store.create_device(&device("A", household_id)).unwrap();
+        store
+            .create_webhook(&Webhook {
+                webhook_id: 0,
+                household_id,
+                url: url.to_string(),
+                events: vec![EventType::AlertFired],
+                created_at: at(0),
+                secret: Some("whsec".to_string()),
+            })
+            .unwrap();
+        let alert = serde_json::json!({"kind": "low_battery"});
+        let mut queued =
+            enqueue(store, "A", EventType::AlertFired, "alert", &alert, at(0)).unwrap();
+        assert_eq!(queued.len(), 1);
+        queued.remove(0)
+    }
+
+    #[actix_web::test]
+    async fn deliveries_are_signed_over_the_path_and_body() {
+        for (name, store) in backends() {
+            let receiver = Receiver::start(&[204]);
+            let queued = queue_alert(store.as_ref(), &receiver.url);
+
+            let client = SETTINGS.client().unwrap();
+            let dead = deliver_due(store.as_ref(), &client, SETTINGS, at(0))
+                .await
+                .unwrap();
+            assert!(dead.is_empty(), "{name}");
+
+            let requests = receiver.requests();
+            assert_eq!(requests.len(), 1, "{name}");
+            let (line, headers, body) = &requests[0];
+            assert_eq!(line, "POST /hooks/buddy HTTP/1.1", "{name}");
+            assert_eq!(headers["x-buddy-event"], "alert.fired", "{name}");
+            let timestamp = &headers[&TIMESTAMP_HEADER.to_lowercase()];
+            let expected =
+                crate::device_auth::sign("whsec", "POST", "/hooks/buddy", timestamp, body);
+            assert_eq!(
+                headers[&SIGNATURE_HEADER.to_lowercase()],
+                expected,
+                "{name}"
+            );
+            let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+            assert_eq!(body["data"]["alert"]["kind"], "low_battery", "{name}");
+
+            let delivered = store.delivery(queued.delivery_id).unwrap().unwrap();
+            assert_eq!(delivered.state, DeliveryState::Delivered, "{name}");
+            assert_eq!(delivered.attempts, 1, "{name}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn failed_deliveries_back_off_exponentially_then_go_dead() {
+        for (name, store) in backends() {
+            let receiver = Receiver::start(&[500]);
+            let queued = queue_alert(store.as_ref(), &receiver.url);
+            let client = SETTINGS.client().unwrap();
+
+            let mut due = at(0);
+            for (attempts, delay_secs) in [(1, 30), (2, 60)] {
+                let before = Utc::now();
+                let dead = deliver_due(store.as_ref(), &client, SETTINGS, due)
+                    .await
+                    .unwrap();
+                assert!(dead.is_empty(), "{name}");
+                let delivery = store.delivery(queued.delivery_id).unwrap().unwrap();
+                assert_eq!(delivery.state, DeliveryState::Pending, "{name}");
+                assert_eq!(delivery.attempts, attempts, "{name}");
+                let wait = (delivery.next_attempt_at - before).num_seconds();
+                assert!(
+                    (delay_secs..delay_secs + 5).contains(&wait),
+                    "{name}: {wait}"
+                );
+
+                // Nothing goes out before then
+                let early = delivery.next_attempt_at - chrono::Duration::seconds(1);
+                deliver_due(store.as_ref(), &client, SETTINGS, early)
+                    .await
+                    .unwrap();
+                assert_eq!(receiver.requests().len(), attempts as usize, "{name}");
+                due = delivery.next_attempt_at;
+            }
+
+            let dead = deliver_due(store.as_ref(), &client, SETTINGS, due)
+                .await
+                .unwrap();
+            assert_eq!(dead.len(), 1, "{name}");
+            assert_eq!(dead[0].state, DeliveryState::Dead, "{name}");
+            assert_eq!(dead[0].attempts, 3, "{name}");
+            assert_eq!(
+                dead[0].last_error.as_deref(),
+                Some("HTTP 500 Internal Server Error")
+            );
+            let later = due + chrono::Duration::days(1);
+            deliver_due(store.as_ref(), &client, SETTINGS, later)
+                .await
+                .unwrap();
+            assert_eq!(receiver.requests().len(), 3, "{name}");
+            let dead_letters = DeliveryQuery {
+                state: Some(DeliveryState::Dead),
+                limit: None,
+            };
+            let listed = store
+                .deliveries(dead[0].household_id, &dead_letters)
+                .unwrap();
+            assert_eq!(listed, dead, "{name}");
+        }
+    }
+
+    #[test]
+    fn private_addresses_are_refused_unless_allowed() {
+        let webhook = |url: &str| NewWebhook {
+            url: url.to_string(),
+            events: vec![EventType::AlertFired],
+        };
+        for url in [
+            "http://127.0.0.1:8123/api/webhook/buddy",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.0.0.5/hook",
+            "https://user@192.168.1.10:8443/hook",
+            "http://localhost:8123/",
+            "http://[::1]/hook",
+            "http://[fe80::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+        ] {
+            assert_eq!(
+                webhook(url).validate(false),
+                Err(WebhookError::PrivateAddress(url.to_string()))
+            );
+            assert_eq!(webhook(url).validate(true), Ok(()), "{url}");
+        }
+        for url in [
+            "https://hooks.example.com/buddy",
+            "http://8.8.8.8:8080/hook?x=1",
+        ] {
+            assert_eq!(webhook(url).validate(false), Ok(()), "{url}");
+        }
+        for url in [
+            "http://2130706433/",
+            "http://0x7f.1/",
+            "http://127.000.0.1/",
+        ] {
+            assert_eq!(
+                webhook(url).validate(true),
+                Err(WebhookError::InvalidUrl(url.to_string()))
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn private_addresses_are_not_posted_to_unless_allowed() {
+        for (name, store) in backends() {
+            let receiver = Receiver::start(&[204]);
+            let queued = queue_alert(store.as_ref(), &receiver.url);
+            let settings = DeliverySettings {
+                allow_private: false,
+                ..SETTINGS
+            };
+
+            let client = settings.client().unwrap();
+            deliver_due(store.as_ref(), &client, settings, at(0))
+                .await
+                .unwrap();
+            assert!(receiver.requests().is_empty(), "{name}");
+            let delivery = store.delivery(queued.delivery_id).unwrap().unwrap();
+            assert_eq!(delivery.state, DeliveryState::Pending, "{name}");
+            assert_eq!(
+                delivery.last_error.as_deref(),
+                Some("127.0.0.1 is not a public address"),
+                "{name}"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_delivery_being_posted_is_not_posted_again_by_an_overlapping_run() {
+        for (name, store) in backends() {
+            let receiver = Receiver::start(&[204]);
+            queue_alert(store.as_ref(), &receiver.url);
+            let client = SETTINGS.client().unwrap();
+
+            let first = deliver_due(store.as_ref(), &client, SETTINGS, at(0));
+            let second = deliver_due(store.as_ref(), &client, SETTINGS, at(0));
+            let (first, second) = futures_util::future::join(first, second).await;
+            first.unwrap();
+            second.unwrap();
+            assert_eq!(receiver.requests().len(), 1, "{name}");
+        }
+    }
+}